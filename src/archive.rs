@@ -0,0 +1,111 @@
+use std::io::Read;
+
+use anyhow::{Context, Result, bail};
+use flate2::read::GzDecoder;
+use ruzstd::decoding::StreamingDecoder;
+
+/// Read the file at `member` out of a tarball (gzip, zstd or uncompressed),
+/// decompressing only as far as that file. A `*` component in `member`
+/// matches any one name, for the versioned top-level directory most release
+/// tarballs have (`*/PKGBUILD`). Files over `max_size` bytes are rejected.
+pub fn extract(bytes: &[u8], member: &str, max_size: u64) -> Result<Vec<u8>> {
+    let stream: Box<dyn Read + '_> = match bytes {
+        [0x1f, 0x8b, ..] => Box::new(GzDecoder::new(bytes)),
+        [0x28, 0xb5, 0x2f, 0xfd, ..] => Box::new(
+            StreamingDecoder::new(bytes).context("not a valid zstd stream")?,
+        ),
+        // ustar magic at offset 257
+        _ if bytes.get(257..262) == Some(b"ustar") => Box::new(bytes),
+        _ => bail!("not a tarball (expected tar, tar.gz or tar.zst)"),
+    };
+
+    let mut archive = tar::Archive::new(stream);
+    for entry in archive.entries().context("Failed to read tarball")? {
+        let entry = entry.context("Failed to read tarball")?;
+        let path = entry.path().context("Failed to read tarball")?;
+        if !matches_member(&path.to_string_lossy(), member) {
+            continue;
+        }
+        if !entry.header().entry_type().is_file() {
+            bail!("{member} in the tarball is not a regular file");
+        }
+        if entry.size() > max_size {
+            bail!("{member} in the tarball is too large (over {max_size} bytes)");
+        }
+        let mut content = Vec::new();
+        entry
+            .take(max_size)
+            .read_to_end(&mut content)
+            .context("Failed to read tarball")?;
+        return Ok(content);
+    }
+    bail!("no {member} in the tarball")
+}
+
+/// Whether an archive path names `member`, ignoring a leading `./`
+fn matches_member(path: &str, member: &str) -> bool {
+    let path = path.strip_prefix("./").unwrap_or(path);
+    let member = member.strip_prefix("./").unwrap_or(member);
+    let (mut names, mut patterns) = (path.split('/'), member.split('/'));
+    loop {
+        match (names.next(), patterns.next()) {
+            (None, None) => return true,
+            (Some(name), Some(pattern)) if pattern == "*" || pattern == name => {}
+            _ => return false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use ruzstd::encoding::{CompressionLevel, compress_to_vec};
+
+    use super::*;
+
+    fn tarball() -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, content) in [
+            ("foo-1.2/README", "hello\n"),
+            ("foo-1.2/packaging/PKGBUILD", "pkgver=1.2\npkgrel=1\n"),
+        ] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, path, content.as_bytes()).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    #[test]
+    fn test_extract() {
+        let tar = tarball();
+        let mut gz = GzEncoder::new(Vec::new(), Compression::default());
+        gz.write_all(&tar).unwrap();
+        let gz = gz.finish().unwrap();
+        let zst = compress_to_vec(&tar[..], CompressionLevel::Fastest);
+
+        for bytes in [&tar, &gz, &zst] {
+            let pkgbuild = extract(bytes, "foo-1.2/packaging/PKGBUILD", 1024).unwrap();
+            assert_eq!(pkgbuild, b"pkgver=1.2\npkgrel=1\n");
+            assert_eq!(extract(bytes, "*/packaging/PKGBUILD", 1024).unwrap(), pkgbuild);
+        }
+        let missing = extract(&gz, "*/PKGBUILD", 1024).unwrap_err();
+        assert!(missing.to_string().contains("no */PKGBUILD"));
+        let large = extract(&gz, "*/packaging/PKGBUILD", 8).unwrap_err();
+        assert!(large.to_string().contains("too large"));
+        assert!(extract(b"pkgver=1\n", "PKGBUILD", 1024).is_err());
+    }
+
+    #[test]
+    fn test_matches_member() {
+        assert!(matches_member("./foo-1.2/PKGBUILD", "*/PKGBUILD"));
+        assert!(matches_member("PKGBUILD", "./PKGBUILD"));
+        assert!(!matches_member("foo/bar/PKGBUILD", "*/PKGBUILD"));
+        assert!(!matches_member("foo", "foo/PKGBUILD"));
+    }
+}
@@ -0,0 +1,106 @@
+use std::path::Path;
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+use anyhow::{Result, bail};
+use colored::Colorize;
+
+use crate::cli::Options;
+use crate::fetch::Fetcher;
+
+/// Run `rchan bench <url>...`: fetch each URL `opts.count` times, one request
+/// at a time, and report latency and success rate. Bodies are not parsed.
+pub fn run_bench(_base: &Path, opts: &Options) -> Result<ExitCode> {
+    if opts.args.is_empty() {
+        bail!("bench needs at least one URL");
+    }
+    if !opts.no_banner {
+        println!(
+            "{} {}",
+            "rchan bench".bold().cyan(),
+            "- remote fetch latency".dimmed()
+        );
+        println!(
+            "{} {} requests per URL, timeout {}s, {} retries\n",
+            "Settings:".bold(),
+            opts.count,
+            opts.timeout.unwrap_or(crate::fetch::DEFAULT_TIMEOUT).as_secs_f64(),
+            opts.retries.unwrap_or(0)
+        );
+    }
+
+    let fetcher = Fetcher::new(opts)?;
+    let mut any_failed = false;
+    for url in &opts.args {
+        let mut latencies = Vec::new();
+        let mut last_error = None;
+        for _ in 0..opts.count {
+            let started = Instant::now();
+            match fetcher.fetch(url, fetcher.policy(), &mut Vec::new()) {
+                Ok(_) => latencies.push(started.elapsed()),
+                Err(e) => last_error = Some(e),
+            }
+        }
+
+        let ok = format!("{}/{} ok", latencies.len(), opts.count);
+        match latency_stats(&mut latencies) {
+            Some((min, median, max)) => println!(
+                "{} {}  min {}  median {}  max {}",
+                url.white().bold(),
+                if latencies.len() == opts.count { ok.green() } else { ok.yellow() },
+                format_latency(min),
+                format_latency(median),
+                format_latency(max)
+            ),
+            None => {
+                any_failed = true;
+                println!("{} {}", url.white().bold(), ok.red());
+            }
+        }
+        if let Some(e) = last_error {
+            println!("  {} {:#}", "last error:".dimmed(), e);
+        }
+    }
+
+    Ok(if any_failed {
+        ExitCode::from(crate::EXIT_ERRORS)
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+/// Minimum, median and maximum of the successful request latencies
+fn latency_stats(latencies: &mut [Duration]) -> Option<(Duration, Duration, Duration)> {
+    if latencies.is_empty() {
+        return None;
+    }
+    latencies.sort();
+    let mid = latencies.len() / 2;
+    let median = if latencies.len().is_multiple_of(2) {
+        (latencies[mid - 1] + latencies[mid]) / 2
+    } else {
+        latencies[mid]
+    };
+    Some((latencies[0], median, latencies[latencies.len() - 1]))
+}
+
+/// Latency in milliseconds with one decimal, e.g. `84.2ms`
+fn format_latency(d: Duration) -> String {
+    format!("{:.1}ms", d.as_secs_f64() * 1000.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_latency_stats() {
+        let ms = Duration::from_millis;
+        assert_eq!(latency_stats(&mut []), None);
+        assert_eq!(latency_stats(&mut [ms(30), ms(10), ms(20)]), Some((ms(10), ms(20), ms(30))));
+        assert_eq!(
+            latency_stats(&mut [ms(40), ms(10), ms(20), ms(30)]),
+            Some((ms(10), ms(25), ms(40)))
+        );
+    }
+}
@@ -1,142 +1,1304 @@
-use std::path::Path;
-use std::process::Command;
+use std::collections::{HashSet, VecDeque};
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Condvar, Mutex, mpsc};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, bail};
 use colored::Colorize;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::cli::{Options, OutputFormat};
+use crate::pkgbuild;
+use crate::scanner::serialize_millis;
+use crate::tui::LiveView;
 
 /// Run the build process: iterate over all subdirectories containing a PKGBUILD and build each one
-pub fn run_build(base: &Path) -> Result<()> {
+///
+/// Packages build one at a time unless `--jobs` was given; each build gets
+/// its own directory under build/. A package that depends on another of the
+/// batch builds after it, and not at all if that one failed. With `--json`,
+/// makepkg's output goes to logs/<name>.log and one JSON document is printed
+/// at the end instead. With `--tui` on a terminal, the logs are shown in a
+/// live view of the batch, and the results printed once it closes.
+pub fn run_build(base: &Path, opts: &Options) -> Result<()> {
     let pkgs_dir = base.join("pkgs");
     let build_dir = base.join("build");
+    let json = match opts.format {
+        OutputFormat::Text => false,
+        OutputFormat::Json => true,
+        OutputFormat::JsonLines => bail!("build supports --json, not --json-lines"),
+        OutputFormat::Markdown => bail!("build supports --json, not --format markdown"),
+    };
 
     // Create pkgs and build directories
     std::fs::create_dir_all(&pkgs_dir)
         .context("Failed to create pkgs directory")?;
     std::fs::create_dir_all(&build_dir)
         .context("Failed to create build directory")?;
+    remove_stale_staging(&pkgs_dir)?;
+
+    if !opts.no_banner {
+        println!(
+            "{} {}",
+            "rchan build".bold().cyan(),
+            "- PKGBUILD batch builder".dimmed()
+        );
+        println!("{} {}\n", "Working directory:".bold(), base.display());
+    }
+
+    let mut entries = pkgbuild_dirs(base, opts.include_hidden)?;
+
+    if entries.is_empty() && !json {
+        println!(
+            "{}",
+            "No subdirectories with PKGBUILD found.".yellow()
+        );
+        return Ok(());
+    }
+
+    if let Some(git_ref) = &opts.changed_since {
+        let changed = changed_dirs(base, git_ref)?;
+        let all = entries.len();
+        entries.retain(|e| changed.contains(&*e.file_name().to_string_lossy()));
+        if !json {
+            println!(
+                "{} of {} packages changed since {}\n",
+                entries.len(),
+                all,
+                git_ref.bold()
+            );
+            if entries.is_empty() {
+                return Ok(());
+            }
+        }
+    }
+
+    let makepkg = find_executable(opts.makepkg_bin.as_deref().unwrap_or(Path::new("makepkg")))?;
+    if opts.verify_build_deps {
+        verify_build_deps(&entries, json)?;
+    }
+    if let Some(escalation) = opts.sudo {
+        prepare_escalation(escalation, &build_dir)?;
+    }
+
+    let names: Vec<String> =
+        entries.iter().map(|e| e.file_name().to_string_lossy().into_owned()).collect();
+    let deps = local_dependencies(&entries);
+    let order = build_order(&names, &deps)?;
+    if !json && deps.iter().any(|d| !d.is_empty()) {
+        let order: Vec<&str> = order.iter().map(|&i| names[i].as_str()).collect();
+        println!("{} {}\n", "Build order:".bold(), order.join(", "));
+    }
+
+    let jobs = build_jobs(opts);
+    let mut view = match opts.tui && std::io::stdout().is_terminal() {
+        true => Some(LiveView::start(&names).context("Failed to start the live view")?),
+        false => None,
+    };
+    // makepkg's output must not garble the JSON document or the live view
+    let logged = json || view.is_some();
+    let mut deferred = String::new();
+    let total = entries.len();
+    let mut success_count = 0;
+    let mut fail_count = 0;
+    let mut records = Vec::new();
+    let batch_start = Instant::now();
+
+    let schedule = Schedule::new(order, &deps);
+    let (tx, rx) = mpsc::channel();
+    let mut first_error = None;
+    std::thread::scope(|s| {
+        for _ in 0..jobs {
+            let tx = tx.clone();
+            let (schedule, entries, names) = (&schedule, &entries, &names);
+            let (pkgs_dir, build_dir, makepkg) = (&pkgs_dir, &build_dir, &makepkg);
+            s.spawn(move || {
+                while let Some((i, index, failed_dep)) = schedule.next() {
+                    let pkg_src = entries[index].path();
+                    let name = names[index].clone();
+                    if let Some(dep) = failed_dep {
+                        let report = format!(
+                            "  {} {}: not built, dependency {} did not build\n\n",
+                            "ERROR".red().bold(),
+                            name,
+                            names[dep]
+                        );
+                        let record = BuildRecord::new(name, Duration::ZERO, BuildStatus::Error);
+                        schedule.finish(index, false);
+                        if tx.send(BuildEvent::Finished(index, Ok((record, report)))).is_err() {
+                            break;
+                        }
+                        continue;
+                    }
+                    if tx.send(BuildEvent::Started(i, index)).is_err() {
+                        break;
+                    }
+                    let build_dir = build_dir.join(&name);
+                    let outcome = build_package(
+                        makepkg, &pkg_src, &build_dir, pkgs_dir, name, logged, opts,
+                    );
+                    let built = matches!(&outcome, Ok((r, _)) if r.status == BuildStatus::Ok);
+                    schedule.finish(index, built);
+                    if tx.send(BuildEvent::Finished(index, outcome)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(tx);
+
+        loop {
+            let event = match &mut view {
+                Some(live) => match rx.recv_timeout(Duration::from_millis(250)) {
+                    Ok(event) => event,
+                    Err(mpsc::RecvTimeoutError::Timeout) => {
+                        if !live.tick(Duration::ZERO) {
+                            // Closed by the user: go on with plain output
+                            view = None;
+                            print!("{deferred}");
+                            deferred.clear();
+                        }
+                        continue;
+                    }
+                    Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                },
+                None => match rx.recv() {
+                    Ok(event) => event,
+                    Err(_) => break,
+                },
+            };
+            let outcome = match event {
+                BuildEvent::Started(i, index) => {
+                    match &mut view {
+                        Some(live) => live.building(index, log_path(&pkgs_dir, &names[index])),
+                        None if !json => println!(
+                            "[{}/{}] {} {}",
+                            i + 1,
+                            total,
+                            "Building".bold().blue(),
+                            names[index].white().bold()
+                        ),
+                        None => {}
+                    }
+                    continue;
+                }
+                BuildEvent::Finished(index, outcome) => {
+                    if let (Some(live), Ok((record, _))) = (&mut view, &outcome) {
+                        let ok = record.status == BuildStatus::Ok;
+                        live.finished(index, record.status.text(), ok, record.duration);
+                    }
+                    outcome
+                }
+            };
+            match outcome {
+                Ok((record, report)) => {
+                    if view.is_some() {
+                        deferred.push_str(&report);
+                    } else if !json {
+                        print!("{report}");
+                    }
+                    if record.status == BuildStatus::Ok {
+                        success_count += 1;
+                    } else {
+                        fail_count += 1;
+                    }
+                    records.push(record);
+                }
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+    });
+    // Restore the terminal before printing what the live view held back
+    drop(view);
+    print!("{deferred}");
+    if let Some(e) = first_error {
+        return Err(e);
+    }
+
+    // Final cleanup of the build directory
+    clean_dir(&build_dir)?;
+
+    // Packages whose signature failed stay out of the repository
+    if let Some(db) = &opts.repo_db {
+        let mut artifacts: Vec<&Path> = records
+            .iter()
+            .filter(|r| r.status != BuildStatus::SignFailed)
+            .flat_map(|r| r.artifacts.iter().map(PathBuf::as_path))
+            .collect();
+        artifacts.sort();
+        if !artifacts.is_empty() {
+            let db = base.join(db);
+            update_repo_db(&db, &artifacts, opts)?;
+            if !json {
+                let signed = if opts.repo_sign_db { " and signed it" } else { "" };
+                println!(
+                    "{} added {} packages to {}{signed}\n",
+                    "REPO".cyan().bold(),
+                    artifacts.len(),
+                    db.display()
+                );
+            }
+        }
+    }
+
+    if json {
+        // Report in name order, like the builds were queued
+        records.sort_by(|a, b| a.name.cmp(&b.name));
+        let report = BuildReport {
+            summary: BuildSummary {
+                packages: total,
+                succeeded: success_count,
+                failed: fail_count,
+                duration: batch_start.elapsed(),
+            },
+            packages: records,
+        };
+        println!("{}", crate::report::to_json(&report, opts.pretty_json()));
+        return Ok(());
+    }
+
+    print_build_table(&mut records, opts.reverse);
 
     println!(
-        "{} {}",
-        "rchan build".bold().cyan(),
-        "- PKGBUILD batch builder".dimmed()
+        "{}: {} packages, {} succeeded, {} failed in {}",
+        "Summary".bold(),
+        total,
+        success_count.to_string().green(),
+        fail_count.to_string().red(),
+        format_duration(batch_start.elapsed())
     );
-    println!("{} {}\n", "Working directory:".bold(), base.display());
 
+    Ok(())
+}
+
+/// Subdirectories of `base` holding a PKGBUILD, sorted by name; `pkgs` and
+/// `build` are rchan's own. Hidden directories are skipped unless
+/// `include_hidden`.
+pub fn pkgbuild_dirs(base: &Path, include_hidden: bool) -> Result<Vec<std::fs::DirEntry>> {
     let mut entries: Vec<_> = std::fs::read_dir(base)?
         .filter_map(|e| e.ok())
         .filter(|e| {
             let path = e.path();
+            let hidden = e.file_name().to_string_lossy().starts_with('.');
             path.is_dir()
                 && path.join("PKGBUILD").exists()
-                && path.file_name().map_or(true, |n| n != "pkgs" && n != "build")
+                && path.file_name().is_none_or(|n| n != "pkgs" && n != "build")
+                && (!hidden || include_hidden)
         })
         .collect();
-
     entries.sort_by_key(|e| e.file_name());
+    Ok(entries)
+}
 
-    if entries.is_empty() {
-        println!(
-            "{}",
-            "No subdirectories with PKGBUILD found.".yellow()
-        );
-        return Ok(());
+/// `--repo-db`: add packages to a pacman repository database with
+/// `repo-add`, which writes the files database next to it. With
+/// `--repo-sign-db` both are signed with the `--sign` key (else `$GPGKEY`)
+/// and their signatures must exist afterwards.
+fn update_repo_db(db: &Path, artifacts: &[&Path], opts: &Options) -> Result<()> {
+    let mut repo_add = Command::new("repo-add");
+    if opts.repo_sign_db {
+        repo_add.arg("--sign");
+        if let Some(key) = &opts.sign_key {
+            repo_add.arg("--key").arg(key);
+        }
     }
+    let output = repo_add
+        .arg(db)
+        .args(artifacts)
+        .output()
+        .context("Failed to execute repo-add")?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        match stderr.lines().rfind(|l| !l.trim().is_empty()) {
+            Some(detail) => bail!("repo-add failed: {}", detail.trim()),
+            None => bail!("repo-add exited with {}", output.status),
+        }
+    }
+    if opts.repo_sign_db {
+        let missing: Vec<String> = db_signatures(db)
+            .iter()
+            .filter(|sig| !sig.is_file())
+            .map(|sig| sig.display().to_string())
+            .collect();
+        if !missing.is_empty() {
+            bail!("repo-add --sign did not write {}", missing.join(", "));
+        }
+    }
+    Ok(())
+}
 
-    let total = entries.len();
-    let mut success_count = 0;
-    let mut fail_count = 0;
-
-    for (i, entry) in entries.iter().enumerate() {
-        let pkg_src = entry.path();
-        let name = pkg_src
-            .file_name()
-            .unwrap_or_default()
-            .to_string_lossy()
-            .to_string();
+/// Signatures `repo-add --sign` writes for `db`: its own, and the files
+/// database's (`custom.db.tar.zst` has `custom.files.tar.zst`)
+fn db_signatures(db: &Path) -> Vec<PathBuf> {
+    let signature = |path: &Path| PathBuf::from(format!("{}.sig", path.display()));
+    let mut signatures = vec![signature(db)];
+    let fname = db.file_name().unwrap_or_default().to_string_lossy();
+    if let Some(at) = fname.rfind(".db.") {
+        let files = format!("{}.files.{}", &fname[..at], &fname[at + 4..]);
+        signatures.push(signature(&db.with_file_name(files)));
+    }
+    signatures
+}
 
-        println!(
-            "[{}/{}] {} {}",
-            i + 1,
-            total,
-            "Building".bold().blue(),
-            name.white().bold()
+/// Number of concurrent builds. With `--jobs > 1`, fall back to serial
+/// builds when makepkg is configured with a directory all builds share,
+/// unless `--parallel-builds-safe` says that is fine. The warnings go to
+/// stderr, which keeps stdout for the `--json` document.
+fn build_jobs(opts: &Options) -> usize {
+    if opts.build_jobs <= 1 {
+        return 1;
+    }
+    let shared = shared_makepkg_dirs();
+    for (var, value) in &shared {
+        eprintln!(
+            "{} makepkg {var}={value} is shared by all builds; parallel builds may collide",
+            "WARN".yellow().bold()
         );
+    }
+    if shared.is_empty() || opts.parallel_builds_safe {
+        return opts.build_jobs;
+    }
+    eprintln!(
+        "{} building serially; pass --parallel-builds-safe to build in parallel anyway\n",
+        "WARN".yellow().bold()
+    );
+    1
+}
 
-        // Clean build directory
-        clean_dir(&build_dir)?;
+/// `SRCDEST`/`PKGDEST` as makepkg will see them: the environment overrides
+/// the user config, which overrides the system config
+fn shared_makepkg_dirs() -> Vec<(&'static str, String)> {
+    let mut files = vec![PathBuf::from("/etc/makepkg.conf")];
+    if let Ok(dropins) = std::fs::read_dir("/etc/makepkg.conf.d") {
+        let mut dropins: Vec<_> = dropins
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.extension().is_some_and(|ext| ext == "conf"))
+            .collect();
+        dropins.sort();
+        files.extend(dropins);
+    }
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")));
+    if let Some(config_home) = config_home {
+        files.push(config_home.join("pacman/makepkg.conf"));
+    }
+    if let Some(home) = std::env::var_os("HOME") {
+        files.push(Path::new(&home).join(".makepkg.conf"));
+    }
 
-        // Copy all contents from source directory to build directory
-        if let Err(e) = copy_dir_contents(&pkg_src, &build_dir) {
-            println!(
-                "  {} Failed to copy files: {}\n",
-                "ERROR".red().bold(),
-                e
-            );
-            fail_count += 1;
+    let mut dirs: Vec<(&'static str, String)> = Vec::new();
+    let mut set = |var: &'static str, value: String| {
+        dirs.retain(|(v, _)| *v != var);
+        if !value.is_empty() {
+            dirs.push((var, value));
+        }
+    };
+    for file in files {
+        if let Ok(content) = std::fs::read_to_string(file) {
+            for (var, value) in parse_makepkg_conf(&content) {
+                set(var, value);
+            }
+        }
+    }
+    for var in SHARED_DIR_VARS {
+        if let Ok(value) = std::env::var(var) {
+            set(var, value);
+        }
+    }
+    dirs
+}
+
+/// makepkg settings that point every build at the same directory
+const SHARED_DIR_VARS: [&str; 2] = ["SRCDEST", "PKGDEST"];
+
+/// Top-level `SRCDEST=`/`PKGDEST=` assignments in a makepkg.conf, in order
+fn parse_makepkg_conf(content: &str) -> Vec<(&'static str, String)> {
+    let mut found = Vec::new();
+    for line in content.lines() {
+        let Some((var, value)) = line.trim().split_once('=') else {
             continue;
+        };
+        let Some(var) = SHARED_DIR_VARS.into_iter().find(|v| *v == var) else {
+            continue;
+        };
+        let value = value.split(" #").next().unwrap_or_default().trim();
+        let value = value.trim_matches(|c| c == '"' || c == '\'');
+        found.push((var, value.to_string()));
+    }
+    found
+}
+
+/// Build one package in its own `build_dir` and move the artifacts to
+/// `pkgs_dir`, signing them with `--sign`. Returns the record and the lines
+/// to print for it.
+fn build_package(
+    makepkg_bin: &Path,
+    pkg_src: &Path,
+    build_dir: &Path,
+    pkgs_dir: &Path,
+    name: String,
+    logged: bool,
+    opts: &Options,
+) -> Result<(BuildRecord, String)> {
+    let mut report = String::new();
+
+    // Start from an empty build directory
+    std::fs::create_dir_all(build_dir)
+        .with_context(|| format!("Failed to create {}", build_dir.display()))?;
+    clean_dir(build_dir)?;
+
+    // Copy all contents from source directory to build directory
+    if let Err(e) = copy_dir_contents(pkg_src, build_dir) {
+        report.push_str(&format!(
+            "  {} {}: failed to copy files: {}\n\n",
+            "ERROR".red().bold(),
+            name,
+            e
+        ));
+        return Ok((BuildRecord::new(name, Duration::ZERO, BuildStatus::Error), report));
+    }
+
+    if opts.import_keys {
+        let content = std::fs::read_to_string(build_dir.join("PKGBUILD")).unwrap_or_default();
+        for key in pkgbuild::parse_validpgpkeys(&content) {
+            match import_key(&key) {
+                Ok(false) => {}
+                Ok(true) => {
+                    report.push_str(&format!("  {} imported {}\n", "KEY".cyan().bold(), key))
+                }
+                // makepkg will report the missing key if it matters
+                Err(e) => report.push_str(&format!(
+                    "  {} {}: {:#}\n",
+                    "WARN".yellow().bold(),
+                    name,
+                    e
+                )),
+            }
         }
+    }
 
-        // Run makepkg in the build directory
-        let status = Command::new("makepkg")
-            .arg("-s")
-            .arg("--noconfirm")
-            .current_dir(&build_dir)
-            .status()
-            .context("Failed to execute makepkg")?;
+    // Run makepkg in the build directory. JSON on stdout or the live view
+    // must not be interleaved with its output, so it goes to a log file then.
+    let mut makepkg = Command::new(makepkg_bin);
+    match opts.sudo {
+        // Nothing may escalate: missing dependencies fail the build instead
+        Some(Escalation::None) => {}
+        Some(_) => {
+            makepkg.arg("-s").arg("--config").arg(build_dir.with_file_name(AUTH_CONF));
+        }
+        None => {
+            makepkg.arg("-s");
+        }
+    }
+    makepkg.args(makepkg_args(opts)).arg("--noconfirm").current_dir(build_dir);
+    let log = match logged {
+        true => {
+            let (path, file) = open_log(pkgs_dir, &name)?;
+            makepkg.stdout(file.try_clone()?).stderr(file);
+            Some(path)
+        }
+        false => None,
+    };
+    let record = |duration, status, artifacts: &[PathBuf]| BuildRecord {
+        name: name.clone(),
+        duration,
+        status,
+        artifacts: artifacts.to_vec(),
+        log: log.clone(),
+    };
+    let started = Instant::now();
+    let status = makepkg.status().context("Failed to execute makepkg")?;
+    let elapsed = started.elapsed();
 
-        if !status.success() {
-            println!(
-                "  {} makepkg exited with {}\n",
-                "FAIL".red().bold(),
-                status
-            );
-            fail_count += 1;
+    if !status.success() {
+        report.push_str(&format!(
+            "  {} {}: makepkg exited with {} after {}\n\n",
+            "FAIL".red().bold(),
+            name,
+            status,
+            format_duration(elapsed)
+        ));
+        return Ok((record(elapsed, BuildStatus::Failed, &[]), report));
+    }
+
+    // Ask makepkg which files it produced (split packages, arch and PKGEXT
+    // included) instead of guessing from the directory. Only now: a pkgver()
+    // function has bumped the version in the PKGBUILD during the build.
+    let expected = match package_list(makepkg_bin, build_dir) {
+        Ok(expected) => expected,
+        Err(e) => {
+            report.push_str(&format!("  {} {}: {:#}\n\n", "ERROR".red().bold(), name, e));
+            return Ok((record(elapsed, BuildStatus::Error, &[]), report));
+        }
+    };
+
+    // Gather exactly the expected packages in a staging directory, sign
+    // them there, then move them to the pkgs directory together
+    let staging = pkgs_dir.join(staging_name(std::process::id(), &name));
+    let mut missing = Vec::new();
+    let mut staged = Vec::new();
+    for path in &expected {
+        let Some(fname) = path.file_name() else {
+            continue;
+        };
+        if !path.is_file() {
+            missing.push(fname.to_string_lossy().into_owned());
             continue;
         }
+        staged.push(stage_artifact(path, &staging)?);
+    }
+    std::fs::remove_dir_all(build_dir)
+        .with_context(|| format!("Failed to remove {}", build_dir.display()))?;
 
-        // Move generated .pkg.tar.zst files to the pkgs directory
-        let mut pkg_found = false;
-        for file in std::fs::read_dir(&build_dir)? {
-            let file = file?;
-            let fname = file.file_name();
-            let fname_str = fname.to_string_lossy();
-            if fname_str.ends_with(".pkg.tar.zst") {
-                let dest = pkgs_dir.join(&fname);
-                std::fs::rename(file.path(), &dest).with_context(|| {
-                    format!("Failed to move {} to pkgs/", fname_str)
-                })?;
-                println!(
-                    "  {} {}",
-                    "->".green(),
-                    fname_str.green()
-                );
-                pkg_found = true;
+    if opts.metadata {
+        for artifact in &staged {
+            write_metadata(artifact, &name)?;
+        }
+    }
+    let sign_error = match opts.sign {
+        true => staged
+            .iter()
+            .find_map(|artifact| sign_artifact(artifact, opts.sign_key.as_deref()).err()),
+        false => None,
+    };
+    let moved = publish_artifacts(&staged, pkgs_dir)?;
+    for artifact in &moved {
+        let fname = artifact.file_name().unwrap_or_default().to_string_lossy();
+        report.push_str(&format!("  {} {}\n", "->".green(), fname.green()));
+    }
+    if let Some(e) = sign_error {
+        report.push_str(&format!(
+            "  {} {}: {:#}\n\n",
+            "SIGN".red().bold(),
+            name,
+            e
+        ));
+        return Ok((record(elapsed, BuildStatus::SignFailed, &moved), report));
+    }
+
+    if missing.is_empty() {
+        report.push_str(&format!(
+            "  {} {} in {}\n\n",
+            "OK".green().bold(),
+            name,
+            format_duration(elapsed)
+        ));
+        Ok((record(elapsed, BuildStatus::Ok, &moved), report))
+    } else {
+        report.push_str(&format!(
+            "  {} {}: expected packages not found after build: {}\n\n",
+            "WARN".yellow().bold(),
+            name,
+            missing.join(", ")
+        ));
+        Ok((record(elapsed, BuildStatus::NoArtifact, &moved), report))
+    }
+}
+
+/// How makepkg escalates privileges to install dependencies (`--sudo`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Escalation {
+    Sudo,
+    Doas,
+    /// Don't escalate; makepkg runs without `-s`
+    None,
+}
+
+impl std::str::FromStr for Escalation {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "sudo" => Ok(Escalation::Sudo),
+            "doas" => Ok(Escalation::Doas),
+            "none" => Ok(Escalation::None),
+            other => bail!("unknown escalation '{other}' (expected sudo, doas or none)"),
+        }
+    }
+}
+
+/// makepkg config in build/ that selects the `--sudo` tool
+const AUTH_CONF: &str = ".makepkg-auth.conf";
+
+/// Check that `escalation` works without a password, so `makepkg -s` fails
+/// instead of waiting for one, and write the makepkg config selecting it
+fn prepare_escalation(escalation: Escalation, build_dir: &Path) -> Result<()> {
+    let tool = match escalation {
+        Escalation::Sudo => "sudo",
+        Escalation::Doas => "doas",
+        Escalation::None => return Ok(()),
+    };
+    // What makepkg -s will run, minus the package arguments
+    let output = Command::new(tool)
+        .args(["-n", "pacman", "--version"])
+        .stdin(Stdio::null())
+        .output();
+    let output = match output {
+        Ok(output) => output,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            bail!("--sudo {tool}: {tool} is not installed")
+        }
+        Err(e) => return Err(e).context(format!("Failed to execute {tool}")),
+    };
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!(
+            "--sudo {tool}: '{tool} -n pacman' failed ({}), so installing dependencies would \
+             wait for a password. Allow pacman without a password (e.g. a NOPASSWD rule), or \
+             install dependencies beforehand and pass --sudo none",
+            stderr.trim()
+        );
+    }
+
+    // makepkg reads the authentication command from its config, not the
+    // environment: load the usual config, then override PACMAN_AUTH. -n makes
+    // it fail rather than prompt if the rule stops matching mid-batch.
+    let system = std::env::var("MAKEPKG_CONF").unwrap_or("/etc/makepkg.conf".to_string());
+    let system = format!("'{}'", system.replace('\'', r"'\''"));
+    let conf = format!(
+        "# Written by rchan build --sudo {tool}\n\
+         source {system}\n\
+         for conf in {system}.d/*.conf; do [[ -f $conf ]] && source \"$conf\"; done\n\
+         PACMAN_AUTH=({tool} -n)\n"
+    );
+    let path = build_dir.join(AUTH_CONF);
+    std::fs::write(&path, conf).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Final state of one package in a batch build
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum BuildStatus {
+    /// Built and artifacts moved to pkgs/
+    Ok,
+    /// makepkg exited with an error
+    Failed,
+    /// makepkg succeeded but some expected package is missing
+    NoArtifact,
+    /// The build could not be prepared
+    Error,
+    /// Built, but `--sign` could not produce a signature
+    SignFailed,
+}
+
+impl BuildStatus {
+    /// Label for the summary table and the live view
+    fn text(self) -> &'static str {
+        match self {
+            BuildStatus::Ok => "OK",
+            BuildStatus::Failed => "FAIL",
+            BuildStatus::NoArtifact => "WARN",
+            BuildStatus::Error => "ERROR",
+            BuildStatus::SignFailed => "SIGN",
+        }
+    }
+
+    /// Colored label for the summary table
+    fn label(self) -> colored::ColoredString {
+        match self {
+            BuildStatus::Ok => self.text().green().bold(),
+            BuildStatus::NoArtifact => self.text().yellow().bold(),
+            BuildStatus::Failed | BuildStatus::Error | BuildStatus::SignFailed => {
+                self.text().red().bold()
             }
         }
+    }
+}
 
-        if pkg_found {
-            println!("  {}\n", "OK".green().bold());
-            success_count += 1;
-        } else {
-            println!(
-                "  {} No .pkg.tar.zst found after build\n",
-                "WARN".yellow().bold()
+/// What a build worker tells the main thread
+enum BuildEvent {
+    /// makepkg is about to run for the package at an index, the given
+    /// position in the build order
+    Started(usize, usize),
+    /// The package at an index is done, or could not be built
+    Finished(usize, Result<(BuildRecord, String)>),
+}
+
+/// Outcome and makepkg duration of one package in a batch build
+#[derive(Serialize)]
+struct BuildRecord {
+    name: String,
+    status: BuildStatus,
+    #[serde(rename = "duration_ms", serialize_with = "serialize_millis")]
+    duration: Duration,
+    /// Packages moved to pkgs/
+    artifacts: Vec<PathBuf>,
+    /// makepkg's output, when it was logged (`--json`)
+    log: Option<PathBuf>,
+}
+
+impl BuildRecord {
+    /// A build that ended before makepkg ran
+    fn new(name: String, duration: Duration, status: BuildStatus) -> Self {
+        Self {
+            name,
+            status,
+            duration,
+            artifacts: Vec::new(),
+            log: None,
+        }
+    }
+}
+
+/// The `build --metadata` sidecar of a package
+#[derive(Serialize)]
+struct ArtifactMetadata<'a> {
+    filename: &'a str,
+    pkgname: String,
+    /// `[epoch:]pkgver-pkgrel`
+    version: String,
+    /// Build time in seconds since the Unix epoch
+    timestamp: u64,
+    /// The same instant as UTC `YYYY-MM-DDTHH:MM:SSZ`
+    date: String,
+    /// The package directory it was built from
+    pkgdir: &'a str,
+    sha256: String,
+}
+
+/// The `build --json` document
+#[derive(Serialize)]
+struct BuildReport {
+    packages: Vec<BuildRecord>,
+    summary: BuildSummary,
+}
+
+/// Counts printed at the end of a build
+#[derive(Serialize)]
+struct BuildSummary {
+    packages: usize,
+    succeeded: usize,
+    failed: usize,
+    #[serde(rename = "duration_ms", serialize_with = "serialize_millis")]
+    duration: Duration,
+}
+
+/// makepkg options from the typed build flags, then `--makepkg-args`
+fn makepkg_args(opts: &Options) -> Vec<&str> {
+    let flags = [
+        (opts.nocheck, "--nocheck"),
+        (opts.holdver, "--holdver"),
+        (opts.clean, "--clean"),
+        (opts.cleanbuild, "--cleanbuild"),
+    ];
+    let typed = flags.into_iter().filter(|(set, _)| *set).map(|(_, flag)| flag);
+    typed.chain(opts.makepkg_args.iter().map(String::as_str)).collect()
+}
+
+/// logs/<name>.log next to pkgs/, where makepkg's output goes when logged
+fn log_path(pkgs_dir: &Path, name: &str) -> PathBuf {
+    pkgs_dir.with_file_name("logs").join(format!("{name}.log"))
+}
+
+/// Create (or truncate) [`log_path`] for makepkg's output
+fn open_log(pkgs_dir: &Path, name: &str) -> Result<(PathBuf, std::fs::File)> {
+    let path = log_path(pkgs_dir, name);
+    let logs_dir = pkgs_dir.with_file_name("logs");
+    std::fs::create_dir_all(&logs_dir)
+        .with_context(|| format!("Failed to create {}", logs_dir.display()))?;
+    let file = std::fs::File::create(&path)
+        .with_context(|| format!("Failed to create {}", path.display()))?;
+    Ok((path, file))
+}
+
+/// Print every package with its status and build time, slowest first (fastest
+/// first with `reverse`)
+fn print_build_table(records: &mut [BuildRecord], reverse: bool) {
+    if records.is_empty() {
+        return;
+    }
+    records.sort_by(|a, b| b.duration.cmp(&a.duration).then(a.name.cmp(&b.name)));
+    if reverse {
+        records.reverse();
+    }
+    let width = records.iter().map(|r| r.name.len()).max().unwrap_or(0);
+
+    println!("{}", "Build times:".bold());
+    for record in records.iter() {
+        println!(
+            "  {:<5} {:<width$}  {:>8}",
+            record.status.label(),
+            record.name,
+            format_duration(record.duration)
+        );
+    }
+    println!();
+}
+
+/// Human-friendly duration: `850ms`, `12.3s`, `4m05s`, `1h02m`
+pub fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    if secs >= 3600 {
+        format!("{}h{:02}m", secs / 3600, secs % 3600 / 60)
+    } else if secs >= 60 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    } else if secs >= 1 {
+        format!("{:.1}s", d.as_secs_f64())
+    } else {
+        format!("{}ms", d.as_millis())
+    }
+}
+
+/// Top-level directories of `base` holding files that differ from `git_ref`
+/// (committed or not), per `git diff --name-only`
+fn changed_dirs(base: &Path, git_ref: &str) -> Result<HashSet<String>> {
+    let output = Command::new("git")
+        .args(["diff", "--name-only", "--relative", git_ref, "--"])
+        .current_dir(base)
+        .output()
+        .context("Failed to execute git")?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("git diff {git_ref} failed: {}", stderr.trim());
+    }
+    Ok(top_level_dirs(&String::from_utf8_lossy(&output.stdout)))
+}
+
+/// First component of each path in `git diff --name-only` output; files
+/// directly in the base belong to no package
+fn top_level_dirs(names: &str) -> HashSet<String> {
+    names
+        .lines()
+        .filter_map(|path| path.split_once('/'))
+        .map(|(dir, _)| dir.to_string())
+        .collect()
+}
+
+/// For each package of the batch, the other packages of the batch it
+/// depends on: a `depends`/`makedepends` entry naming one of their pkgnames
+/// or provides. Unreadable PKGBUILDs depend on nothing; their build reports
+/// the problem.
+fn local_dependencies(entries: &[std::fs::DirEntry]) -> Vec<Vec<usize>> {
+    let contents: Vec<String> = entries
+        .iter()
+        .map(|e| std::fs::read_to_string(e.path().join("PKGBUILD")).unwrap_or_default())
+        .collect();
+    let provided: Vec<Vec<String>> = contents
+        .iter()
+        .map(|content| {
+            let provides = pkgbuild::parse_array(content, "provides").into_iter();
+            let provides = provides.map(|p| p.split('=').next().unwrap_or_default().to_string());
+            pkgbuild::parse_array(content, "pkgname").into_iter().chain(provides).collect()
+        })
+        .collect();
+    contents
+        .iter()
+        .enumerate()
+        .map(|(i, content)| {
+            let depends = pkgbuild::parse_build_depends(content);
+            (0..contents.len())
+                .filter(|&j| j != i && provided[j].iter().any(|name| depends.contains(name)))
+                .collect()
+        })
+        .collect()
+}
+
+/// The batch in an order where every package comes after its dependencies
+/// (`deps`, by index into `names`), else by name. A dependency cycle is an
+/// error naming the packages in it.
+fn build_order(names: &[String], deps: &[Vec<usize>]) -> Result<Vec<usize>> {
+    let mut order = Vec::with_capacity(names.len());
+    let mut done = vec![false; names.len()];
+    // Names are sorted, so the lowest ready index is the first name
+    let ready = |done: &[bool], i: usize| !done[i] && deps[i].iter().all(|&d| done[d]);
+    while let Some(next) = (0..names.len()).find(|&i| ready(&done, i)) {
+        done[next] = true;
+        order.push(next);
+    }
+    let Some(start) = done.iter().position(|&d| !d) else {
+        return Ok(order);
+    };
+    // Everything left waits on something else left; follow that to a cycle
+    let mut path = vec![start];
+    loop {
+        let last = *path.last().expect("non-empty path");
+        let dep = *deps[last].iter().find(|&&d| !done[d]).expect("blocked package");
+        if let Some(pos) = path.iter().position(|&p| p == dep) {
+            let cycle: Vec<&str> =
+                path[pos..].iter().chain([&dep]).map(|&i| names[i].as_str()).collect();
+            bail!("dependency cycle among local packages: {}", cycle.join(" -> "));
+        }
+        path.push(dep);
+    }
+}
+
+/// Hands a batch's packages to the build workers in build order, each only
+/// once the packages it depends on have finished
+struct Schedule<'a> {
+    deps: &'a [Vec<usize>],
+    state: Mutex<ScheduleState>,
+    finished: Condvar,
+}
+
+struct ScheduleState {
+    /// Packages not yet handed out, in build order
+    pending: VecDeque<usize>,
+    /// Per package: unfinished, or whether it built
+    built: Vec<Option<bool>>,
+    /// Packages handed out so far
+    started: usize,
+}
+
+impl<'a> Schedule<'a> {
+    fn new(order: Vec<usize>, deps: &'a [Vec<usize>]) -> Self {
+        let state = ScheduleState {
+            built: vec![None; deps.len()],
+            pending: order.into(),
+            started: 0,
+        };
+        Self { deps, state: Mutex::new(state), finished: Condvar::new() }
+    }
+
+    /// The next package whose dependencies have finished, waiting for one
+    /// if need be: its position in the batch, its index, and a dependency
+    /// that failed to build, if any. None once all are handed out.
+    fn next(&self) -> Option<(usize, usize, Option<usize>)> {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if state.pending.is_empty() {
+                return None;
+            }
+            let ready = state
+                .pending
+                .iter()
+                .position(|&i| self.deps[i].iter().all(|&d| state.built[d].is_some()));
+            if let Some(position) = ready {
+                let index = state.pending.remove(position).expect("valid position");
+                let mut deps = self.deps[index].iter().copied();
+                let failed = deps.find(|&d| state.built[d] == Some(false));
+                state.started += 1;
+                return Some((state.started - 1, index, failed));
+            }
+            state = self.finished.wait(state).unwrap();
+        }
+    }
+
+    /// Record that package `index` finished, successfully or not
+    fn finish(&self, index: usize, built: bool) {
+        self.state.lock().unwrap().built[index] = Some(built);
+        self.finished.notify_all();
+    }
+}
+
+/// `--verify-build-deps`: check every `depends`/`makedepends` entry of the
+/// batch before anything is built. An entry is known when it is installed
+/// (`pacman -T`), satisfied by a sync repository (`pacman -Sp`) or a package
+/// or provide of the batch itself, provides counting everywhere; packages with unknown ones
+/// are listed and the build stops.
+fn verify_build_deps(entries: &[std::fs::DirEntry], json: bool) -> Result<()> {
+    let mut batch = HashSet::new();
+    let mut packages = Vec::new();
+    for entry in entries {
+        let pkgbuild_path = entry.path().join("PKGBUILD");
+        let content = std::fs::read_to_string(&pkgbuild_path)
+            .with_context(|| format!("Failed to read {}", pkgbuild_path.display()))?;
+        batch.extend(pkgbuild::parse_array(&content, "pkgname"));
+        batch.extend(pkgbuild::parse_array(&content, "provides").into_iter().map(|provide| {
+            provide.split('=').next().unwrap_or_default().to_string()
+        }));
+        let name = entry.file_name().to_string_lossy().into_owned();
+        packages.push((name, pkgbuild::parse_build_depends(&content)));
+    }
+
+    let mut wanted: Vec<&str> = packages
+        .iter()
+        .flat_map(|(_, depends)| depends.iter().map(String::as_str))
+        .filter(|depend| !batch.contains(*depend))
+        .collect();
+    wanted.sort();
+    wanted.dedup();
+    let unknown = unknown_deps(Path::new("pacman"), &wanted)?;
+
+    let mut missing = 0;
+    for (name, depends) in &packages {
+        let names: Vec<&str> = depends
+            .iter()
+            .map(String::as_str)
+            .filter(|depend| unknown.contains(*depend))
+            .collect();
+        if !names.is_empty() {
+            missing += 1;
+            eprintln!(
+                "{} {} - unknown dependencies: {}",
+                "ERROR".red().bold(),
+                name.white().bold(),
+                names.join(", ")
             );
-            fail_count += 1;
         }
     }
+    if missing > 0 {
+        bail!("{missing} packages have unknown dependencies; not building");
+    }
+    if !json {
+        println!("{}\n", "All build dependencies are available.".dimmed());
+    }
+    Ok(())
+}
 
-    // Final cleanup of the build directory
-    clean_dir(&build_dir)?;
+/// Which of `depends` nothing installed or in a sync repository satisfies,
+/// asking `pacman`
+fn unknown_deps(pacman: &Path, depends: &[&str]) -> Result<HashSet<String>> {
+    if depends.is_empty() {
+        return Ok(HashSet::new());
+    }
+    // `pacman -T` prints the dependencies nothing installed satisfies and
+    // exits with 127 if there are any
+    let output = Command::new(pacman)
+        .arg("-T")
+        .args(depends)
+        .output()
+        .context("Failed to execute pacman")?;
+    if !output.status.success() && output.status.code() != Some(127) {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("pacman -T failed: {}", stderr.trim());
+    }
+    let not_installed: Vec<String> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| line.trim().to_string())
+        .filter(|line| !line.is_empty())
+        .collect();
+    if not_installed.is_empty() {
+        return Ok(HashSet::new());
+    }
 
-    println!(
-        "{}: {} packages, {} succeeded, {} failed",
-        "Summary".bold(),
-        total,
-        success_count.to_string().green(),
-        fail_count.to_string().red()
-    );
+    // Resolve them as `makepkg -s` would install them, provides and version
+    // constraints included (`cargo` by `rust`), without their own
+    // dependencies; only the targets nothing satisfies are errors
+    let output = Command::new(pacman)
+        .args(["-Sp", "-dd", "--noconfirm", "--print-format", "%n"])
+        .args(&not_installed)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .output()
+        .context("Failed to execute pacman")?;
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let unknown = not_found(&stderr);
+    if !output.status.success() && unknown.is_empty() {
+        bail!("pacman -Sp failed: {}", stderr.trim());
+    }
+    Ok(unknown)
+}
+
+/// Targets `pacman -Sp` reported as not found
+fn not_found(stderr: &str) -> HashSet<String> {
+    stderr
+        .lines()
+        .filter_map(|line| line.strip_prefix("error: target not found: "))
+        .map(|name| name.trim().to_string())
+        .collect()
+}
 
+/// Make sure a `validpgpkeys` fingerprint is in the user's keyring, fetching
+/// it with `gpg --recv-keys` if not. Returns whether it was fetched.
+fn import_key(fingerprint: &str) -> Result<bool> {
+    let known = Command::new("gpg")
+        .args(["--batch", "--list-keys", fingerprint])
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .context("Failed to execute gpg")?
+        .success();
+    if known {
+        return Ok(false);
+    }
+    let output = Command::new("gpg")
+        .args(["--batch", "--recv-keys", fingerprint])
+        .output()
+        .context("Failed to execute gpg")?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("gpg --recv-keys {fingerprint} failed: {}", stderr.trim());
+    }
+    Ok(true)
+}
+
+/// Write `<artifact>.sig` with `gpg --detach-sign`.
+/// The key is `key`, else `$GPGKEY` (as makepkg uses it), else gpg's default.
+fn sign_artifact(artifact: &Path, key: Option<&str>) -> Result<()> {
+    let key = key
+        .map(str::to_string)
+        .or_else(|| std::env::var("GPGKEY").ok().filter(|k| !k.is_empty()));
+    let signature = PathBuf::from(format!("{}.sig", artifact.display()));
+
+    let mut gpg = Command::new("gpg");
+    gpg.args(["--batch", "--yes", "--detach-sign", "--no-armor"]);
+    if let Some(key) = &key {
+        gpg.args(["--local-user", key]);
+    }
+    let output = gpg
+        .arg("--output")
+        .arg(&signature)
+        .arg(artifact)
+        .output()
+        .context("Failed to execute gpg")?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("gpg --detach-sign failed: {}", stderr.trim());
+    }
+    if !signature.is_file() {
+        bail!("gpg did not write {}", signature.display());
+    }
+    Ok(())
+}
+
+/// Full paths of the packages makepkg will build in `build_dir`, sorted by
+/// file name
+fn package_list(makepkg: &Path, build_dir: &Path) -> Result<Vec<PathBuf>> {
+    let output = Command::new(makepkg)
+        .arg("--packagelist")
+        .current_dir(build_dir)
+        .output()
+        .context("Failed to execute makepkg --packagelist")?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("makepkg --packagelist failed: {}", stderr.trim());
+    }
+    let mut expected: Vec<PathBuf> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(PathBuf::from)
+        .collect();
+    // Move and print in a stable order so build logs diff cleanly
+    expected.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+    expected.dedup();
+    if expected.is_empty() {
+        bail!("makepkg --packagelist listed no packages");
+    }
+    Ok(expected)
+}
+
+/// Resolve `program` like the shell would: a bare name is looked up in PATH,
+/// anything with a slash is taken relative to the current directory. It must
+/// be an executable file. The path returned is absolute, as makepkg runs in
+/// the build directory.
+pub fn find_executable(program: &Path) -> Result<PathBuf> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let executable = |path: &Path| {
+        path.metadata()
+            .is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+    };
+    if program.components().count() > 1 {
+        if !program.is_file() {
+            bail!("makepkg binary {} does not exist", program.display());
+        }
+        if !executable(program) {
+            bail!("makepkg binary {} is not executable", program.display());
+        }
+        return std::path::absolute(program)
+            .with_context(|| format!("Failed to resolve {}", program.display()));
+    }
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    std::env::split_paths(&path)
+        .map(|dir| dir.join(program))
+        .find(|candidate| executable(candidate))
+        .with_context(|| format!("makepkg binary {} not found in PATH", program.display()))
+}
+
+/// Prefix of the per-build staging directories in pkgs/
+const STAGING_PREFIX: &str = ".staging-";
+
+/// Staging directory name for package `name` built by process `pid`, which
+/// keeps concurrent runs in the same pkgs/ apart
+fn staging_name(pid: u32, name: &str) -> String {
+    format!("{STAGING_PREFIX}{pid}-{name}")
+}
+
+/// Move a built package into `staging`, created if needed. Packages written
+/// to another filesystem (a `PKGDEST` elsewhere) are copied instead.
+fn stage_artifact(path: &Path, staging: &Path) -> Result<PathBuf> {
+    std::fs::create_dir_all(staging)
+        .with_context(|| format!("Failed to create {}", staging.display()))?;
+    let target = staging.join(path.file_name().unwrap_or_default());
+    std::fs::rename(path, &target)
+        .or_else(|_| std::fs::copy(path, &target).and_then(|_| std::fs::remove_file(path)))
+        .with_context(|| format!("Failed to stage {}", path.display()))?;
+    Ok(target)
+}
+
+/// `--metadata`: write `<artifact>.json` next to a package with its name,
+/// version, build time, package directory and SHA-256
+fn write_metadata(artifact: &Path, pkgdir: &str) -> Result<()> {
+    let fname = artifact.file_name().unwrap_or_default().to_string_lossy();
+    let (pkgname, version) = crate::prune::parse_artifact_name(&fname)
+        .with_context(|| format!("cannot tell the package name and version of {fname}"))?;
+    let mut file = std::fs::File::open(artifact)
+        .with_context(|| format!("Failed to read {}", artifact.display()))?;
+    let mut hasher = Sha256::new();
+    std::io::copy(&mut file, &mut hasher)
+        .with_context(|| format!("Failed to read {}", artifact.display()))?;
+    let sha256: String = hasher.finalize().iter().map(|b| format!("{b:02x}")).collect();
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+
+    let metadata = ArtifactMetadata {
+        filename: &fname,
+        pkgname,
+        version: version.to_string(),
+        timestamp,
+        date: crate::report::utc_date(timestamp),
+        pkgdir,
+        sha256,
+    };
+    let path = PathBuf::from(format!("{}.json", artifact.display()));
+    let mut content = crate::report::to_json(&metadata, true);
+    content.push('\n');
+    std::fs::write(&path, content).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Move staged packages (and their signatures and metadata, first) into
+/// `pkgs_dir` and remove their staging directory. Both are on the same
+/// filesystem, so each file appears in pkgs/ whole or not at all.
+fn publish_artifacts(staged: &[PathBuf], pkgs_dir: &Path) -> Result<Vec<PathBuf>> {
+    let publish = |path: &Path| -> Result<PathBuf> {
+        let fname = path.file_name().unwrap_or_default();
+        let target = pkgs_dir.join(fname);
+        std::fs::rename(path, &target)
+            .with_context(|| format!("Failed to move {} to pkgs/", fname.to_string_lossy()))?;
+        Ok(target)
+    };
+    let mut moved = Vec::new();
+    for artifact in staged {
+        for ext in ["sig", "json"] {
+            let sidecar = PathBuf::from(format!("{}.{ext}", artifact.display()));
+            if sidecar.is_file() {
+                publish(&sidecar)?;
+            }
+        }
+        moved.push(publish(artifact)?);
+    }
+    if let Some(staging) = staged.first().and_then(|a| a.parent()) {
+        std::fs::remove_dir_all(staging)
+            .with_context(|| format!("Failed to remove {}", staging.display()))?;
+    }
+    Ok(moved)
+}
+
+/// Remove staging directories left in `pkgs_dir` by builds that were killed
+/// before their packages were published. Those of an rchan still running
+/// (another build into the same pkgs/) are left alone.
+fn remove_stale_staging(pkgs_dir: &Path) -> Result<()> {
+    for entry in std::fs::read_dir(pkgs_dir)? {
+        let entry = entry?;
+        let file_name = entry.file_name().to_string_lossy().into_owned();
+        let Some(rest) = file_name.strip_prefix(STAGING_PREFIX) else {
+            continue;
+        };
+        let owner = rest.split_once('-').and_then(|(pid, _)| pid.parse::<u32>().ok());
+        if owner.is_some_and(|pid| Path::new("/proc").join(pid.to_string()).exists()) {
+            continue;
+        }
+        std::fs::remove_dir_all(entry.path())
+            .with_context(|| format!("Failed to remove {}", entry.path().display()))?;
+    }
     Ok(())
 }
 
@@ -172,3 +1334,275 @@ fn clean_dir(dir: &Path) -> Result<()> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::scratch_dir;
+
+    #[test]
+    fn test_parse_makepkg_conf() {
+        let conf = "#SRCDEST=/home/sources\n\
+                    PKGDEST=\"/srv/packages\" # shared\n\
+                    SRCDEST=\n\
+                    BUILDDIR=/tmp/makepkg\n";
+        assert_eq!(
+            parse_makepkg_conf(conf),
+            [("PKGDEST", "/srv/packages".to_string()), ("SRCDEST", String::new())]
+        );
+    }
+
+    #[test]
+    fn test_find_executable() {
+        assert!(find_executable(Path::new("sh")).unwrap().is_absolute());
+        assert!(find_executable(Path::new("/bin/sh")).is_ok());
+        assert!(find_executable(Path::new("rchan-no-such-makepkg")).is_err());
+
+        let dir = scratch_dir("find-executable");
+        let file = dir.join("makepkg");
+        std::fs::write(&file, "#!/bin/sh\n").unwrap();
+        let err = find_executable(&file).unwrap_err();
+        assert!(err.to_string().contains("not executable"));
+        assert!(find_executable(&file.with_extension("missing")).is_err());
+
+        // A path relative to the current directory still works once makepkg
+        // runs in the build dir
+        std::fs::set_permissions(&file, std::os::unix::fs::PermissionsExt::from_mode(0o755))
+            .unwrap();
+        let cwd = std::env::current_dir().unwrap();
+        let up: PathBuf = cwd.components().skip(1).map(|_| "..").collect();
+        let relative = up.join(file.strip_prefix("/").unwrap());
+        let resolved = find_executable(&relative).unwrap();
+        assert!(resolved.is_absolute());
+        assert_eq!(resolved.canonicalize().unwrap(), file.canonicalize().unwrap());
+    }
+
+    #[test]
+    fn test_not_found() {
+        let stderr = "error: target not found: libfoo\n\
+                      warning: database file for 'extra' does not exist\n\
+                      error: target not found: python-bar>=2\n";
+        let names = not_found(stderr);
+        assert_eq!(names, HashSet::from(["libfoo".to_string(), "python-bar>=2".to_string()]));
+        assert!(not_found("").is_empty());
+    }
+
+    #[test]
+    fn test_unknown_deps_provided() {
+        // Nothing is installed; the sync repositories have `rust`, which
+        // provides `cargo`
+        let dir = scratch_dir("provided-deps");
+        let pacman = dir.join("pacman");
+        std::fs::write(
+            &pacman,
+            "#!/bin/sh\n\
+             if [ \"$1\" = -T ]; then shift; printf '%s\\n' \"$@\"; exit 127; fi\n\
+             status=0\n\
+             for target; do\n\
+                 case $target in\n\
+                     -*|%n) ;;\n\
+                     rust|cargo) echo rust ;;\n\
+                     *) echo \"error: target not found: $target\" >&2; status=1 ;;\n\
+                 esac\n\
+             done\n\
+             exit $status\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&pacman, std::os::unix::fs::PermissionsExt::from_mode(0o755))
+            .unwrap();
+        assert!(unknown_deps(&pacman, &["cargo", "rust"]).unwrap().is_empty());
+        let unknown = unknown_deps(&pacman, &["cargo", "rchan-no-such-dep"]).unwrap();
+        assert_eq!(unknown, HashSet::from(["rchan-no-such-dep".to_string()]));
+    }
+
+    #[test]
+    fn test_staged_artifacts() {
+        let dir = scratch_dir("staging");
+        let (build_dir, pkgs_dir) = (dir.join("build"), dir.join("pkgs"));
+        std::fs::create_dir_all(&build_dir).unwrap();
+        std::fs::create_dir_all(&pkgs_dir).unwrap();
+        let artifact = build_dir.join("foo-1-1-x86_64.pkg.tar.zst");
+        // Left by a process that is gone
+        let mut exited = Command::new("true").spawn().unwrap();
+        exited.wait().unwrap();
+        let staging = pkgs_dir.join(staging_name(exited.id(), "foo"));
+        // Belongs to a build still running, this one
+        let running = pkgs_dir.join(staging_name(std::process::id(), "bar"));
+        std::fs::create_dir(&running).unwrap();
+        let published = |pkgs_dir: &Path| {
+            let mut names: Vec<String> = std::fs::read_dir(pkgs_dir)
+                .unwrap()
+                .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+                .filter(|name| !name.starts_with('.'))
+                .collect();
+            names.sort();
+            names
+        };
+
+        // A build killed after staging leaves nothing in pkgs/ itself, and
+        // the next run clears the leftovers
+        std::fs::write(&artifact, "partial").unwrap();
+        stage_artifact(&artifact, &staging).unwrap();
+        assert!(published(&pkgs_dir).is_empty());
+        remove_stale_staging(&pkgs_dir).unwrap();
+        assert!(!staging.exists());
+        assert!(running.exists());
+
+        std::fs::write(&artifact, "package").unwrap();
+        let staged = stage_artifact(&artifact, &staging).unwrap();
+        std::fs::write(format!("{}.sig", staged.display()), "signature").unwrap();
+        write_metadata(&staged, "foo-pkg").unwrap();
+        let moved = publish_artifacts(&[staged], &pkgs_dir).unwrap();
+        assert_eq!(moved, [pkgs_dir.join("foo-1-1-x86_64.pkg.tar.zst")]);
+        assert_eq!(
+            published(&pkgs_dir),
+            [
+                "foo-1-1-x86_64.pkg.tar.zst",
+                "foo-1-1-x86_64.pkg.tar.zst.json",
+                "foo-1-1-x86_64.pkg.tar.zst.sig"
+            ]
+        );
+        assert_eq!(std::fs::read_to_string(&moved[0]).unwrap(), "package");
+        let metadata = std::fs::read_to_string(pkgs_dir.join("foo-1-1-x86_64.pkg.tar.zst.json"));
+        let metadata: serde_json::Value = serde_json::from_str(&metadata.unwrap()).unwrap();
+        assert_eq!(metadata["pkgname"], "foo");
+        assert_eq!(metadata["version"], "1-1");
+        assert_eq!(metadata["pkgdir"], "foo-pkg");
+        assert_eq!(
+            metadata["sha256"],
+            "bc4a71180870f7945155fbb02f4b0a2e3faa2a62d6d31b7039013055ed19869a"
+        );
+        assert!(!staging.exists() && !artifact.exists());
+    }
+
+    #[test]
+    fn test_build_package_bumped_pkgver() {
+        // Like a VCS package's pkgver(): the build rewrites pkgver, and
+        // --packagelist reads whatever the PKGBUILD says at the time
+        let dir = scratch_dir("bumped-pkgver");
+        let (pkg_src, build_dir, pkgs_dir) = (dir.join("foo"), dir.join("build"), dir.join("pkgs"));
+        std::fs::create_dir_all(&pkg_src).unwrap();
+        std::fs::write(pkg_src.join("PKGBUILD"), "pkgver=1\n").unwrap();
+        let makepkg = dir.join("makepkg");
+        std::fs::write(
+            &makepkg,
+            "#!/bin/sh\n\
+             . ./PKGBUILD\n\
+             if [ \"$1\" = --packagelist ]; then\n\
+                 echo \"$PWD/foo-$pkgver-1-any.pkg.tar.zst\"; exit\n\
+             fi\n\
+             echo pkgver=2 > PKGBUILD\n\
+             touch foo-2-1-any.pkg.tar.zst\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&makepkg, std::os::unix::fs::PermissionsExt::from_mode(0o755))
+            .unwrap();
+
+        let (record, report) = build_package(
+            &makepkg,
+            &pkg_src,
+            &build_dir,
+            &pkgs_dir,
+            "foo".to_string(),
+            false,
+            &Options::default(),
+        )
+        .unwrap();
+        assert_eq!(record.status, BuildStatus::Ok, "{report}");
+        assert_eq!(record.artifacts, [pkgs_dir.join("foo-2-1-any.pkg.tar.zst")]);
+        assert!(record.artifacts[0].is_file());
+    }
+
+    #[test]
+    fn test_build_package_interrupted() {
+        // makepkg is killed while writing the package
+        let dir = scratch_dir("interrupted");
+        let (pkg_src, build_dir, pkgs_dir) = (dir.join("foo"), dir.join("build"), dir.join("pkgs"));
+        std::fs::create_dir_all(&pkg_src).unwrap();
+        std::fs::create_dir_all(&pkgs_dir).unwrap();
+        std::fs::write(pkg_src.join("PKGBUILD"), "pkgver=1\n").unwrap();
+        let makepkg = dir.join("makepkg");
+        std::fs::write(
+            &makepkg,
+            "#!/bin/sh\n\
+             if [ \"$1\" = --packagelist ]; then\n\
+                 echo \"$PWD/foo-1-1-any.pkg.tar.zst\"; exit\n\
+             fi\n\
+             echo partial > foo-1-1-any.pkg.tar.zst\n\
+             kill -KILL $$\n",
+        )
+        .unwrap();
+        std::fs::set_permissions(&makepkg, std::os::unix::fs::PermissionsExt::from_mode(0o755))
+            .unwrap();
+
+        let (record, report) = build_package(
+            &makepkg,
+            &pkg_src,
+            &build_dir,
+            &pkgs_dir,
+            "foo".to_string(),
+            false,
+            &Options::default(),
+        )
+        .unwrap();
+        assert_eq!(record.status, BuildStatus::Failed, "{report}");
+        assert!(record.artifacts.is_empty());
+        // Neither the partial package nor a staging directory reached pkgs/
+        assert_eq!(std::fs::read_dir(&pkgs_dir).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_db_signatures() {
+        assert_eq!(
+            db_signatures(Path::new("pkgs/custom.db.tar.zst")),
+            [
+                PathBuf::from("pkgs/custom.db.tar.zst.sig"),
+                PathBuf::from("pkgs/custom.files.tar.zst.sig")
+            ]
+        );
+        assert_eq!(db_signatures(Path::new("custom.db")), [PathBuf::from("custom.db.sig")]);
+    }
+
+    #[test]
+    fn test_makepkg_args() {
+        assert!(makepkg_args(&Options::default()).is_empty());
+        let opts = Options {
+            holdver: true,
+            clean: true,
+            makepkg_args: vec!["--skippgpcheck".to_string()],
+            ..Options::default()
+        };
+        assert_eq!(makepkg_args(&opts), ["--holdver", "--clean", "--skippgpcheck"]);
+    }
+
+    #[test]
+    fn test_build_order() {
+        let names: Vec<String> = ["a", "b", "c", "d"].map(String::from).into();
+        // a needs c, c needs d; b stands alone
+        let deps = vec![vec![2], vec![], vec![3], vec![]];
+        assert_eq!(build_order(&names, &deps).unwrap(), [1, 3, 2, 0]);
+
+        let cycle = vec![vec![2], vec![], vec![3], vec![0]];
+        let err = build_order(&names, &cycle).unwrap_err();
+        assert_eq!(err.to_string(), "dependency cycle among local packages: a -> c -> d -> a");
+    }
+
+    #[test]
+    fn test_schedule() {
+        // b depends on a, which fails
+        let deps = vec![vec![], vec![0], vec![]];
+        let schedule = Schedule::new(vec![0, 1, 2], &deps);
+        assert_eq!(schedule.next(), Some((0, 0, None)));
+        // b waits for a, so c goes first
+        assert_eq!(schedule.next(), Some((1, 2, None)));
+        schedule.finish(0, false);
+        assert_eq!(schedule.next(), Some((2, 1, Some(0))));
+        assert_eq!(schedule.next(), None);
+    }
+
+    #[test]
+    fn test_top_level_dirs() {
+        let dirs = top_level_dirs("foo/PKGBUILD\nfoo/fix.patch\nbar/sub/file\nREADME.md\n");
+        assert_eq!(dirs, HashSet::from(["foo".to_string(), "bar".to_string()]));
+    }
+}
@@ -0,0 +1,180 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// File in the scanned directory that caches remote responses between checks
+pub const CACHE_FILE: &str = ".rchan-cache.json";
+
+/// Remote PKGBUILDs from earlier checks, reused while the server said they
+/// stay fresh and revalidated with conditional requests after that, so an
+/// unchanged remote isn't downloaded again
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ResultCache {
+    /// Last response per package name
+    pub packages: BTreeMap<String, CacheEntry>,
+}
+
+/// One package's cached remote
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CacheEntry {
+    /// [`content_hash`] of the local PKGBUILD the entry was stored with; a
+    /// changed PKGBUILD starts from scratch
+    pub local_hash: String,
+    /// Source the content was fetched from
+    pub source: String,
+    /// Validators the server sent with the content
+    #[serde(flatten)]
+    pub validators: Validators,
+    /// The remote PKGBUILD (or release info) as last fetched
+    pub content: String,
+}
+
+/// HTTP cache validators of a response, and how long it stays fresh
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Validators {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub etag: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<String>,
+    /// Unix time until which the response may be reused without asking the
+    /// server, from its `Cache-Control: max-age`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fresh_until: Option<u64>,
+}
+
+impl Validators {
+    /// Whether the server sent nothing that lets a later check skip the
+    /// download
+    pub fn is_empty(&self) -> bool {
+        self.etag.is_none() && self.last_modified.is_none() && self.fresh_until.is_none()
+    }
+
+    /// Whether the response is still within its freshness window
+    pub fn is_fresh(&self) -> bool {
+        self.fresh_until.is_some_and(|until| unix_now() < until)
+    }
+}
+
+/// When a response with these `Cache-Control` and `Age` headers stops being
+/// fresh, as Unix time; None if the server didn't allow reusing it
+pub fn fresh_until(cache_control: Option<&str>, age: Option<&str>) -> Option<u64> {
+    let mut max_age = None;
+    for directive in cache_control?.split(',') {
+        let directive = directive.trim().to_ascii_lowercase();
+        match directive.split_once('=') {
+            Some(("max-age", secs)) => max_age = secs.trim_matches('"').parse::<u64>().ok(),
+            None if directive == "no-cache" || directive == "no-store" => return None,
+            _ => {}
+        }
+    }
+    // The response may already have sat in a proxy for `Age` seconds
+    let age = age.and_then(|age| age.trim().parse::<u64>().ok()).unwrap_or(0);
+    let left = max_age?.checked_sub(age).filter(|&left| left > 0)?;
+    Some(unix_now() + left)
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+impl ResultCache {
+    /// Load the cache file; a missing file is an empty cache
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    /// Write the cache file, replacing it only once fully written
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let content = serde_json::to_string(self).expect("serializable cache");
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, content)
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, path).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// The entry for `name`, if it was stored for the same local PKGBUILD
+    /// and source
+    pub fn get(&self, name: &str, local_hash: &str, source: &str) -> Option<&CacheEntry> {
+        self.packages
+            .get(name)
+            .filter(|entry| entry.local_hash == local_hash && entry.source == source)
+    }
+}
+
+/// Hash identifying file content (64-bit FNV-1a, as hex). Stable across
+/// runs and builds, which `std`'s hasher doesn't promise; not meant to
+/// resist tampering.
+pub fn content_hash(bytes: &[u8]) -> String {
+    let hash = bytes.iter().fold(0xcbf2_9ce4_8422_2325_u64, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    format!("{hash:016x}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_content_hash() {
+        // Reference values of FNV-1a 64
+        assert_eq!(content_hash(b""), "cbf29ce484222325");
+        assert_eq!(content_hash(b"a"), "af63dc4c8601ec8c");
+        assert_ne!(content_hash(b"pkgver=1\n"), content_hash(b"pkgver=2\n"));
+    }
+
+    #[test]
+    fn test_cache_lookup() {
+        let entry = CacheEntry {
+            local_hash: content_hash(b"pkgver=1\n"),
+            source: "https://example.org/PKGBUILD".to_string(),
+            validators: Validators {
+                etag: Some("\"abc\"".to_string()),
+                last_modified: None,
+                fresh_until: None,
+            },
+            content: "pkgver=2\n".to_string(),
+        };
+        let mut cache = ResultCache::default();
+        cache.packages.insert("foo".to_string(), entry.clone());
+
+        let json = serde_json::to_string(&cache).unwrap();
+        let cache: ResultCache = serde_json::from_str(&json).unwrap();
+        let hash = content_hash(b"pkgver=1\n");
+        assert_eq!(cache.get("foo", &hash, &entry.source), Some(&entry));
+        assert_eq!(cache.get("foo", &content_hash(b"pkgver=3\n"), &entry.source), None);
+        assert_eq!(cache.get("foo", &hash, "https://example.org/other"), None);
+        assert_eq!(cache.get("bar", &hash, &entry.source), None);
+    }
+
+    #[test]
+    fn test_fresh_until() {
+        let now = unix_now();
+        let until = fresh_until(Some("public, max-age=300"), None).unwrap();
+        assert!((now + 299..=now + 301).contains(&until));
+        let until = fresh_until(Some("Max-Age=300"), Some("100")).unwrap();
+        assert!((now + 199..=now + 201).contains(&until));
+        assert_eq!(fresh_until(Some("max-age=300"), Some("300")), None);
+        assert_eq!(fresh_until(Some("max-age=300, no-cache"), None), None);
+        assert_eq!(fresh_until(Some("private"), None), None);
+        assert_eq!(fresh_until(None, None), None);
+
+        let mut validators = Validators::default();
+        assert!(!validators.is_fresh());
+        validators.fresh_until = Some(now + 60);
+        assert!(validators.is_fresh() && !validators.is_empty());
+        validators.fresh_until = Some(now - 1);
+        assert!(!validators.is_fresh());
+    }
+}
@@ -0,0 +1,1226 @@
+use std::io::IsTerminal;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::{Context, Result, bail};
+use colored::Colorize;
+
+use crate::config::GlobalConfig;
+use crate::fetch::SourceKind;
+use crate::output;
+use crate::pkgbuild::ParseOptions;
+use crate::builder::Escalation;
+use crate::updater::PkgrelPolicy;
+
+/// Subcommand selected on the command line
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    /// Check PKGBUILD updates (default)
+    Check,
+    /// Build all packages with makepkg
+    Build,
+    /// Rewrite local PKGBUILDs to the remote version
+    Update,
+    /// Compare local PKGBUILD versions with installed packages
+    Installed,
+    /// Delete old package versions from pkgs/
+    Prune,
+    /// Re-check periodically and print changes
+    Watch,
+    /// Measure fetch latency of URLs
+    Bench,
+    /// Suggest an rchan.yaml for a package directory
+    GuessRemote,
+    /// Regenerate .SRCINFO files with makepkg
+    RefreshSrcinfo,
+    /// Explain how one package's versions compare
+    Why,
+    /// Print a shell completion script
+    Completions,
+    /// List package names for the completion scripts (hidden)
+    Complete,
+    /// Print the help message
+    Help,
+}
+
+/// How `check` prints its results
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    /// Colored human-readable lines
+    Text,
+    /// One JSON document once the scan finishes
+    Json,
+    /// One JSON object per line as each package completes (NDJSON)
+    JsonLines,
+    /// A GitHub-flavored Markdown table once the scan finishes
+    Markdown,
+}
+
+impl std::str::FromStr for OutputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "text" => Ok(OutputFormat::Text),
+            "json" => Ok(OutputFormat::Json),
+            "json-lines" => Ok(OutputFormat::JsonLines),
+            "markdown" => Ok(OutputFormat::Markdown),
+            other => {
+                bail!("unknown format '{other}' (expected text, json, json-lines or markdown)")
+            }
+        }
+    }
+}
+
+/// Order of per-package lines (`--sort`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortOrder {
+    /// By package name (or as listed with `--packages-from`)
+    Name,
+    /// Higher `priority` first, then errors, updates and the rest, each by name
+    Status,
+}
+
+impl std::str::FromStr for SortOrder {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "name" => Ok(SortOrder::Name),
+            "status" => Ok(SortOrder::Status),
+            other => bail!("unknown sort order '{other}' (expected name or status)"),
+        }
+    }
+}
+
+/// When output is colored (`--color`)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorChoice {
+    /// Color a terminal, unless `NO_COLOR` is set (or `CLICOLOR_FORCE` forces it)
+    Auto,
+    /// Color even through a pipe
+    Always,
+    Never,
+}
+
+impl std::str::FromStr for ColorChoice {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            other => bail!("unknown color mode '{other}' (expected auto, always or never)"),
+        }
+    }
+}
+
+/// Global command line options
+#[derive(Debug)]
+pub struct Options {
+    /// Number of packages checked concurrently
+    pub jobs: usize,
+    /// Number of packages `build` builds concurrently (1 unless `--jobs` is given)
+    pub build_jobs: usize,
+    /// Build in parallel even if makepkg's SRCDEST/PKGDEST are shared
+    pub parallel_builds_safe: bool,
+    /// Sign built packages with gpg
+    pub sign: bool,
+    /// Write a `<package>.json` with metadata next to each built package
+    pub metadata: bool,
+    /// Fetch missing `validpgpkeys` with `gpg --recv-keys` before building
+    pub import_keys: bool,
+    /// Check that build dependencies exist before building anything
+    pub verify_build_deps: bool,
+    /// Key for `--sign=KEYID` (None: $GPGKEY, then gpg's default key)
+    pub sign_key: Option<String>,
+    /// Repository database `repo-add` adds built packages to
+    pub repo_db: Option<PathBuf>,
+    /// Sign the repository database with `repo-add --sign`
+    pub repo_sign_db: bool,
+    /// Build only packages with files changed since this git ref
+    pub changed_since: Option<String>,
+    /// How makepkg escalates to install dependencies (None: makepkg's default)
+    pub sudo: Option<Escalation>,
+    /// makepkg binary `build` runs (None: $RCHAN_MAKEPKG, global config, `makepkg`)
+    pub makepkg_bin: Option<PathBuf>,
+    /// Skip the PKGBUILD's check() (makepkg `--nocheck`)
+    pub nocheck: bool,
+    /// Don't update VCS sources to the latest revision (makepkg `--holdver`)
+    pub holdver: bool,
+    /// Remove work files after a successful build (makepkg `--clean`)
+    pub clean: bool,
+    /// Remove `$srcdir` before building (makepkg `--cleanbuild`)
+    pub cleanbuild: bool,
+    /// Extra makepkg arguments, after the ones above (`--makepkg-args`)
+    pub makepkg_args: Vec<String>,
+    /// Show builds in a live terminal view (`--tui`)
+    pub tui: bool,
+    /// Print results in completion order instead of name order
+    pub unordered: bool,
+    /// Order of ordered output
+    pub sort: SortOrder,
+    /// Reverse the `--sort` order of printed results, and the build table
+    pub reverse: bool,
+    /// Treat warnings as errors in the exit status
+    pub strict: bool,
+    /// Exit 0 when updates are available; only errors make the exit status non-zero
+    pub fail_on_error: bool,
+    /// Only print updates, errors and warnings
+    pub quiet: bool,
+    /// Only print the summary line
+    pub summary_only: bool,
+    /// Also print notices, such as a remote URL rewritten to the raw file
+    pub verbose: bool,
+    /// Collapse up-to-date lines after this many (None: print all)
+    pub max_ok_lines: Option<usize>,
+    /// Suppress the header printed before scanning/building
+    pub no_banner: bool,
+    /// When to color output
+    pub color: ColorChoice,
+    /// Error on conflicting `pkgver=` assignments in a PKGBUILD
+    pub strict_pkgver: bool,
+    /// Compare only epoch and pkgver; pkgrel-only changes count as up to date
+    pub ignore_pkgrel: bool,
+    /// File name of the per-package config probed in each directory
+    pub config_name: String,
+    /// How `update` writes pkgrel (None: depends on what changed)
+    pub pkgrel_policy: Option<PkgrelPolicy>,
+    /// Show what `update` would change without writing files
+    pub dry_run: bool,
+    /// Never touch the network; only local remote sources are read
+    pub offline: bool,
+    /// Print a per-source-kind breakdown after the summary
+    pub group_by_source: bool,
+    /// Print request concurrency statistics after the summary
+    pub concurrency_report: bool,
+    /// Print request statistics, such as how much `--rate-limit` slowed the
+    /// run, after the summary
+    pub stats: bool,
+    /// Tune request concurrency during the run instead of using `jobs`
+    pub adaptive_jobs: bool,
+    /// Most network requests per second, across all threads (None: no limit)
+    pub rate_limit: Option<f64>,
+    /// Only check packages with these kinds of remote (`--source-type`;
+    /// empty: all)
+    pub source_types: Vec<SourceKind>,
+    /// Only fetch and parse each remote; don't compare with local PKGBUILDs
+    pub probe: bool,
+    /// Report version hygiene issues in local PKGBUILDs
+    pub lint: bool,
+    /// Print this package's fetched remote verbatim instead of checking
+    pub dump_remote: Option<String>,
+    /// Print the effective configuration instead of checking
+    pub dump_config: bool,
+    /// Package for `--dump-config=NAME` (None: all)
+    pub dump_config_name: Option<String>,
+    /// Wall-clock budget for the whole run
+    pub deadline: Option<Duration>,
+    /// Stop checking once this many packages failed (`--max-errors`)
+    pub max_errors: Option<usize>,
+    /// Number of newest versions per package `prune` keeps
+    pub keep: usize,
+    /// Output format of `check` (and `build`, which only knows text and JSON)
+    pub format: OutputFormat,
+    /// Indent `--json` documents (`--pretty`/`--compact`); unset, they are
+    /// indented only on a terminal
+    pub pretty: Option<bool>,
+    /// Largest remote PKGBUILD response read, in bytes
+    pub max_size: u64,
+    /// Per-attempt request timeout (None: global config, then 30s)
+    pub timeout: Option<Duration>,
+    /// Retries for failed requests (None: global config, then 0)
+    pub retries: Option<u32>,
+    /// Extra trusted root certificates (PEM) for HTTPS
+    pub cacert: Option<PathBuf>,
+    /// Skip TLS certificate verification
+    pub insecure: bool,
+    /// Hosts network sources may use, from the global config (None: any)
+    pub allowed_hosts: Option<Vec<String>>,
+    /// Directories to run in (`-C DIR`, repeatable); several are scanned
+    /// together by check
+    pub dirs: Vec<PathBuf>,
+    /// Check only the package directories listed in this file
+    pub packages_from: Option<PathBuf>,
+    /// Read one package's config from stdin instead of scanning (`check --stdin`)
+    pub stdin: bool,
+    /// Local PKGBUILD to compare against with `--stdin`
+    pub pkgbuild: Option<PathBuf>,
+    /// Append each check's summary as a JSON line to this file
+    pub history: Option<PathBuf>,
+    /// Write each check's summary and exit status to this file as JSON
+    pub summary_json: Option<PathBuf>,
+    /// Shell command run after a check, with the JSON report on stdin
+    pub on_complete: Option<String>,
+    /// Create this file when a check finds updates or errors, remove it otherwise
+    pub touch_on_update: Option<PathBuf>,
+    /// Don't update `.rchan-state.json` after a check
+    pub no_state: bool,
+    /// Check only the packages that failed in the last run, per the state file
+    pub retry_errors: bool,
+    /// Neither use nor update `.rchan-cache.json`
+    pub no_cache: bool,
+    /// Also look for packages in directories whose names start with `.`
+    pub include_hidden: bool,
+    /// Leave `${VAR}` in configs as is when VAR is unset, instead of failing
+    pub allow_undefined_env: bool,
+    /// Time between `watch` passes
+    pub interval: Duration,
+    /// Stop `watch` once any package has an update
+    pub exit_on_update: bool,
+    /// Custom per-package line format, e.g. `{name} {remote}`
+    pub output_template: Option<String>,
+    /// Requests per URL for `bench`
+    pub count: usize,
+    /// Positional arguments after the subcommand (`bench` URLs, `guess-remote` dir,
+    /// `refresh-srcinfo` packages, `why` package)
+    pub args: Vec<String>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            jobs: 4,
+            build_jobs: 1,
+            parallel_builds_safe: false,
+            sign: false,
+            metadata: false,
+            import_keys: false,
+            verify_build_deps: false,
+            sign_key: None,
+            repo_db: None,
+            repo_sign_db: false,
+            changed_since: None,
+            sudo: None,
+            makepkg_bin: None,
+            nocheck: false,
+            holdver: false,
+            clean: false,
+            cleanbuild: false,
+            makepkg_args: Vec::new(),
+            tui: false,
+            unordered: false,
+            sort: SortOrder::Name,
+            reverse: false,
+            strict: false,
+            fail_on_error: false,
+            quiet: false,
+            summary_only: false,
+            verbose: false,
+            max_ok_lines: None,
+            no_banner: false,
+            color: ColorChoice::Auto,
+            strict_pkgver: false,
+            ignore_pkgrel: false,
+            config_name: "rchan.yaml".to_string(),
+            pkgrel_policy: None,
+            dry_run: false,
+            offline: false,
+            group_by_source: false,
+            concurrency_report: false,
+            stats: false,
+            adaptive_jobs: false,
+            rate_limit: None,
+            source_types: Vec::new(),
+            probe: false,
+            lint: false,
+            dump_remote: None,
+            dump_config: false,
+            dump_config_name: None,
+            deadline: None,
+            max_errors: None,
+            keep: 1,
+            format: OutputFormat::Text,
+            pretty: None,
+            max_size: 4 * 1024 * 1024,
+            timeout: None,
+            retries: None,
+            cacert: None,
+            insecure: false,
+            allowed_hosts: None,
+            dirs: Vec::new(),
+            packages_from: None,
+            stdin: false,
+            pkgbuild: None,
+            history: None,
+            touch_on_update: None,
+            on_complete: None,
+            summary_json: None,
+            no_state: false,
+            retry_errors: false,
+            no_cache: false,
+            include_hidden: false,
+            allow_undefined_env: false,
+            interval: Duration::from_secs(600),
+            exit_on_update: false,
+            output_template: None,
+            count: 5,
+            args: Vec::new(),
+        }
+    }
+}
+
+impl Options {
+    /// Whether to indent JSON documents: as `--pretty`/`--compact` say, else
+    /// only when stdout is a terminal
+    pub fn pretty_json(&self) -> bool {
+        self.pretty.unwrap_or_else(|| std::io::stdout().is_terminal())
+    }
+
+    /// Fill in settings the command line left unset from the environment
+    /// (`$RCHAN_MAKEPKG`) and the global config
+    pub fn apply_global(&mut self, global: &GlobalConfig) {
+        if self.timeout.is_none() {
+            self.timeout = global.timeout.map(Duration::from_secs_f64);
+        }
+        if self.retries.is_none() {
+            self.retries = global.retries;
+        }
+        if self.cacert.is_none() {
+            self.cacert = global.cacert.clone();
+        }
+        self.allowed_hosts = global.allowed_hosts.clone();
+        if self.makepkg_bin.is_none() {
+            self.makepkg_bin = std::env::var_os("RCHAN_MAKEPKG")
+                .filter(|bin| !bin.is_empty())
+                .map(PathBuf::from)
+                .or_else(|| global.makepkg_bin.clone());
+        }
+    }
+
+    /// PKGBUILD parse options selected on the command line
+    pub fn parse_options(&self) -> ParseOptions {
+        ParseOptions {
+            strict_pkgver: self.strict_pkgver,
+            pkgver_var: None,
+        }
+    }
+}
+
+/// Parse command line arguments (without the program name)
+pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<(Command, Options)> {
+    let mut command = None;
+    let mut opts = Options::default();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        // Support both `--flag value` and `--flag=value`
+        let (flag, inline) = match arg.split_once('=') {
+            Some((f, v)) if f.starts_with("--") => (f.to_string(), Some(v.to_string())),
+            _ => (arg, None),
+        };
+
+        match flag.as_str() {
+            "--help" | "-h" => return Ok((Command::Help, opts)),
+            "--jobs" | "-j" => {
+                opts.jobs = parse_number(&flag, value(&flag, inline, &mut args)?)?;
+                if opts.jobs == 0 {
+                    bail!("'{flag}' must be at least 1");
+                }
+                opts.build_jobs = opts.jobs;
+            }
+            "--unordered" => opts.unordered = true,
+            "--sort" => opts.sort = value(&flag, inline, &mut args)?.parse()?,
+            "--reverse" => opts.reverse = true,
+            "--strict" => opts.strict = true,
+            "--fail-on-error" => opts.fail_on_error = true,
+            "--quiet" | "-q" => {
+                opts.quiet = true;
+                opts.no_banner = true;
+            }
+            "--verbose" | "-v" => opts.verbose = true,
+            "--summary-only" => {
+                opts.summary_only = true;
+                opts.quiet = true;
+                opts.no_banner = true;
+            }
+            "--max-ok-lines" => {
+                opts.max_ok_lines = Some(parse_number(&flag, value(&flag, inline, &mut args)?)?);
+            }
+            "--no-banner" => opts.no_banner = true,
+            "--no-color" => opts.color = ColorChoice::Never,
+            "--color" => opts.color = value(&flag, inline, &mut args)?.parse()?,
+            "--strict-pkgver" => opts.strict_pkgver = true,
+            "--ignore-pkgrel" => opts.ignore_pkgrel = true,
+            "--offline" => opts.offline = true,
+            "--group-by-source" => opts.group_by_source = true,
+            "--concurrency-report" => opts.concurrency_report = true,
+            "--stats" => opts.stats = true,
+            "--no-state" => opts.no_state = true,
+            "--retry-errors" => opts.retry_errors = true,
+            "--no-cache" => opts.no_cache = true,
+            "--include-hidden" => opts.include_hidden = true,
+            "--allow-undefined-env" => opts.allow_undefined_env = true,
+            "--probe" => opts.probe = true,
+            "--lint" => opts.lint = true,
+            "--adaptive-jobs" => opts.adaptive_jobs = true,
+            "--rate-limit" => {
+                let rate: f64 = parse_number(&flag, value(&flag, inline, &mut args)?)?;
+                if !(rate > 0.0 && rate.is_finite()) {
+                    bail!("'{flag}' must be a positive number of requests per second");
+                }
+                opts.rate_limit = Some(rate);
+            }
+            "--source-type" => {
+                for kind in value(&flag, inline, &mut args)?.split(',') {
+                    opts.source_types.push(kind.trim().parse()?);
+                }
+            }
+            "--dump-remote" => opts.dump_remote = Some(value(&flag, inline, &mut args)?),
+            // Like `--sign`, only `--dump-config=NAME` takes a name
+            "--dump-config" => {
+                if inline.as_deref() == Some("") {
+                    bail!("'{flag}=' requires a package name");
+                }
+                opts.dump_config = true;
+                opts.dump_config_name = inline;
+            }
+            "--stdin" => opts.stdin = true,
+            "--pkgbuild" => opts.pkgbuild = Some(value(&flag, inline, &mut args)?.into()),
+            "--json" | "--json-lines" => {
+                opts.format = if flag == "--json" {
+                    OutputFormat::Json
+                } else {
+                    OutputFormat::JsonLines
+                };
+                opts.no_banner = true;
+            }
+            "--pretty" => opts.pretty = Some(true),
+            "--compact" => opts.pretty = Some(false),
+            "--format" => {
+                opts.format = value(&flag, inline, &mut args)?.parse()?;
+                opts.no_banner |= opts.format != OutputFormat::Text;
+            }
+            "--deadline" => {
+                let seconds: f64 = parse_number(&flag, value(&flag, inline, &mut args)?)?;
+                if !(seconds > 0.0 && seconds.is_finite()) {
+                    bail!("'{flag}' must be a positive number of seconds");
+                }
+                opts.deadline = Some(Duration::from_secs_f64(seconds));
+            }
+            "--max-errors" => {
+                let max = parse_number(&flag, value(&flag, inline, &mut args)?)?;
+                if max == 0 {
+                    bail!("'{flag}' must be at least 1");
+                }
+                opts.max_errors = Some(max);
+            }
+            "--timeout" => {
+                let seconds: f64 = parse_number(&flag, value(&flag, inline, &mut args)?)?;
+                if !(seconds > 0.0 && seconds.is_finite()) {
+                    bail!("'{flag}' must be a positive number of seconds");
+                }
+                opts.timeout = Some(Duration::from_secs_f64(seconds));
+            }
+            "--retries" => {
+                opts.retries = Some(parse_number(&flag, value(&flag, inline, &mut args)?)?);
+            }
+            "--directory" | "-C" => opts.dirs.push(value(&flag, inline, &mut args)?.into()),
+            "--packages-from" => {
+                opts.packages_from = Some(value(&flag, inline, &mut args)?.into());
+            }
+            "--cacert" => opts.cacert = Some(value(&flag, inline, &mut args)?.into()),
+            "--insecure" => opts.insecure = true,
+            "--history" => opts.history = Some(value(&flag, inline, &mut args)?.into()),
+            "--summary-json" => {
+                opts.summary_json = Some(value(&flag, inline, &mut args)?.into());
+            }
+            "--on-complete" => opts.on_complete = Some(value(&flag, inline, &mut args)?),
+            "--touch-on-update" => {
+                opts.touch_on_update = Some(value(&flag, inline, &mut args)?.into());
+            }
+            "--max-size" => {
+                opts.max_size = parse_size(&flag, value(&flag, inline, &mut args)?)?;
+                if opts.max_size == 0 {
+                    bail!("'{flag}' must be at least 1 byte");
+                }
+            }
+            "--interval" => {
+                let seconds: f64 = parse_number(&flag, value(&flag, inline, &mut args)?)?;
+                if !(seconds > 0.0 && seconds.is_finite()) {
+                    bail!("'{flag}' must be a positive number of seconds");
+                }
+                opts.interval = Duration::from_secs_f64(seconds);
+            }
+            "--exit-on-update" => opts.exit_on_update = true,
+            "--count" => {
+                opts.count = parse_number(&flag, value(&flag, inline, &mut args)?)?;
+                if opts.count == 0 {
+                    bail!("'{flag}' must be at least 1");
+                }
+            }
+            "--parallel-builds-safe" => opts.parallel_builds_safe = true,
+            "--import-keys" => opts.import_keys = true,
+            "--verify-build-deps" => opts.verify_build_deps = true,
+            "--repo-db" => opts.repo_db = Some(value(&flag, inline, &mut args)?.into()),
+            "--repo-sign-db" => opts.repo_sign_db = true,
+            "--sudo" => opts.sudo = Some(value(&flag, inline, &mut args)?.parse()?),
+            "--makepkg-bin" => {
+                let bin = value(&flag, inline, &mut args)?;
+                if bin.is_empty() {
+                    bail!("'{flag}' requires a path");
+                }
+                opts.makepkg_bin = Some(bin.into());
+            }
+            "--nocheck" => opts.nocheck = true,
+            "--holdver" => opts.holdver = true,
+            "--clean" => opts.clean = true,
+            "--cleanbuild" => opts.cleanbuild = true,
+            "--makepkg-args" => {
+                let extra = value(&flag, inline, &mut args)?;
+                if extra.trim().is_empty() {
+                    bail!("'{flag}' requires makepkg arguments");
+                }
+                opts.makepkg_args.extend(extra.split_whitespace().map(str::to_string));
+            }
+            "--tui" => opts.tui = true,
+            "--changed-since" => {
+                let git_ref = value(&flag, inline, &mut args)?;
+                if git_ref.is_empty() || git_ref.starts_with('-') {
+                    bail!("'{flag}' requires a git ref");
+                }
+                opts.changed_since = Some(git_ref);
+            }
+            // Only `--sign=KEYID` takes a key, so `--sign` can precede other arguments
+            "--metadata" => opts.metadata = true,
+            "--sign" => {
+                if inline.as_deref() == Some("") {
+                    bail!("'{flag}=' requires a key id");
+                }
+                opts.sign = true;
+                opts.sign_key = inline;
+            }
+            "--output-template" => {
+                let template = value(&flag, inline, &mut args)?;
+                output::validate_template(&template)?;
+                opts.output_template = Some(template);
+            }
+            "--config-name" => {
+                opts.config_name = value(&flag, inline, &mut args)?;
+                if opts.config_name.is_empty() || opts.config_name.contains('/') {
+                    bail!("'{flag}' must be a plain file name");
+                }
+            }
+            "--pkgrel" => opts.pkgrel_policy = Some(value(&flag, inline, &mut args)?.parse()?),
+            "--dry-run" => opts.dry_run = true,
+            "--keep" => {
+                opts.keep = parse_number(&flag, value(&flag, inline, &mut args)?)?;
+                if opts.keep == 0 {
+                    bail!("'{flag}' must be at least 1");
+                }
+            }
+            "check" if command.is_none() => command = Some(Command::Check),
+            "build" if command.is_none() => command = Some(Command::Build),
+            "update" if command.is_none() => command = Some(Command::Update),
+            "installed" if command.is_none() => command = Some(Command::Installed),
+            "prune" if command.is_none() => command = Some(Command::Prune),
+            "watch" if command.is_none() => command = Some(Command::Watch),
+            "bench" if command.is_none() => command = Some(Command::Bench),
+            "guess-remote" if command.is_none() => command = Some(Command::GuessRemote),
+            "refresh-srcinfo" if command.is_none() => command = Some(Command::RefreshSrcinfo),
+            "why" if command.is_none() => command = Some(Command::Why),
+            "completions" if command.is_none() => command = Some(Command::Completions),
+            "__complete" if command.is_none() => command = Some(Command::Complete),
+            other if other.starts_with('-') => bail!("unknown option '{other}'"),
+            _ if matches!(
+                command,
+                Some(
+                    Command::Bench
+                        | Command::GuessRemote
+                        | Command::RefreshSrcinfo
+                        | Command::Why
+                        | Command::Completions
+                )
+            ) => {
+                opts.args.push(flag)
+            }
+            other => bail!("unknown command '{other}'"),
+        }
+    }
+
+    if opts.stdin != opts.pkgbuild.is_some() {
+        bail!("'--stdin' and '--pkgbuild' must be used together");
+    }
+    if opts.stdin && opts.packages_from.is_some() {
+        bail!("'--stdin' checks one package; it can't be combined with '--packages-from'");
+    }
+    let command = command.unwrap_or(Command::Check);
+    if opts.stdin && command != Command::Check {
+        bail!("'--stdin' only applies to check");
+    }
+    if opts.probe && command != Command::Check {
+        bail!("'--probe' only applies to check");
+    }
+    if opts.lint && (command != Command::Check || opts.probe) {
+        bail!("'--lint' only applies to check, without '--probe'");
+    }
+    if opts.retry_errors && (command != Command::Check || opts.stdin) {
+        bail!("'--retry-errors' only applies to check, without '--stdin'");
+    }
+    if opts.dump_remote.is_some() && (command != Command::Check || opts.stdin || opts.probe) {
+        bail!("'--dump-remote' only applies to check, without '--stdin' or '--probe'");
+    }
+    if opts.dump_config
+        && (command != Command::Check
+            || opts.stdin
+            || opts.probe
+            || opts.dump_remote.is_some()
+            || opts.format != OutputFormat::Text)
+    {
+        bail!(
+            "'--dump-config' only applies to check, without '--stdin', '--probe', \
+             '--dump-remote' or '--json'"
+        );
+    }
+    let dumping = opts.dump_remote.is_some() || opts.dump_config;
+    if opts.unordered && opts.sort != SortOrder::Name {
+        bail!("'--sort' can't be combined with '--unordered'");
+    }
+    if opts.unordered && opts.reverse {
+        bail!("'--reverse' can't be combined with '--unordered'");
+    }
+    let filters = matches!(command, Command::Check | Command::Update | Command::Watch);
+    if !opts.source_types.is_empty() && (!filters || opts.stdin) {
+        bail!("'--source-type' only applies to check, update and watch, without '--stdin'");
+    }
+    if opts.on_complete.is_some() && (command != Command::Check || dumping) {
+        bail!("'--on-complete' only applies to check");
+    }
+    if opts.fail_on_error && (command != Command::Check || dumping) {
+        bail!("'--fail-on-error' only applies to check");
+    }
+    if opts.summary_json.is_some() && (command != Command::Check || dumping) {
+        bail!("'--summary-json' only applies to check");
+    }
+    if opts.pretty.is_some() && opts.format != OutputFormat::Json {
+        bail!("'--pretty' and '--compact' only apply to --json");
+    }
+    if opts.metadata && command != Command::Build {
+        bail!("'--metadata' only applies to build");
+    }
+    let makepkg_flags = opts.nocheck || opts.holdver || opts.clean || opts.cleanbuild;
+    if (makepkg_flags || !opts.makepkg_args.is_empty()) && command != Command::Build {
+        bail!(
+            "'--nocheck', '--holdver', '--clean', '--cleanbuild' and '--makepkg-args' only \
+             apply to build"
+        );
+    }
+    if opts.tui && command != Command::Build {
+        bail!("'--tui' only applies to build");
+    }
+    if opts.tui && opts.format != OutputFormat::Text {
+        bail!("'--tui' can't be combined with --json");
+    }
+    if opts.repo_db.is_some() && command != Command::Build {
+        bail!("'--repo-db' only applies to build");
+    }
+    if opts.repo_sign_db && opts.repo_db.is_none() {
+        bail!("'--repo-sign-db' signs the '--repo-db' database; give one");
+    }
+    if opts.dirs.len() > 1
+        && (command != Command::Check
+            || opts.stdin
+            || opts.packages_from.is_some()
+            || dumping)
+    {
+        bail!(
+            "several '-C' directories only apply to check, without '--stdin', \
+             '--packages-from', '--dump-remote' or '--dump-config'"
+        );
+    }
+    Ok((command, opts))
+}
+
+/// Take the value of a flag, either inline (`--flag=value`) or from the next argument
+fn value(
+    flag: &str,
+    inline: Option<String>,
+    args: &mut impl Iterator<Item = String>,
+) -> Result<String> {
+    match inline {
+        Some(v) => Ok(v),
+        None => args
+            .next()
+            .with_context(|| format!("'{flag}' requires a value")),
+    }
+}
+
+/// Parse a numeric flag value
+fn parse_number<T: std::str::FromStr>(flag: &str, value: String) -> Result<T> {
+    value
+        .parse()
+        .map_err(|_| anyhow::anyhow!("invalid value '{value}' for '{flag}'"))
+}
+
+/// Parse a byte size with an optional binary K/M/G suffix (e.g. `512K`, `4M`)
+fn parse_size(flag: &str, value: String) -> Result<u64> {
+    let (digits, multiplier) = match value.to_ascii_uppercase().chars().last() {
+        Some('K') => (&value[..value.len() - 1], 1 << 10),
+        Some('M') => (&value[..value.len() - 1], 1 << 20),
+        Some('G') => (&value[..value.len() - 1], 1 << 30),
+        _ => (value.as_str(), 1),
+    };
+    let number: u64 = parse_number(flag, digits.to_string())?;
+    number
+        .checked_mul(multiplier)
+        .with_context(|| format!("invalid value '{value}' for '{flag}'"))
+}
+
+pub fn print_help() {
+    let version = env!("CARGO_PKG_VERSION");
+    println!(
+        "{} {} {}",
+        "rchan".bold().cyan(),
+        version,
+        "- PKGBUILD update checker & builder".dimmed()
+    );
+    println!();
+    println!("{}", "USAGE:".bold());
+    println!("  rchan              Check PKGBUILD updates for all packages");
+    println!("  rchan build        Build all packages with makepkg");
+    println!("  rchan update       Rewrite local PKGBUILDs to the remote version");
+    println!("  rchan installed    Compare PKGBUILD versions with installed packages");
+    println!("  rchan prune        Delete old package versions from pkgs/");
+    println!("  rchan watch        Re-check periodically and print changes");
+    println!("  rchan bench URL... Measure fetch latency and success rate of URLs");
+    println!("  rchan guess-remote DIR");
+    println!("                     Propose a remote for DIR's PKGBUILD and draft its config");
+    println!("  rchan refresh-srcinfo [NAME...]");
+    println!("                     Regenerate .SRCINFO with makepkg --printsrcinfo");
+    println!("  rchan why NAME     Show how NAME's local and remote versions compare, and why");
+    println!("  rchan completions SHELL");
+    println!("                     Print the bash, zsh or fish completion script");
+    println!("  rchan --help, -h   Show this help message");
+    println!();
+    println!("{}", "OPTIONS:".bold());
+    println!("  -j, --jobs N       Check (or update) N packages concurrently (default: 4)");
+    println!("  --unordered        Print results as they complete instead of by name");
+    println!("  --sort ORDER       name (default) or status: higher config priority first,");
+    println!("                     then errors, updates and the rest");
+    println!("  --reverse          Reverse the --sort order (and build's table of build times)");
+    println!("  --strict           Treat warnings as errors in the exit status");
+    println!("  --fail-on-error    Exit 0 when updates are available, non-zero only on errors");
+    println!("  -q, --quiet        Only print updates, errors and warnings (implies --no-banner)");
+    println!("  --summary-only     Only print the summary line (implies --quiet)");
+    println!("  -v, --verbose      Also print notices, e.g. when a remote URL was rewritten");
+    println!("  --max-ok-lines N   Print at most N up-to-date lines, then how many more there are");
+    println!("  --no-banner        Don't print the header before scanning/building");
+    println!("  --color WHEN       Color output: auto (default; terminals only, unless NO_COLOR");
+    println!("                     is set), always or never");
+    println!("  --no-color         Same as --color never");
+    println!("  --strict-pkgver    Error when a PKGBUILD has conflicting pkgver= lines");
+    println!("  --ignore-pkgrel    Only report upstream (epoch/pkgver) changes, not pkgrel bumps");
+    println!("  --config-name NAME Per-package config file name (default: rchan.yaml)");
+    println!("  --include-hidden   Also look for packages in directories starting with '.'");
+    println!("  --allow-undefined-env");
+    println!("                     Leave ${{VAR}} in configs as is when VAR is unset");
+    println!("  -C, --directory DIR");
+    println!("                     Run in DIR; check scans all of them when given repeatedly");
+    println!("  --offline          Never touch the network; only read local remote sources");
+    println!("  --group-by-source  Break the summary down by source (AUR, GitHub, ...)");
+    println!("  --adaptive-jobs    Ramp request concurrency up while requests are fast and back");
+    println!("                     off on timeouts and 5xx, instead of a fixed --jobs");
+    println!("  --rate-limit N     Make at most N requests per second, across all jobs");
+    println!("  --concurrency-report");
+    println!("                     Show how many requests ran in parallel, to tune --jobs");
+    println!("  --stats            Show request statistics, such as how long --rate-limit held");
+    println!("                     requests back");
+    println!("  --deadline SECS    Abort remaining checks once the run takes SECS seconds");
+    println!("  --max-errors N     Abort remaining checks once N packages have failed");
+    println!("  --packages-from F  Check only the package directories listed in file F");
+    println!("  --source-type KIND Only check packages whose remote is of KIND (aur, github,");
+    println!("                     gitlab, url, local, ssh, pypi, crates, git); repeatable");
+    println!("  --probe            Only check that each remote can be fetched and parsed");
+    println!("  --lint             Also report version hygiene issues in local PKGBUILDs");
+    println!("  --dump-remote NAME Print package NAME's remote as fetched, to debug parsing");
+    println!("  --dump-config[=NAME]");
+    println!("                     Print the configuration each package (or NAME) is checked");
+    println!("                     with, and where each setting comes from");
+    println!("  --stdin --pkgbuild PATH");
+    println!("                     Check one package: rchan.yaml from stdin, local PKGBUILD PATH");
+    println!("  --history FILE     Append this run's summary to FILE as a JSON line");
+    println!("  --summary-json FILE");
+    println!("                     Write the summary counts and exit status to FILE as JSON");
+    println!("  --on-complete CMD  Run CMD with sh after the check, the JSON report on stdin");
+    println!("  --touch-on-update FILE");
+    println!("                     Create FILE on updates or errors, remove it when all is clean");
+    println!("  --no-state         Don't record remote versions in .rchan-state.json");
+    println!("  --retry-errors     Only check packages that failed in the last run");
+    println!("  --no-cache         Fetch every remote in full; don't use .rchan-cache.json");
+    println!("  --timeout SECS     Per-request timeout (default: 30)");
+    println!("  --retries N        Retry failed requests N times (default: 0)");
+    println!("  --max-size SIZE    Largest remote PKGBUILD to download, e.g. 512K (default: 4M)");
+    println!("  --cacert FILE      Also trust the CA certificates in PEM FILE for HTTPS");
+    println!("  --insecure         Skip HTTPS certificate checks (unsafe; lab use only)");
+    println!("  --output-template T");
+    println!("                     Format package lines with T, e.g. '{{name}} {{remote}}'.");
+    println!("                     Placeholders: {{name}} {{status}} {{local}} {{remote}}");
+    println!("                     {{source}} {{epoch}} {{message}}; {{{{ }}}} are literal");
+    println!("  --json             Print one JSON document when the scan finishes");
+    println!("  --json-lines       Print one JSON object per package as it completes");
+    println!("  --pretty, --compact");
+    println!("                     Indent --json output or not (default: only on a terminal)");
+    println!("  --format FORMAT    text (default), json, json-lines or markdown (a table of");
+    println!("                     results; with --quiet, only updates and errors)");
+    println!();
+    println!("{}", "BUILD OPTIONS:".bold());
+    println!("  -j, --jobs N       Build N packages concurrently (default: 1)");
+    println!("  --parallel-builds-safe");
+    println!("                     Build in parallel even if makepkg's SRCDEST/PKGDEST are shared");
+    println!("  --sign[=KEYID]     Sign packages with gpg (default key: $GPGKEY, else gpg's)");
+    println!("  --metadata         Write <package>.json with version, date and SHA-256");
+    println!("  --import-keys      Fetch missing validpgpkeys from the keyserver before building");
+    println!("  --repo-db FILE     Add built packages to a pacman repository with repo-add");
+    println!("  --repo-sign-db     Sign the repository database too (key as for --sign)");
+    println!("  --verify-build-deps");
+    println!("                     Check that every depends/makedepends entry is installed, in");
+    println!("                     a repository or built in the batch before building");
+    println!("  --sudo TOOL        Install dependencies with sudo or doas, failing fast if it");
+    println!("                     needs a password; none builds without installing them");
+    println!("  --makepkg-bin PATH Run this makepkg or wrapper (default: $RCHAN_MAKEPKG, then");
+    println!("                     makepkg_bin in the global config, then makepkg)");
+    println!("  --nocheck          Skip check() (makepkg --nocheck)");
+    println!("  --holdver          Keep VCS sources at their current revision (makepkg --holdver)");
+    println!("  --clean            Remove work files after building (makepkg --clean)");
+    println!("  --cleanbuild       Remove $srcdir before building (makepkg --cleanbuild)");
+    println!("  --makepkg-args ARGS");
+    println!("                     Pass more whitespace-separated arguments to makepkg, after");
+    println!("                     the flags above; repeatable");
+    println!("  --tui              Show a live view of the builds and their logs (plain output");
+    println!("                     when not on a terminal)");
+    println!("  --changed-since REF");
+    println!("                     Only build packages with files changed since git REF");
+    println!();
+    println!("{}", "UPDATE OPTIONS:".bold());
+    println!("  --pkgrel POLICY    remote, reset or keep (default: reset on a pkgver");
+    println!("                     change, remote on a pkgrel-only change)");
+    println!("  --dry-run          Show what would change without writing files");
+    println!();
+    println!("{}", "PRUNE OPTIONS:".bold());
+    println!("  --keep N           Keep the N newest versions of each package (default: 1)");
+    println!("  --dry-run          Show what would be deleted without deleting");
+    println!();
+    println!("{}", "WATCH OPTIONS:".bold());
+    println!("  --interval SECS    Time between checks (default: 600)");
+    println!("  --exit-on-update   Exit with status 0 as soon as a package gets a new update");
+    println!();
+    println!("{}", "BENCH OPTIONS:".bold());
+    println!("  --count N          Requests per URL (default: 5); --timeout/--retries apply");
+    println!();
+    println!("{}", "GUESS-REMOTE OPTIONS:".bold());
+    println!("  --dry-run          Print the draft config without offering to write it");
+    println!("  --offline          List candidates without checking that they can be fetched");
+    println!();
+    println!("{}", "EXIT STATUS:".bold());
+    println!("  0  All packages up to date");
+    println!("  1  Fatal error (bad arguments, unreadable directory, ...)");
+    println!("  2  Updates available (0 with --fail-on-error)");
+    println!("  3  Package errors (or warnings with --strict)");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_defaults() {
+        let (command, opts) = parse(args(&[])).unwrap();
+        assert_eq!(command, Command::Check);
+        assert_eq!(opts.jobs, 4);
+        assert_eq!(opts.build_jobs, 1);
+        assert!(!opts.unordered);
+        assert_eq!(opts.config_name, "rchan.yaml");
+    }
+
+    #[test]
+    fn test_parse_jobs_forms() {
+        let (_, opts) = parse(args(&["--jobs", "8"])).unwrap();
+        assert_eq!(opts.jobs, 8);
+        assert_eq!(opts.build_jobs, 8);
+        let (_, opts) = parse(args(&["--jobs=2", "--unordered"])).unwrap();
+        assert_eq!(opts.jobs, 2);
+        assert!(opts.unordered);
+        assert!(parse(args(&["-j", "0"])).is_err());
+        assert!(parse(args(&["--jobs"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_config_name() {
+        let (_, opts) = parse(args(&["--config-name", ".rchan.yaml"])).unwrap();
+        assert_eq!(opts.config_name, ".rchan.yaml");
+        assert!(parse(args(&["--config-name=sub/rchan.yaml"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_update() {
+        let (command, opts) = parse(args(&["update", "--pkgrel", "keep", "--dry-run"])).unwrap();
+        assert_eq!(command, Command::Update);
+        assert_eq!(opts.pkgrel_policy, Some(PkgrelPolicy::Keep));
+        assert!(opts.dry_run);
+        assert!(parse(args(&["update", "--pkgrel=bump"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_max_size() {
+        assert_eq!(parse(args(&[])).unwrap().1.max_size, 4 << 20);
+        assert_eq!(parse(args(&["--max-size", "512k"])).unwrap().1.max_size, 512 << 10);
+        assert_eq!(parse(args(&["--max-size=1000"])).unwrap().1.max_size, 1000);
+        assert!(parse(args(&["--max-size", "M"])).is_err());
+        assert!(parse(args(&["--max-size", "0"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_watch() {
+        let (command, opts) = parse(args(&["watch", "--interval=30", "--exit-on-update"])).unwrap();
+        assert_eq!(command, Command::Watch);
+        assert_eq!(opts.interval, Duration::from_secs(30));
+        assert!(opts.exit_on_update);
+        assert!(parse(args(&["watch", "--interval", "0"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_output_template() {
+        let (_, opts) = parse(args(&["--output-template", "{name} {remote}"])).unwrap();
+        assert_eq!(opts.output_template.as_deref(), Some("{name} {remote}"));
+        assert!(parse(args(&["--output-template={version}"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_bench() {
+        let (command, opts) =
+            parse(args(&["bench", "https://a/PKGBUILD", "--count", "3", "https://b/PKGBUILD"]))
+                .unwrap();
+        assert_eq!(command, Command::Bench);
+        assert_eq!(opts.count, 3);
+        assert_eq!(opts.args, ["https://a/PKGBUILD", "https://b/PKGBUILD"]);
+        assert!(parse(args(&["https://a/PKGBUILD"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_guess_remote() {
+        let (command, opts) = parse(args(&["guess-remote", "foo", "--dry-run"])).unwrap();
+        assert_eq!(command, Command::GuessRemote);
+        assert_eq!(opts.args, ["foo"]);
+        assert!(opts.dry_run);
+    }
+
+    #[test]
+    fn test_parse_refresh_srcinfo() {
+        let (command, opts) = parse(args(&["refresh-srcinfo", "foo-git", "bar-git"])).unwrap();
+        assert_eq!(command, Command::RefreshSrcinfo);
+        assert_eq!(opts.args, ["foo-git", "bar-git"]);
+    }
+
+    #[test]
+    fn test_parse_stdin() {
+        let (command, opts) = parse(args(&["check", "--stdin", "--pkgbuild", "PKGBUILD"])).unwrap();
+        assert_eq!(command, Command::Check);
+        assert!(opts.stdin);
+        assert_eq!(opts.pkgbuild.as_deref(), Some(std::path::Path::new("PKGBUILD")));
+        assert!(parse(args(&["check", "--stdin"])).is_err());
+        assert!(parse(args(&["--pkgbuild", "PKGBUILD"])).is_err());
+        assert!(parse(args(&["build", "--stdin", "--pkgbuild", "PKGBUILD"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_color() {
+        assert_eq!(parse(args(&[])).unwrap().1.color, ColorChoice::Auto);
+        let (_, opts) = parse(args(&["--color", "always"])).unwrap();
+        assert_eq!(opts.color, ColorChoice::Always);
+        let (_, opts) = parse(args(&["--color=always", "--no-color"])).unwrap();
+        assert_eq!(opts.color, ColorChoice::Never);
+        assert!(parse(args(&["--color", "sometimes"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_format() {
+        let (_, opts) = parse(args(&["--format", "markdown"])).unwrap();
+        assert_eq!(opts.format, OutputFormat::Markdown);
+        assert!(opts.no_banner);
+        let (_, opts) = parse(args(&["--json", "--format=text"])).unwrap();
+        assert_eq!(opts.format, OutputFormat::Text);
+        assert!(parse(args(&["--format", "yaml"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_source_type() {
+        let flags = ["--source-type", "url", "--source-type=github,local"];
+        let (_, opts) = parse(args(&flags)).unwrap();
+        assert_eq!(opts.source_types, [SourceKind::Url, SourceKind::GitHub, SourceKind::Local]);
+        assert!(parse(args(&["--source-type", "svn"])).is_err());
+        assert!(parse(args(&["build", "--source-type", "aur"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_sort() {
+        assert_eq!(parse(args(&[])).unwrap().1.sort, SortOrder::Name);
+        let (_, opts) = parse(args(&["--sort", "status"])).unwrap();
+        assert_eq!(opts.sort, SortOrder::Status);
+        assert!(parse(args(&["--sort=size"])).is_err());
+        assert!(parse(args(&["--sort=status", "--unordered"])).is_err());
+        let (_, opts) = parse(args(&["--sort", "status", "--reverse"])).unwrap();
+        assert!(opts.reverse);
+        assert!(parse(args(&["--reverse", "--unordered"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_makepkg_flags() {
+        let (_, opts) = parse(args(&[
+            "build",
+            "--nocheck",
+            "--cleanbuild",
+            "--makepkg-args",
+            "--skippgpcheck  --log",
+            "--makepkg-args=--nosign",
+        ]))
+        .unwrap();
+        assert!(opts.nocheck && opts.cleanbuild);
+        assert!(!opts.holdver && !opts.clean);
+        assert_eq!(opts.makepkg_args, ["--skippgpcheck", "--log", "--nosign"]);
+        assert!(parse(args(&["build", "--makepkg-args", " "])).is_err());
+        assert!(parse(args(&["--holdver"])).is_err());
+        assert!(parse(args(&["update", "--makepkg-args", "--log"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_tui() {
+        let (_, opts) = parse(args(&["build", "--tui", "-j", "4"])).unwrap();
+        assert!(opts.tui);
+        assert!(parse(args(&["--tui"])).is_err());
+        assert!(parse(args(&["build", "--tui", "--json"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_sudo() {
+        let (_, opts) = parse(args(&["build", "--sudo", "doas"])).unwrap();
+        assert_eq!(opts.sudo, Some(Escalation::Doas));
+        let (_, opts) = parse(args(&["build", "--sudo=none"])).unwrap();
+        assert_eq!(opts.sudo, Some(Escalation::None));
+        assert!(parse(args(&["build", "--sudo", "su"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_sign() {
+        let (_, opts) = parse(args(&["build", "--sign"])).unwrap();
+        assert!(opts.sign);
+        assert_eq!(opts.sign_key, None);
+        let (_, opts) = parse(args(&["build", "--sign=0xDEADBEEF"])).unwrap();
+        assert_eq!(opts.sign_key.as_deref(), Some("0xDEADBEEF"));
+        assert!(parse(args(&["build", "--sign="])).is_err());
+    }
+
+    #[test]
+    fn test_parse_repo_db() {
+        let (_, opts) =
+            parse(args(&["build", "--repo-db", "pkgs/custom.db.tar.zst", "--repo-sign-db"]))
+                .unwrap();
+        assert_eq!(opts.repo_db, Some(PathBuf::from("pkgs/custom.db.tar.zst")));
+        assert!(opts.repo_sign_db);
+        assert!(parse(args(&["build", "--repo-sign-db"])).is_err());
+        assert!(parse(args(&["check", "--repo-db", "custom.db.tar.zst"])).is_err());
+        assert!(parse(args(&["build", "--metadata"])).unwrap().1.metadata);
+        assert!(parse(args(&["check", "--metadata"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_touch_on_update() {
+        let (_, opts) = parse(args(&["--touch-on-update=/run/rchan/attention"])).unwrap();
+        assert_eq!(opts.touch_on_update, Some(PathBuf::from("/run/rchan/attention")));
+        assert!(parse(args(&["--touch-on-update"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_rate_limit() {
+        assert_eq!(parse(args(&["--rate-limit", "5"])).unwrap().1.rate_limit, Some(5.0));
+        assert_eq!(parse(args(&["--rate-limit=0.5"])).unwrap().1.rate_limit, Some(0.5));
+        assert!(parse(args(&["--rate-limit", "0"])).is_err());
+        assert!(parse(args(&["--rate-limit", "inf"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_verbose() {
+        assert!(parse(args(&["-v"])).unwrap().1.verbose);
+        assert!(parse(args(&["--verbose"])).unwrap().1.verbose);
+        assert!(!parse(args(&["--quiet"])).unwrap().1.verbose);
+    }
+
+    #[test]
+    fn test_parse_stats() {
+        assert!(parse(args(&["--stats"])).unwrap().1.stats);
+        assert!(!parse(args(&["--concurrency-report"])).unwrap().1.stats);
+    }
+
+    #[test]
+    fn test_parse_fail_on_error() {
+        let (_, opts) = parse(args(&["--fail-on-error", "--strict"])).unwrap();
+        assert!(opts.fail_on_error);
+        assert!(opts.strict);
+        assert!(parse(args(&["build", "--fail-on-error"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_dump_config() {
+        let (_, opts) = parse(args(&["--dump-config", "--timeout", "5"])).unwrap();
+        assert!(opts.dump_config);
+        assert_eq!(opts.dump_config_name, None);
+        let (_, opts) = parse(args(&["--dump-config=foo"])).unwrap();
+        assert_eq!(opts.dump_config_name.as_deref(), Some("foo"));
+        assert!(parse(args(&["--dump-config="])).is_err());
+        assert!(parse(args(&["--dump-config", "--json"])).is_err());
+        assert!(parse(args(&["build", "--dump-config"])).is_err());
+        assert!(parse(args(&["--dump-config", "--fail-on-error"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_why() {
+        let (command, opts) = parse(args(&["why", "foo", "--ignore-pkgrel"])).unwrap();
+        assert_eq!(command, Command::Why);
+        assert_eq!(opts.args, ["foo"]);
+        assert!(opts.ignore_pkgrel);
+    }
+
+    #[test]
+    fn test_parse_completions() {
+        let (command, opts) = parse(args(&["completions", "zsh"])).unwrap();
+        assert_eq!(command, Command::Completions);
+        assert_eq!(opts.args, ["zsh"]);
+        let (command, _) = parse(args(&["-C", "repo", "__complete"])).unwrap();
+        assert_eq!(command, Command::Complete);
+        assert!(parse(args(&["__complete", "foo"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_pretty() {
+        let (_, opts) = parse(args(&["--json", "--compact"])).unwrap();
+        assert_eq!(opts.pretty, Some(false));
+        let (_, opts) = parse(args(&["build", "--pretty", "--json"])).unwrap();
+        assert!(opts.pretty_json());
+        assert!(parse(args(&["--json-lines", "--compact"])).is_err());
+        assert!(parse(args(&["--pretty"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_on_complete() {
+        let (_, opts) = parse(args(&["--on-complete", "jq .summary > last.json"])).unwrap();
+        assert_eq!(opts.on_complete.as_deref(), Some("jq .summary > last.json"));
+        assert!(parse(args(&["build", "--on-complete", "true"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_summary_json() {
+        let (_, opts) = parse(args(&["--summary-json=badge/summary.json"])).unwrap();
+        assert_eq!(opts.summary_json, Some(PathBuf::from("badge/summary.json")));
+        assert!(parse(args(&["prune", "--summary-json", "s.json"])).is_err());
+    }
+
+    #[test]
+    fn test_parse_unknown() {
+        assert!(parse(args(&["frobnicate"])).is_err());
+        assert!(parse(args(&["--frobnicate"])).is_err());
+    }
+}
@@ -0,0 +1,126 @@
+use std::path::Path;
+use std::process::ExitCode;
+
+use anyhow::{Result, bail};
+
+use crate::builder;
+use crate::cli::Options;
+
+/// Subcommands offered as the first word
+const COMMANDS: &[&str] = &[
+    "check",
+    "build",
+    "update",
+    "installed",
+    "prune",
+    "watch",
+    "bench",
+    "guess-remote",
+    "refresh-srcinfo",
+    "why",
+    "completions",
+];
+
+/// Package names complete after these subcommands and after `--dump-remote`.
+/// `-C DIR` on the command line is passed on, so the names come from DIR.
+const BASH: &str = r#"_rchan() {
+    local cur=${COMP_WORDS[COMP_CWORD]} prev=${COMP_WORDS[COMP_CWORD-1]}
+    local i cmd dir=()
+    for ((i = 1; i < COMP_CWORD; i++)); do
+        case ${COMP_WORDS[i]} in
+            -C|--directory) dir=(-C "${COMP_WORDS[i+1]}"); ((i++)) ;;
+            -*) ;;
+            *) [[ -z $cmd ]] && cmd=${COMP_WORDS[i]} ;;
+        esac
+    done
+    if [[ $cur == -* ]]; then
+        return
+    elif [[ $prev == --dump-remote || $cmd == guess-remote || $cmd == refresh-srcinfo
+            || $cmd == why ]]; then
+        COMPREPLY=($(compgen -W "$(rchan "${dir[@]}" __complete 2>/dev/null)" -- "$cur"))
+    elif [[ -z $cmd ]]; then
+        COMPREPLY=($(compgen -W "@COMMANDS@" -- "$cur"))
+    fi
+}
+complete -o default -F _rchan rchan
+"#;
+
+const ZSH: &str = r#"#compdef rchan
+_rchan() {
+    local -a dir
+    local i=${words[(I)(-C|--directory)]}
+    (( i > 0 && i < CURRENT - 1 )) && dir=(-C ${words[i+1]})
+    if [[ ${words[CURRENT-1]} == --dump-remote ]] \
+        || (( ${words[(I)(guess-remote|refresh-srcinfo|why)]} )); then
+        compadd -- ${(f)"$(rchan $dir __complete 2>/dev/null)"}
+    elif (( CURRENT == 2 )) && [[ ${words[CURRENT]} != -* ]]; then
+        compadd -- @COMMANDS@
+    else
+        _files
+    fi
+}
+compdef _rchan rchan
+"#;
+
+const FISH: &str = r#"function __rchan_packages
+    set -l tokens (commandline -opc)
+    set -l dir
+    if set -l i (contains -i -- -C $tokens; or contains -i -- --directory $tokens)
+        and test (count $tokens) -gt $i
+        set dir -C $tokens[(math $i + 1)]
+    end
+    rchan $dir __complete 2>/dev/null
+end
+complete -c rchan -n __fish_use_subcommand -f -a "@COMMANDS@"
+complete -c rchan -n "__fish_seen_subcommand_from guess-remote refresh-srcinfo why" \
+    -f -a "(__rchan_packages)"
+complete -c rchan -l dump-remote -x -a "(__rchan_packages)"
+complete -c rchan -s C -l directory -x -a "(__fish_complete_directories)"
+"#;
+
+/// Run `rchan completions <shell>`: print the completion script for bash,
+/// zsh or fish
+pub fn run_completions(opts: &Options) -> Result<ExitCode> {
+    let [shell] = opts.args.as_slice() else {
+        bail!("usage: rchan completions bash|zsh|fish");
+    };
+    print!("{}", script(shell)?);
+    Ok(ExitCode::SUCCESS)
+}
+
+/// The completion script for `shell`, with the subcommands filled in
+fn script(shell: &str) -> Result<String> {
+    let template = match shell {
+        "bash" => BASH,
+        "zsh" => ZSH,
+        "fish" => FISH,
+        other => bail!("unsupported shell '{other}' (expected bash, zsh or fish)"),
+    };
+    Ok(template.replace("@COMMANDS@", &COMMANDS.join(" ")))
+}
+
+/// Run the hidden `rchan __complete`: print the package directory names under
+/// `base`, one per line, for the completion scripts. Errors print nothing, as
+/// a completion has nowhere to show them.
+pub fn run_complete(base: &Path, opts: &Options) -> Result<ExitCode> {
+    for entry in builder::pkgbuild_dirs(base, opts.include_hidden).unwrap_or_default() {
+        println!("{}", entry.file_name().to_string_lossy());
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_script() {
+        for shell in ["bash", "zsh", "fish"] {
+            let script = script(shell).unwrap();
+            assert!(!script.contains("@COMMANDS@"), "{shell}");
+            assert!(script.contains("check build update"), "{shell}");
+            assert!(script.contains(" __complete 2>/dev/null"), "{shell}");
+        }
+        assert!(script("powershell").is_err());
+    }
+}
@@ -1,17 +1,709 @@
-use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{Context, bail};
+use regex::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+
+use crate::fetch::{RequestPolicy, SourceKind};
+
+/// A `${VAR}` environment variable reference in a config value
+pub const ENV_VAR: &str = r"\$\{([A-Za-z_][A-Za-z0-9_]*)\}";
 
 /// rchan.yaml configuration file structure
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, Serialize)]
 pub struct RchanConfig {
-    /// URL of the remote PKGBUILD
-    pub remote_pkgbuild: String,
+    /// URL of the remote PKGBUILD.
+    /// May also be a `file://` URL or a local path (relative to the package
+    /// directory).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_pkgbuild: Option<String>,
+    /// PyPI project whose latest release is the upstream pkgver
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pypi: Option<String>,
+    /// crates.io crate whose latest stable release is the upstream pkgver
+    #[serde(default, rename = "crate", skip_serializing_if = "Option::is_none")]
+    pub crate_name: Option<String>,
+    /// git repository whose newest version tag is the upstream pkgver, read
+    /// with `git ls-remote` (any URL git accepts)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub git_tags: Option<String>,
+    /// Release tarball with the PKGBUILD inside
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_tarball: Option<RemoteTarball>,
+    /// File holding nothing but the upstream version, e.g. a `VERSION` file,
+    /// as any `remote_pkgbuild` source
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_version_file: Option<String>,
+    /// Per-attempt timeout in seconds for this package's remote
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<f64>,
+    /// Retries for this package's remote
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retries: Option<u32>,
+    /// `Accept` header for this package's remote, for servers that only
+    /// hand out the raw file to a specific one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub accept: Option<String>,
+    /// Branch, tag or commit to read a raw GitHub/GitLab `remote_pkgbuild`
+    /// from, replacing the one in the URL
+    #[serde(default, rename = "ref", skip_serializing_if = "Option::is_none")]
+    pub git_ref: Option<String>,
+    /// Local PKGBUILD file checked against the remote, e.g. `PKGBUILD.foo`
+    /// for a variant (default `PKGBUILD`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pkgbuild: Option<String>,
+    /// Variable holding the authoritative version in both PKGBUILDs, read
+    /// (and rewritten by `update`) in place of `pkgver`, e.g. `_realver`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pkgver_var: Option<String>,
+    /// Tag shown before the package's line, e.g. `critical` for `[critical]`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// Importance; higher comes first with `--sort status`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<u8>,
+    /// Keys rchan doesn't know. `x-*` keys (e.g. `x-owner`) are user
+    /// metadata and ignored; anything else is likely a typo.
+    #[serde(flatten)]
+    pub extra: HashMap<String, Value>,
+    /// `${VAR}` references to unset environment variables, left as they
+    /// were; checking a package with any is an error unless
+    /// `--allow-undefined-env` is given
+    #[serde(skip)]
+    pub undefined_env: Vec<String>,
+    /// The files the config was read from, itself first and then each
+    /// `include` in turn, with the keys each one sets as written (before
+    /// `${VAR}` expansion). A key comes from the first file that has it.
+    #[serde(skip)]
+    pub layers: Vec<(PathBuf, Value)>,
 }
 
 impl RchanConfig {
-    /// Read and parse rchan.yaml from a file path
-    pub fn from_file(path: &std::path::Path) -> anyhow::Result<Self> {
-        let content = std::fs::read_to_string(path)?;
-        let config: RchanConfig = serde_yaml::from_str(&content)?;
+    /// Read and parse rchan.yaml from a file path.
+    ///
+    /// An `include: <path>` key (relative to the including file) pulls in
+    /// fields from another YAML file; keys in the including file win.
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let mut layers = Vec::new();
+        let value = load_with_includes(path, &mut Vec::new(), &mut layers)?;
+        Self::from_value(value, layers)
+    }
+
+    /// Parse an rchan.yaml document that isn't a file (`check --stdin`);
+    /// an `include` is relative to `dir`
+    pub fn from_yaml(content: &str, dir: &Path) -> anyhow::Result<Self> {
+        let value: Value = serde_yaml::from_str(content)?;
+        let mut layers = Vec::new();
+        let value = resolve_include(value, dir, "config", &mut Vec::new(), &mut layers)?;
+        Self::from_value(value, layers)
+    }
+
+    fn from_value(mut value: Value, layers: Vec<(PathBuf, Value)>) -> anyhow::Result<Self> {
+        let mut undefined_env = Vec::new();
+        expand_env(&mut value, &mut undefined_env);
+        let mut config: RchanConfig = serde_yaml::from_value(value)?;
+        config.undefined_env = undefined_env;
+        config.layers = layers;
+        let remote = config.remote()?;
+        if let Some(timeout) = config.timeout {
+            validate_timeout(timeout)?;
+        }
+        if let Some(tarball) = &config.remote_tarball {
+            // The URL and path are joined with `#` in the fetch location
+            if tarball.url.contains('#') {
+                bail!("remote_tarball url must not contain '#'");
+            }
+            if tarball.path.is_empty() || tarball.path.starts_with('/') {
+                bail!("remote_tarball path must be relative, not '{}'", tarball.path);
+            }
+        }
+        if let Some(accept) = &config.accept
+            && (accept.trim().is_empty() || !accept.chars().all(|c| (' '..='~').contains(&c)))
+        {
+            bail!("accept must be a non-empty header value of printable ASCII, not '{accept}'");
+        }
+        if config.git_ref.is_some() {
+            match remote {
+                // `git+` sources list the tags of the whole repository,
+                // which no branch narrows down
+                Remote::GitTags(_) => {
+                    bail!("ref doesn't apply to git_tags, as tags aren't per branch")
+                }
+                Remote::Pkgbuild(url) if url.starts_with("git+") => {
+                    bail!("ref doesn't apply to git+ sources, as tags aren't per branch")
+                }
+                Remote::Pkgbuild(_) => {}
+                _ => bail!("ref only applies to remote_pkgbuild"),
+            }
+        }
+        if let Some(name) = &config.pkgbuild
+            && (name.is_empty() || name.contains('/') || name == "." || name == "..")
+        {
+            bail!("pkgbuild must be a file name in the package directory, not '{name}'");
+        }
+        // Names go into the API URL path as is
+        if let Some(project) = &config.pypi {
+            let valid = project.starts_with(|c: char| c.is_ascii_alphanumeric())
+                && project.ends_with(|c: char| c.is_ascii_alphanumeric())
+                && project.chars().all(|c| c.is_ascii_alphanumeric() || "._-".contains(c));
+            if !valid {
+                bail!("pypi must be a PyPI project name, not '{project}'");
+            }
+        }
+        if let Some(name) = &config.crate_name {
+            let valid = name.starts_with(|c: char| c.is_ascii_alphabetic())
+                && name.len() <= 64
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+            if !valid {
+                bail!("crate must be a crates.io crate name, not '{name}'");
+            }
+        }
+        if let Some(var) = &config.pkgver_var {
+            let mut chars = var.chars();
+            let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+                && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if !valid {
+                bail!("pkgver_var must be a shell variable name, not '{var}'");
+            }
+        }
+        if config.label.as_deref().is_some_and(|label| label.trim().is_empty()) {
+            bail!("label must not be empty");
+        }
+        Ok(config)
+    }
+
+    /// `default` with this package's `timeout`/`retries`/`accept` overrides
+    /// applied
+    pub fn request_policy<'a>(&'a self, default: RequestPolicy<'a>) -> RequestPolicy<'a> {
+        RequestPolicy {
+            timeout: self.timeout.map_or(default.timeout, Duration::from_secs_f64),
+            retries: self.retries.unwrap_or(default.retries),
+            accept: self.accept.as_deref().or(default.accept),
+        }
+    }
+
+    /// The configured upstream; exactly one source key must be set
+    pub fn remote(&self) -> anyhow::Result<Remote<'_>> {
+        let remotes = [
+            self.remote_pkgbuild.as_deref().map(Remote::Pkgbuild),
+            self.pypi.as_deref().map(Remote::PyPi),
+            self.crate_name.as_deref().map(Remote::Crate),
+            self.git_tags.as_deref().map(Remote::GitTags),
+            self.remote_tarball.as_ref().map(Remote::Tarball),
+            self.remote_version_file.as_deref().map(Remote::VersionFile),
+        ];
+        match remotes.into_iter().flatten().collect::<Vec<_>>()[..] {
+            [remote] => Ok(remote),
+            _ => bail!(
+                "exactly one of remote_pkgbuild, remote_tarball, remote_version_file, pypi, \
+                 crate or git_tags must be set"
+            ),
+        }
+    }
+
+    /// Unknown keys that are not `x-` prefixed custom metadata, sorted
+    pub fn unknown_keys(&self) -> Vec<&str> {
+        let mut keys: Vec<&str> = self
+            .extra
+            .keys()
+            .map(String::as_str)
+            .filter(|k| !k.starts_with("x-"))
+            .collect();
+        keys.sort();
+        keys
+    }
+}
+
+/// User-wide settings from `$XDG_CONFIG_HOME/rchan/config.yaml`
+/// (`~/.config/rchan/config.yaml`). The command line takes precedence.
+#[derive(Debug, Default, Deserialize)]
+pub struct GlobalConfig {
+    /// Per-attempt timeout in seconds
+    pub timeout: Option<f64>,
+    /// Retries after a connection error, timeout or 5xx response
+    pub retries: Option<u32>,
+    /// Extra trusted root certificates (PEM), relative to the config file
+    pub cacert: Option<PathBuf>,
+    /// Hosts remotes may point at; `*.example.org` allows its subdomains
+    pub allowed_hosts: Option<Vec<String>>,
+    /// makepkg (or a wrapper) `build` runs: a command name looked up in
+    /// PATH, or a path relative to the config file
+    pub makepkg_bin: Option<PathBuf>,
+}
+
+impl GlobalConfig {
+    /// Location of the global config, if a config directory is known
+    pub fn path() -> Option<PathBuf> {
+        let config_home = std::env::var_os("XDG_CONFIG_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+        Some(config_home.join("rchan").join("config.yaml"))
+    }
+
+    /// Load the global config; a missing file means defaults
+    pub fn load() -> anyhow::Result<Self> {
+        match Self::path() {
+            Some(path) if path.is_file() => Self::from_file(&path),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let mut config: GlobalConfig = serde_yaml::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+        if let Some(timeout) = config.timeout {
+            validate_timeout(timeout).with_context(|| format!("In {}", path.display()))?;
+        }
+        for pattern in config.allowed_hosts.iter().flatten() {
+            validate_host_pattern(pattern).with_context(|| format!("In {}", path.display()))?;
+        }
+        if let (Some(cacert), Some(dir)) = (&config.cacert, path.parent()) {
+            config.cacert = Some(dir.join(cacert));
+        }
+        if let (Some(bin), Some(dir)) = (&config.makepkg_bin, path.parent())
+            && bin.components().count() > 1
+        {
+            config.makepkg_bin = Some(dir.join(bin));
+        }
         Ok(config)
     }
 }
+
+/// Reject timeouts that are not a positive number of seconds
+fn validate_timeout(seconds: f64) -> anyhow::Result<()> {
+    if !(seconds > 0.0 && seconds.is_finite()) {
+        bail!("timeout must be a positive number of seconds");
+    }
+    Ok(())
+}
+
+/// Reject `allowed_hosts` entries that are not a host name or `*.domain`
+fn validate_host_pattern(pattern: &str) -> anyhow::Result<()> {
+    let host = pattern.strip_prefix("*.").unwrap_or(pattern);
+    let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '.';
+    if host.is_empty() || !host.chars().all(valid) {
+        bail!("allowed_hosts entry '{pattern}' must be a host name or '*.domain'");
+    }
+    Ok(())
+}
+
+/// Where a package's upstream version comes from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Remote<'a> {
+    /// A PKGBUILD to parse (URL or local path)
+    Pkgbuild(&'a str),
+    /// The latest release of a PyPI project
+    PyPi(&'a str),
+    /// The latest stable release of a crates.io crate
+    Crate(&'a str),
+    /// The newest version tag of a git repository
+    GitTags(&'a str),
+    /// A PKGBUILD inside a tarball
+    Tarball(&'a RemoteTarball),
+    /// A file holding just the version (URL or local path)
+    VersionFile(&'a str),
+}
+
+/// `remote_tarball`: where the tarball is and where the PKGBUILD is in it
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RemoteTarball {
+    /// The tarball (tar.gz, tar.zst or tar), as any `remote_pkgbuild` source
+    pub url: String,
+    /// Path of the PKGBUILD in the tarball; `*` matches any one directory
+    /// name, e.g. `*/PKGBUILD`
+    pub path: String,
+}
+
+impl Remote<'_> {
+    pub fn kind(&self) -> SourceKind {
+        match self {
+            Remote::Pkgbuild(url) => SourceKind::of(url),
+            Remote::PyPi(_) => SourceKind::PyPi,
+            Remote::Crate(_) => SourceKind::Crates,
+            Remote::GitTags(_) => SourceKind::Git,
+            Remote::Tarball(tarball) => SourceKind::of(&tarball.url),
+            Remote::VersionFile(url) => SourceKind::of(url),
+        }
+    }
+}
+
+/// Load a YAML file and resolve its `include` chain.
+/// `stack` holds the files currently being loaded, to detect cycles; each
+/// file is appended to `layers` as it is read.
+fn load_with_includes(
+    path: &Path,
+    stack: &mut Vec<PathBuf>,
+    layers: &mut Vec<(PathBuf, Value)>,
+) -> anyhow::Result<Value> {
+    let canonical = path
+        .canonicalize()
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    if stack.contains(&canonical) {
+        bail!("include cycle detected at {}", path.display());
+    }
+
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    let value: Value = serde_yaml::from_str(&content)
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+
+    layers.push((path.to_path_buf(), value.clone()));
+    stack.push(canonical);
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let merged = resolve_include(value, dir, &path.display().to_string(), stack, layers);
+    stack.pop();
+    merged
+}
+
+/// Merge the file named by `value`'s `include` key, if any, under `value`.
+/// The include is relative to `dir`; `origin` names `value` in errors.
+fn resolve_include(
+    mut value: Value,
+    dir: &Path,
+    origin: &str,
+    stack: &mut Vec<PathBuf>,
+    layers: &mut Vec<(PathBuf, Value)>,
+) -> anyhow::Result<Value> {
+    let Some(mapping) = value.as_mapping_mut() else {
+        return Ok(value);
+    };
+    let Some(include) = mapping.remove("include") else {
+        return Ok(value);
+    };
+    let Some(include) = include.as_str() else {
+        bail!("'include' in {origin} must be a path");
+    };
+
+    let mut merged = load_with_includes(&dir.join(include), stack, layers)?;
+    merge(&mut merged, value);
+    Ok(merged)
+}
+
+/// Replace `${VAR}` in the string values of a config (after includes) with
+/// the environment variable. Unset variables are left as they are and
+/// collected in `undefined`; `x-*` metadata is not expanded.
+fn expand_env(value: &mut Value, undefined: &mut Vec<String>) {
+    let var_re = Regex::new(ENV_VAR).expect("valid variable regex");
+    let mut expand = |text: &mut String| {
+        let expanded = var_re.replace_all(text, |c: &Captures| match std::env::var(&c[1]) {
+            Ok(value) => value,
+            Err(_) => {
+                if !undefined.iter().any(|v| v == &c[1]) {
+                    undefined.push(c[1].to_string());
+                }
+                c[0].to_string()
+            }
+        });
+        *text = expanded.into_owned();
+    };
+    let Some(mapping) = value.as_mapping_mut() else {
+        return;
+    };
+    for (key, value) in mapping.iter_mut() {
+        if key.as_str().is_some_and(|k| k.starts_with("x-")) {
+            continue;
+        }
+        match value {
+            Value::String(text) => expand(text),
+            Value::Mapping(fields) => {
+                for field in fields.values_mut() {
+                    if let Value::String(text) = field {
+                        expand(text);
+                    }
+                }
+            }
+            Value::Sequence(items) => {
+                for item in items {
+                    if let Value::String(text) = item {
+                        expand(text);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Merge `overlay` into `base`; nested mappings merge key by key and
+/// everything else in `overlay` replaces the value in `base`
+fn merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Mapping(base), Value::Mapping(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::scratch_dir;
+
+    #[test]
+    fn test_include_local_wins() {
+        let dir = scratch_dir("include-local-wins");
+        std::fs::write(
+            dir.join("shared.yaml"),
+            "remote_pkgbuild: https://example.com/shared/PKGBUILD\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("rchan.yaml"),
+            "include: shared.yaml\nremote_pkgbuild: https://example.com/foo/PKGBUILD\n",
+        )
+        .unwrap();
+        let config = RchanConfig::from_file(&dir.join("rchan.yaml")).unwrap();
+        assert_eq!(config.remote().unwrap(), Remote::Pkgbuild("https://example.com/foo/PKGBUILD"));
+
+        std::fs::write(dir.join("rchan.yaml"), "include: shared.yaml\n").unwrap();
+        let config = RchanConfig::from_file(&dir.join("rchan.yaml")).unwrap();
+        assert_eq!(
+            config.remote().unwrap(),
+            Remote::Pkgbuild("https://example.com/shared/PKGBUILD")
+        );
+    }
+
+    #[test]
+    fn test_custom_keys() {
+        let dir = scratch_dir("custom-keys");
+        std::fs::write(
+            dir.join("rchan.yaml"),
+            "remote_pkgbuild: https://example.com/PKGBUILD\n\
+             x-owner: alice\n\
+             x-notes: [pinned, upstream slow]\n\
+             remote_pkgbiuld: typo\n",
+        )
+        .unwrap();
+        let config = RchanConfig::from_file(&dir.join("rchan.yaml")).unwrap();
+        assert_eq!(config.extra["x-owner"], Value::from("alice"));
+        assert_eq!(config.unknown_keys(), ["remote_pkgbiuld"]);
+
+        // Custom keys survive a round trip
+        let yaml = serde_yaml::to_string(&config).unwrap();
+        let back: RchanConfig = serde_yaml::from_str(&yaml).unwrap();
+        assert_eq!(back.extra["x-notes"], config.extra["x-notes"]);
+    }
+
+    #[test]
+    fn test_version_sources() {
+        let dir = scratch_dir("version-sources");
+        let path = dir.join("rchan.yaml");
+        std::fs::write(&path, "crate: ripgrep\n").unwrap();
+        let config = RchanConfig::from_file(&path).unwrap();
+        assert_eq!(config.remote().unwrap(), Remote::Crate("ripgrep"));
+        assert_eq!(config.remote().unwrap().kind(), SourceKind::Crates);
+        std::fs::write(&path, "git_tags: https://git.example.org/foo.git\n").unwrap();
+        let config = RchanConfig::from_file(&path).unwrap();
+        assert_eq!(config.remote().unwrap().kind(), SourceKind::Git);
+
+        let tarball = "remote_tarball:\n  url: https://github.com/o/r/releases/download/r.tar.gz\n";
+        std::fs::write(&path, format!("{tarball}  path: '*/PKGBUILD'\n")).unwrap();
+        let config = RchanConfig::from_file(&path).unwrap();
+        assert_eq!(config.remote().unwrap().kind(), SourceKind::GitHub);
+        for bad in ["", "  path: /PKGBUILD\n", "  path: PKGBUILD\n  paht: x\n"] {
+            std::fs::write(&path, format!("{tarball}{bad}")).unwrap();
+            assert!(RchanConfig::from_file(&path).is_err(), "{bad}");
+        }
+
+        std::fs::write(&path, "remote_version_file: https://example.org/VERSION\n").unwrap();
+        let config = RchanConfig::from_file(&path).unwrap();
+        assert_eq!(config.remote().unwrap(), Remote::VersionFile("https://example.org/VERSION"));
+        assert_eq!(config.remote().unwrap().kind(), SourceKind::Url);
+
+        std::fs::write(&path, "pypi: requests\nremote_pkgbuild: https://x/PKGBUILD\n").unwrap();
+        assert!(RchanConfig::from_file(&path).is_err());
+        std::fs::write(&path, "x-owner: me\n").unwrap();
+        assert!(RchanConfig::from_file(&path).is_err());
+
+        // Names that would change the API URL
+        for good in ["pypi: zope.interface\n", "pypi: Flask_2-x\n", "crate: serde_json\n"] {
+            std::fs::write(&path, good).unwrap();
+            assert!(RchanConfig::from_file(&path).is_ok(), "{good}");
+        }
+        for bad in ["pypi: a/b\n", "pypi: 'x?y'\n", "pypi: -x\n", "crate: 'a#b'\n", "crate: 1x\n"] {
+            std::fs::write(&path, bad).unwrap();
+            assert!(RchanConfig::from_file(&path).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn test_git_ref() {
+        let dir = scratch_dir("git-ref");
+        let path = dir.join("rchan.yaml");
+        std::fs::write(&path, "remote_pkgbuild: https://x/PKGBUILD\nref: testing\n").unwrap();
+        let config = RchanConfig::from_file(&path).unwrap();
+        assert_eq!(config.git_ref.as_deref(), Some("testing"));
+        assert!(config.unknown_keys().is_empty());
+
+        std::fs::write(&path, "pypi: requests\nref: testing\n").unwrap();
+        assert!(RchanConfig::from_file(&path).is_err());
+        for git in ["git_tags: https://x/r.git", "remote_pkgbuild: git+https://x/r.git"] {
+            std::fs::write(&path, format!("{git}\nref: testing\n")).unwrap();
+            let err = RchanConfig::from_file(&path).unwrap_err();
+            assert!(err.to_string().contains("tags aren't per branch"), "{git}");
+        }
+    }
+
+    #[test]
+    fn test_pkgbuild_name() {
+        let dir = scratch_dir("pkgbuild-name");
+        let path = dir.join("rchan.yaml");
+        std::fs::write(&path, "remote_pkgbuild: https://x/PKGBUILD\n").unwrap();
+        assert_eq!(RchanConfig::from_file(&path).unwrap().pkgbuild, None);
+
+        std::fs::write(&path, "remote_pkgbuild: https://x/PKGBUILD\npkgbuild: PKGBUILD.lts\n")
+            .unwrap();
+        let config = RchanConfig::from_file(&path).unwrap();
+        assert_eq!(config.pkgbuild.as_deref(), Some("PKGBUILD.lts"));
+
+        std::fs::write(&path, "remote_pkgbuild: https://x/PKGBUILD\npkgbuild: ../PKGBUILD\n")
+            .unwrap();
+        assert!(RchanConfig::from_file(&path).is_err());
+    }
+
+    #[test]
+    fn test_pkgver_var() {
+        let dir = scratch_dir("pkgver-var");
+        let path = dir.join("rchan.yaml");
+        std::fs::write(&path, "remote_pkgbuild: https://x/PKGBUILD\npkgver_var: _realver\n")
+            .unwrap();
+        let config = RchanConfig::from_file(&path).unwrap();
+        assert_eq!(config.pkgver_var.as_deref(), Some("_realver"));
+
+        for bad in ["''", "1ver", "ver-sion", "$ver"] {
+            let content = format!("remote_pkgbuild: https://x/PKGBUILD\npkgver_var: {bad}\n");
+            std::fs::write(&path, content).unwrap();
+            assert!(RchanConfig::from_file(&path).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn test_label_priority() {
+        let yaml = "remote_pkgbuild: x\nlabel: critical\npriority: 9\n";
+        let config = RchanConfig::from_yaml(yaml, Path::new("/")).unwrap();
+        assert_eq!(config.label.as_deref(), Some("critical"));
+        assert_eq!(config.priority, Some(9));
+        assert!(config.unknown_keys().is_empty());
+
+        for bad in ["label: ''", "priority: 256", "priority: -1"] {
+            let yaml = format!("remote_pkgbuild: x\n{bad}\n");
+            assert!(RchanConfig::from_yaml(&yaml, Path::new("/")).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn test_env_expansion() {
+        // cargo sets CARGO_PKG_NAME for test runs
+        let yaml = "remote_pkgbuild: https://m/${CARGO_PKG_NAME}/${RCHAN_UNSET_VAR}\n\
+                    label: ${CARGO_PKG_NAME}\nx-owner: ${RCHAN_UNSET_OWNER}\n";
+        let config = RchanConfig::from_yaml(yaml, Path::new("/")).unwrap();
+        let name = env!("CARGO_PKG_NAME");
+        assert_eq!(
+            config.remote_pkgbuild.unwrap(),
+            format!("https://m/{name}/${{RCHAN_UNSET_VAR}}")
+        );
+        assert_eq!(config.label.as_deref(), Some(name));
+        assert_eq!(config.undefined_env, ["RCHAN_UNSET_VAR"]);
+    }
+
+    #[test]
+    fn test_request_policy_overrides() {
+        let dir = scratch_dir("request-policy");
+        let path = dir.join("rchan.yaml");
+        let default = RequestPolicy {
+            timeout: Duration::from_secs(30),
+            retries: 0,
+            accept: None,
+        };
+
+        std::fs::write(&path, "remote_pkgbuild: https://x/PKGBUILD\ntimeout: 2.5\n").unwrap();
+        let config = RchanConfig::from_file(&path).unwrap();
+        let policy = config.request_policy(default);
+        assert_eq!(policy.timeout, Duration::from_millis(2500));
+        assert_eq!(policy.retries, 0);
+
+        std::fs::write(&path, "remote_pkgbuild: https://x/PKGBUILD\nretries: 3\n").unwrap();
+        let config = RchanConfig::from_file(&path).unwrap();
+        assert_eq!(config.request_policy(default).timeout, default.timeout);
+        assert_eq!(config.request_policy(default).retries, 3);
+        assert_eq!(config.request_policy(default).accept, None);
+
+        let yaml = "remote_pkgbuild: https://x/PKGBUILD\naccept: text/x-shellscript\n";
+        std::fs::write(&path, yaml).unwrap();
+        let config = RchanConfig::from_file(&path).unwrap();
+        assert_eq!(config.request_policy(default).accept, Some("text/x-shellscript"));
+
+        std::fs::write(&path, "remote_pkgbuild: https://x/PKGBUILD\naccept: ''\n").unwrap();
+        assert!(RchanConfig::from_file(&path).is_err());
+
+        std::fs::write(&path, "remote_pkgbuild: https://x/PKGBUILD\ntimeout: 0\n").unwrap();
+        assert!(RchanConfig::from_file(&path).is_err());
+    }
+
+    #[test]
+    fn test_global_allowed_hosts() {
+        let dir = scratch_dir("global-allowed-hosts");
+        let path = dir.join("config.yaml");
+        std::fs::write(&path, "allowed_hosts: [aur.archlinux.org, '*.example.org']\n").unwrap();
+        let config = GlobalConfig::from_file(&path).unwrap();
+        assert_eq!(config.allowed_hosts.unwrap(), ["aur.archlinux.org", "*.example.org"]);
+
+        std::fs::write(&path, "allowed_hosts: ['https://example.org/']\n").unwrap();
+        assert!(GlobalConfig::from_file(&path).is_err());
+        std::fs::write(&path, "allowed_hosts: ['*']\n").unwrap();
+        assert!(GlobalConfig::from_file(&path).is_err());
+    }
+
+    #[test]
+    fn test_from_yaml() {
+        let dir = scratch_dir("from-yaml");
+        std::fs::write(dir.join("shared.yaml"), "timeout: 5\n").unwrap();
+        let config =
+            RchanConfig::from_yaml("include: shared.yaml\npypi: foo\n", &dir).unwrap();
+        assert_eq!(config.pypi.as_deref(), Some("foo"));
+        assert_eq!(config.timeout, Some(5.0));
+        assert!(RchanConfig::from_yaml("pypi: foo\ncrate: foo\n", &dir).is_err());
+    }
+
+    #[test]
+    fn test_include_cycle() {
+        let dir = scratch_dir("include-cycle");
+        std::fs::write(dir.join("a.yaml"), "include: b.yaml\n").unwrap();
+        std::fs::write(dir.join("b.yaml"), "include: a.yaml\n").unwrap();
+        let err = RchanConfig::from_file(&dir.join("a.yaml")).unwrap_err();
+        assert!(err.to_string().contains("include cycle"));
+    }
+
+    #[test]
+    fn test_layers() {
+        let dir = scratch_dir("layers");
+        std::fs::create_dir(dir.join("pkg")).unwrap();
+        std::fs::write(dir.join("shared.yaml"), "timeout: 5\nretries: 2\n").unwrap();
+        std::fs::write(
+            dir.join("pkg/rchan.yaml"),
+            "include: ../shared.yaml\npypi: foo\nretries: 1\n",
+        )
+        .unwrap();
+        let config = RchanConfig::from_file(&dir.join("pkg/rchan.yaml")).unwrap();
+        let files: Vec<_> = config.layers.iter().map(|(path, _)| path.clone()).collect();
+        assert_eq!(files, [dir.join("pkg/rchan.yaml"), dir.join("pkg/../shared.yaml")]);
+        assert_eq!(config.layers[0].1["retries"], Value::from(1));
+        assert!(config.layers[0].1.get("timeout").is_none());
+        assert_eq!(config.layers[1].1["timeout"], Value::from(5));
+    }
+}
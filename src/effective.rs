@@ -0,0 +1,284 @@
+use std::fmt::Write;
+use std::path::Path;
+use std::process::ExitCode;
+
+use anyhow::{Context, Result};
+use regex::Regex;
+use serde_json::Value as Json;
+
+use crate::cli::Options;
+use crate::config::{self, GlobalConfig, RchanConfig};
+use crate::fetch::{DEFAULT_ACCEPT, DEFAULT_TIMEOUT};
+use crate::scanner;
+
+/// rchan.yaml keys in the order they are shown; unknown and `x-*` keys
+/// follow, sorted
+const KEYS: &[&str] = &[
+    "remote_pkgbuild",
+    "remote_tarball",
+    "remote_version_file",
+    "pypi",
+    "crate",
+    "git_tags",
+    "ref",
+    "pkgbuild",
+    "pkgver_var",
+    "timeout",
+    "retries",
+    "accept",
+    "label",
+    "priority",
+];
+
+/// Comments past this column are no longer aligned
+const MAX_ALIGN: usize = 60;
+
+/// Which layer each setting the command line, the environment or the global
+/// config can give came from. Taken before [`Options::apply_global`] fills
+/// in what the command line left unset, as the merged options can't tell.
+pub struct Origins {
+    timeout: &'static str,
+    retries: &'static str,
+    cacert: &'static str,
+    allowed_hosts: &'static str,
+    makepkg_bin: &'static str,
+}
+
+impl Origins {
+    pub fn new(opts: &Options, global: &GlobalConfig) -> Self {
+        let layer = |flag: Option<&'static str>, global: bool| match (flag, global) {
+            (Some(flag), _) => flag,
+            (None, true) => "global config",
+            (None, false) => "default",
+        };
+        let env_makepkg = std::env::var_os("RCHAN_MAKEPKG").is_some_and(|bin| !bin.is_empty());
+        Origins {
+            timeout: layer(opts.timeout.and(Some("--timeout")), global.timeout.is_some()),
+            retries: layer(opts.retries.and(Some("--retries")), global.retries.is_some()),
+            cacert: layer(opts.cacert.as_ref().and(Some("--cacert")), global.cacert.is_some()),
+            allowed_hosts: match global.allowed_hosts {
+                Some(_) => "global config",
+                None => "default, any host",
+            },
+            makepkg_bin: match () {
+                _ if opts.makepkg_bin.is_some() => "--makepkg-bin",
+                _ if env_makepkg => "$RCHAN_MAKEPKG",
+                _ => layer(None, global.makepkg_bin.is_some()),
+            },
+        }
+    }
+}
+
+/// One line of the dump: a key, its effective value and where it came from
+struct Setting {
+    key: String,
+    value: Json,
+    origin: String,
+}
+
+impl Setting {
+    fn new(key: &str, value: impl Into<Json>, origin: impl Into<String>) -> Self {
+        Setting {
+            key: key.to_string(),
+            value: value.into(),
+            origin: origin.into(),
+        }
+    }
+}
+
+/// Run `check --dump-config[=NAME]`: print the configuration each package
+/// (or only NAME) is checked with as YAML, every value commented with the
+/// layer it came from. Packages whose config can't be read are listed with
+/// the error, and make the exit status non-zero.
+pub fn run_dump_config(base: &Path, opts: &Options, origins: &Origins) -> Result<ExitCode> {
+    let mut out = String::new();
+    match GlobalConfig::path() {
+        Some(path) if path.is_file() => writeln!(out, "# Global config: {}", path.display())?,
+        Some(path) => writeln!(out, "# Global config: {} (not found)", path.display())?,
+        None => writeln!(out, "# Global config: none ($XDG_CONFIG_HOME and $HOME unset)")?,
+    }
+    out.push_str("global:\n");
+    out.push_str(&render(&global_settings(opts, origins), "  "));
+
+    out.push_str("packages:\n");
+    let mut failed = false;
+    let packages = scanner::config_files(base, opts, opts.dump_config_name.as_deref())?;
+    for (name, rchan_yaml, problem) in packages {
+        let settings = match problem {
+            Some(problem) => Err(anyhow::Error::msg(problem)),
+            None => package_settings(&rchan_yaml, opts, origins),
+        };
+        match settings {
+            Ok(settings) => {
+                writeln!(out, "  {}:  # {}", yaml_key(&name), rchan_yaml.display())?;
+                out.push_str(&render(&settings, "    "));
+            }
+            Err(e) => {
+                failed = true;
+                writeln!(out, "  {}:  # error: {e:#}", yaml_key(&name))?;
+            }
+        }
+    }
+    print!("{out}");
+    Ok(match failed {
+        true => ExitCode::from(crate::EXIT_ERRORS),
+        false => ExitCode::SUCCESS,
+    })
+}
+
+/// Settings from the command line, the environment and the global config,
+/// which apply to every package
+fn global_settings(opts: &Options, origins: &Origins) -> Vec<Setting> {
+    let path = |path: &Path| Json::from(path.display().to_string());
+    vec![
+        Setting::new("timeout", timeout(opts), origins.timeout),
+        Setting::new("retries", opts.retries.unwrap_or(0), origins.retries),
+        Setting::new("cacert", opts.cacert.as_deref().map(path), origins.cacert),
+        Setting::new("allowed_hosts", opts.allowed_hosts.clone(), origins.allowed_hosts),
+        Setting::new(
+            "makepkg_bin",
+            opts.makepkg_bin.as_deref().map_or(Json::from("makepkg"), path),
+            origins.makepkg_bin,
+        ),
+    ]
+}
+
+/// Per-attempt timeout in seconds from the command line or global config
+fn timeout(opts: &Options) -> f64 {
+    opts.timeout.unwrap_or(DEFAULT_TIMEOUT).as_secs_f64()
+}
+
+/// The settings of the package configured in `rchan_yaml`: every key it (or
+/// a file it includes) sets, with `${VAR}` expanded, then the defaults of
+/// those it leaves unset
+fn package_settings(rchan_yaml: &Path, opts: &Options, origins: &Origins) -> Result<Vec<Setting>> {
+    let file_name = rchan_yaml.file_name().unwrap_or_default().to_string_lossy();
+    let config = RchanConfig::from_file(rchan_yaml)
+        .with_context(|| format!("Failed to parse {file_name}"))?;
+    let dir = rchan_yaml.parent().unwrap_or(Path::new("."));
+    let fields = serde_json::to_value(&config)?;
+    let var_re = Regex::new(config::ENV_VAR).expect("valid variable regex");
+
+    let mut extra: Vec<&str> = config.extra.keys().map(String::as_str).collect();
+    extra.sort();
+    let mut settings = Vec::new();
+    for key in KEYS.iter().copied().chain(extra) {
+        let Some(value) = fields.get(key) else {
+            let default = match key {
+                "pkgbuild" => Setting::new(key, "PKGBUILD", "default"),
+                "timeout" => Setting::new(key, timeout(opts), origins.timeout),
+                "retries" => Setting::new(key, opts.retries.unwrap_or(0), origins.retries),
+                "accept" => Setting::new(key, DEFAULT_ACCEPT, "default"),
+                _ => continue,
+            };
+            settings.push(default);
+            continue;
+        };
+        // Keys in the including file win, so the first file with it set it
+        let layer = config.layers.iter().find(|(_, layer)| layer.get(key).is_some());
+        let Some((file, raw)) = layer else {
+            continue;
+        };
+        let mut origin = file.strip_prefix(dir).unwrap_or(file).display().to_string();
+        if key.starts_with("x-") {
+            origin.push_str(", metadata");
+        } else if !KEYS.contains(&key) {
+            origin.push_str(", unknown key, ignored");
+        } else {
+            let raw = serde_yaml::to_string(&raw[key])?;
+            for var in var_re.captures_iter(&raw).map(|c| c[1].to_string()) {
+                match config.undefined_env.contains(&var) {
+                    true => write!(origin, ", ${{{var}}} not set")?,
+                    false => write!(origin, ", ${{{var}}} expanded")?,
+                }
+            }
+        }
+        settings.push(Setting::new(key, value.clone(), origin));
+    }
+    Ok(settings)
+}
+
+/// Settings as YAML lines under `indent`, with comments aligned. Values are
+/// written as JSON, which YAML reads the same.
+fn render(settings: &[Setting], indent: &str) -> String {
+    let lines: Vec<String> = settings
+        .iter()
+        .map(|s| format!("{indent}{}: {}", yaml_key(&s.key), s.value))
+        .collect();
+    let width = lines.iter().map(|line| line.chars().count()).max().unwrap_or(0);
+    let width = width.min(MAX_ALIGN);
+    lines
+        .iter()
+        .zip(settings)
+        .map(|(line, s)| format!("{line:width$}  # {}\n", s.origin))
+        .collect()
+}
+
+/// `name` as a YAML mapping key, quoted unless it is a plain name
+fn yaml_key(name: &str) -> String {
+    let plain = name.starts_with(|c: char| c.is_ascii_alphanumeric())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || "-_./+@".contains(c));
+    match plain {
+        true => name.to_string(),
+        false => Json::from(name).to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::scratch_dir;
+
+    #[test]
+    fn test_package_settings() {
+        let dir = scratch_dir("dump-config");
+        std::fs::create_dir_all(dir.join("foo")).unwrap();
+        std::fs::write(dir.join("shared.yaml"), "timeout: 5\naccept: text/plain\n").unwrap();
+        std::fs::write(
+            dir.join("foo/rchan.yaml"),
+            "include: ../shared.yaml\n\
+             remote_pkgbuild: https://m/${CARGO_PKG_NAME}/${RCHAN_UNSET_VAR}\n\
+             timeout: 10\nx-owner: me\ntypo: 1\n",
+        )
+        .unwrap();
+        let origins = Origins::new(&Options::default(), &GlobalConfig::default());
+        let settings = package_settings(&dir.join("foo/rchan.yaml"), &Options::default(), &origins)
+            .unwrap();
+        let shown: Vec<_> = settings
+            .iter()
+            .map(|s| (s.key.as_str(), s.value.to_string(), s.origin.as_str()))
+            .collect();
+        let remote = format!("\"https://m/{}/${{RCHAN_UNSET_VAR}}\"", env!("CARGO_PKG_NAME"));
+        assert_eq!(
+            shown,
+            [
+                (
+                    "remote_pkgbuild",
+                    remote,
+                    "rchan.yaml, ${CARGO_PKG_NAME} expanded, ${RCHAN_UNSET_VAR} not set"
+                ),
+                ("pkgbuild", "\"PKGBUILD\"".to_string(), "default"),
+                ("timeout", "10.0".to_string(), "rchan.yaml"),
+                ("retries", "0".to_string(), "default"),
+                ("accept", "\"text/plain\"".to_string(), "../shared.yaml"),
+                ("typo", "1".to_string(), "rchan.yaml, unknown key, ignored"),
+                ("x-owner", "\"me\"".to_string(), "rchan.yaml, metadata"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_render() {
+        let settings = [
+            Setting::new("timeout", 30.0, "default"),
+            Setting::new("allowed_hosts", vec!["aur.archlinux.org"], "global config"),
+        ];
+        assert_eq!(
+            render(&settings, "  "),
+            "  timeout: 30.0                         # default\n  \
+             allowed_hosts: [\"aur.archlinux.org\"]  # global config\n"
+        );
+        assert_eq!(yaml_key("foo/rchan.lts.yaml"), "foo/rchan.lts.yaml");
+        assert_eq!(yaml_key("-foo"), "\"-foo\"");
+    }
+}
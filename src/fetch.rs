@@ -0,0 +1,1389 @@
+use std::collections::HashSet;
+use std::io::Read;
+use std::sync::{Condvar, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result, bail};
+use reqwest::blocking::Client;
+use reqwest::header::{
+    ACCEPT, AGE, CACHE_CONTROL, CONTENT_TYPE, ETAG, HeaderMap, HeaderValue, IF_MODIFIED_SINCE,
+    IF_NONE_MATCH, LAST_MODIFIED,
+};
+use reqwest::{Certificate, StatusCode, Url, redirect};
+use serde::Serialize;
+
+use crate::archive;
+use crate::cache::{self, CacheEntry, ResultCache, Validators};
+use crate::cli::Options;
+use crate::process::CommandError;
+use crate::ssh::{self, SshTarget};
+use crate::git;
+
+/// Kind of remote source a package is checked against
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SourceKind {
+    /// AUR (aur.archlinux.org)
+    Aur,
+    /// GitHub (github.com, raw.githubusercontent.com)
+    GitHub,
+    /// GitLab (gitlab.com and self-hosted gitlab.* hosts)
+    GitLab,
+    /// Any other http(s) URL
+    Url,
+    /// Local file (`file://` or path)
+    Local,
+    /// File read over SSH (`ssh://` or scp-style `host:path`)
+    Ssh,
+    /// Latest release on PyPI
+    PyPi,
+    /// Latest release on crates.io
+    Crates,
+    /// Newest version tag of a git repository
+    Git,
+}
+
+impl SourceKind {
+    /// Classify a configured remote source
+    pub fn of(source: &str) -> Self {
+        if SshTarget::parse(source).is_some() {
+            return SourceKind::Ssh;
+        }
+        let Some((scheme, rest)) = source.split_once("://") else {
+            return SourceKind::Local;
+        };
+        if scheme == "file" {
+            return SourceKind::Local;
+        }
+        let host = rest.split(['/', ':', '?']).next().unwrap_or_default();
+        match host {
+            "aur.archlinux.org" => SourceKind::Aur,
+            "github.com" | "raw.githubusercontent.com" => SourceKind::GitHub,
+            h if h == "gitlab.com" || h.starts_with("gitlab.") => SourceKind::GitLab,
+            _ => SourceKind::Url,
+        }
+    }
+}
+
+impl std::str::FromStr for SourceKind {
+    type Err = anyhow::Error;
+
+    /// Parse the lowercase names used in JSON output and `--source-type`
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "aur" => Ok(SourceKind::Aur),
+            "github" => Ok(SourceKind::GitHub),
+            "gitlab" => Ok(SourceKind::GitLab),
+            "url" => Ok(SourceKind::Url),
+            "local" => Ok(SourceKind::Local),
+            "ssh" => Ok(SourceKind::Ssh),
+            "pypi" => Ok(SourceKind::PyPi),
+            "crates" => Ok(SourceKind::Crates),
+            "git" => Ok(SourceKind::Git),
+            other => bail!(
+                "unknown source type '{other}' \
+                 (expected aur, github, gitlab, url, local, ssh, pypi, crates or git)"
+            ),
+        }
+    }
+}
+
+impl std::fmt::Display for SourceKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            SourceKind::Aur => "AUR",
+            SourceKind::GitHub => "GitHub",
+            SourceKind::GitLab => "GitLab",
+            SourceKind::Url => "URL",
+            SourceKind::Local => "local",
+            SourceKind::Ssh => "SSH",
+            SourceKind::PyPi => "PyPI",
+            SourceKind::Crates => "crates.io",
+            SourceKind::Git => "git",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Timeout used when neither config nor command line sets one
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// `Accept` header sent unless a package's config sets its own: raw files
+/// first, but anything a server has
+pub const DEFAULT_ACCEPT: &str = "text/plain, */*;q=0.8";
+
+/// How patient to be with one remote, and what to ask it for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RequestPolicy<'a> {
+    /// Limit for each attempt
+    pub timeout: Duration,
+    /// Extra attempts after a connection error, timeout or 5xx response
+    pub retries: u32,
+    /// `Accept` header replacing [`DEFAULT_ACCEPT`]
+    pub accept: Option<&'a str>,
+}
+
+/// Fetches remote PKGBUILDs for all checks in a run.
+///
+/// Holds one shared HTTP client: reusing it keeps connections alive and
+/// pooled, so scans that hit the same host many times skip a TCP/TLS
+/// handshake per package.
+pub struct Fetcher {
+    client: Client,
+    /// Refuse network sources; only local files are read
+    offline: bool,
+    /// Wall-clock limit for the whole run
+    deadline: Option<Instant>,
+    /// Largest response body read, in bytes
+    max_size: u64,
+    /// Timeout and retries unless a package overrides them
+    policy: RequestPolicy<'static>,
+    /// Network requests currently running
+    in_flight: AtomicUsize,
+    /// Request counts and timings for `--concurrency-report`
+    stats: Mutex<ConcurrencyStats>,
+    created: Instant,
+    /// Hosts network sources may use (None: any)
+    allowed_hosts: Option<Vec<String>>,
+    /// Request concurrency limit for `--adaptive-jobs`
+    adaptive: Option<AdaptiveLimit>,
+    /// Request rate limit for `--rate-limit`
+    rate_limit: Option<RateLimit>,
+    /// Earlier responses for [`Fetcher::fetch_cached`] (None: caching is off)
+    cache: Option<Mutex<ResultCache>>,
+    /// Responses answered from the cache, while fresh or after a 304 Not
+    /// Modified
+    cache_hits: AtomicUsize,
+    /// Sources whose latest response was served as `text/html`, for
+    /// [`Fetcher::parse_hint`]
+    html_responses: Mutex<HashSet<String>>,
+}
+
+/// Body of an http(s) response
+enum HttpBody {
+    Fresh(Vec<u8>, Validators),
+    /// The server confirmed the cached copy is current
+    NotModified,
+}
+
+/// Concurrency `--adaptive-jobs` starts at
+pub const ADAPTIVE_START_JOBS: usize = 2;
+/// Most concurrent requests `--adaptive-jobs` ramps up to
+pub const ADAPTIVE_MAX_JOBS: usize = 16;
+/// Requests faster than this count as evidence there is room for more
+const ADAPTIVE_FAST: Duration = Duration::from_secs(2);
+
+/// Request concurrency limit for `--adaptive-jobs`: one more after a run of
+/// quick successes as long as the limit, halved when a request times out or
+/// fails with a connection error or 5xx (additive increase, multiplicative
+/// decrease, as TCP does)
+struct AdaptiveLimit {
+    state: Mutex<AdaptiveJobs>,
+    changed: Condvar,
+}
+
+/// Where `--adaptive-jobs` ended up
+#[derive(Debug, Default, Clone, Copy)]
+pub struct AdaptiveJobs {
+    /// Limit at the end of the run
+    pub limit: usize,
+    /// Highest limit reached
+    pub peak: usize,
+    /// Times the limit was halved
+    pub backoffs: usize,
+    /// Requests running now
+    active: usize,
+    /// Quick successes since the limit last changed
+    streak: usize,
+    last_backoff: Option<Instant>,
+}
+
+impl AdaptiveLimit {
+    fn new() -> Self {
+        Self {
+            state: Mutex::new(AdaptiveJobs {
+                limit: ADAPTIVE_START_JOBS,
+                peak: ADAPTIVE_START_JOBS,
+                ..AdaptiveJobs::default()
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    /// Wait until another request may start
+    fn acquire(&self) {
+        let mut state = self.state.lock().unwrap();
+        while state.active >= state.limit {
+            state = self.changed.wait(state).unwrap();
+        }
+        state.active += 1;
+    }
+
+    fn release(&self) {
+        self.state.lock().unwrap().active -= 1;
+        self.changed.notify_all();
+    }
+
+    fn success(&self, elapsed: Duration) {
+        let mut state = self.state.lock().unwrap();
+        if elapsed > ADAPTIVE_FAST {
+            return;
+        }
+        state.streak += 1;
+        if state.streak >= state.limit && state.limit < ADAPTIVE_MAX_JOBS {
+            state.limit += 1;
+            state.peak = state.peak.max(state.limit);
+            state.streak = 0;
+            drop(state);
+            self.changed.notify_all();
+        }
+    }
+
+    fn congestion(&self) {
+        let mut state = self.state.lock().unwrap();
+        // Requests that were already in flight fail together; back off once
+        if state.last_backoff.is_some_and(|at| at.elapsed() < Duration::from_secs(1)) {
+            return;
+        }
+        state.limit = (state.limit / 2).max(1);
+        state.streak = 0;
+        state.backoffs += 1;
+        state.last_backoff = Some(Instant::now());
+    }
+}
+
+/// Global request rate limit for `--rate-limit`: a token bucket holding at
+/// most one token, refilled at `rate` tokens per second. Each request takes a
+/// token, waiting for it if the bucket is empty; waiting callers reserve
+/// their token up front, so they are served in order.
+struct RateLimit {
+    rate: f64,
+    state: Mutex<RateState>,
+}
+
+struct RateState {
+    /// Tokens at `updated`; negative when waiting requests have reserved them
+    tokens: f64,
+    updated: Instant,
+    /// Requests that had to wait for a token, and how long in total
+    delayed: usize,
+    waited: Duration,
+}
+
+impl RateLimit {
+    fn new(rate: f64) -> Self {
+        Self {
+            rate,
+            state: Mutex::new(RateState {
+                tokens: 1.0,
+                updated: Instant::now(),
+                delayed: 0,
+                waited: Duration::ZERO,
+            }),
+        }
+    }
+
+    /// Take a token, returning how long to wait before using it
+    fn reserve(&self) -> Duration {
+        let mut state = self.state.lock().unwrap();
+        let now = Instant::now();
+        let refill = now.duration_since(state.updated).as_secs_f64() * self.rate;
+        state.tokens = (state.tokens + refill).min(1.0) - 1.0;
+        state.updated = now;
+        if state.tokens >= 0.0 {
+            return Duration::ZERO;
+        }
+        let wait = Duration::from_secs_f64(-state.tokens / self.rate);
+        state.delayed += 1;
+        state.waited += wait;
+        wait
+    }
+}
+
+/// How much `--rate-limit` held requests back
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RateLimitStats {
+    /// Requests per second allowed
+    pub rate: f64,
+    /// Requests that had to wait for their turn
+    pub delayed: usize,
+    /// Sum of the time those requests waited
+    pub waited: Duration,
+}
+
+/// How well network requests overlapped during a run
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ConcurrencyStats {
+    /// Network fetches made (retries included in their fetch)
+    pub requests: usize,
+    /// Most fetches running at the same time
+    pub max_in_flight: usize,
+    /// Sum of the durations of all fetches, less `throttled`
+    pub busy: Duration,
+    /// Time fetches spent waiting for `--rate-limit` rather than the network
+    pub throttled: Duration,
+    /// Time since the fetcher was created
+    pub wall: Duration,
+}
+
+impl ConcurrencyStats {
+    /// Average fetches in flight; also the speedup over running them serially
+    pub fn average_in_flight(&self) -> f64 {
+        if self.wall.is_zero() {
+            return 0.0;
+        }
+        self.busy.as_secs_f64() / self.wall.as_secs_f64()
+    }
+}
+
+/// Counts a fetch as in flight until dropped
+struct InFlight<'a> {
+    fetcher: &'a Fetcher,
+    started: Instant,
+}
+
+impl<'a> InFlight<'a> {
+    fn start(fetcher: &'a Fetcher) -> Self {
+        if let Some(adaptive) = &fetcher.adaptive {
+            adaptive.acquire();
+        }
+        let now = fetcher.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+        let mut stats = fetcher.stats.lock().unwrap();
+        stats.requests += 1;
+        stats.max_in_flight = stats.max_in_flight.max(now);
+        Self {
+            fetcher,
+            started: Instant::now(),
+        }
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        if let Some(adaptive) = &self.fetcher.adaptive {
+            adaptive.release();
+        }
+        self.fetcher.in_flight.fetch_sub(1, Ordering::SeqCst);
+        self.fetcher.stats.lock().unwrap().busy += self.started.elapsed();
+    }
+}
+
+impl Fetcher {
+    pub fn new(opts: &Options) -> Result<Self> {
+        let mut builder = Client::builder()
+            .user_agent(concat!("rchan/", env!("CARGO_PKG_VERSION")))
+            // Advertise and transparently decode compressed responses
+            .gzip(true)
+            .deflate(true)
+            .brotli(true)
+            .default_headers(HeaderMap::from_iter([(
+                ACCEPT,
+                HeaderValue::from_static(DEFAULT_ACCEPT),
+            )]))
+            .danger_accept_invalid_certs(opts.insecure);
+        if let Some(patterns) = opts.allowed_hosts.clone() {
+            // A redirect must not lead off the allowlist either
+            builder = builder.redirect(redirect::Policy::custom(move |attempt| {
+                match attempt.url().host_str() {
+                    _ if attempt.previous().len() >= 10 => attempt.error("too many redirects"),
+                    Some(host) if host_allowed(host, &patterns) => attempt.follow(),
+                    host => {
+                        let host = host.unwrap_or_default().to_string();
+                        attempt.error(format!("redirect to {host}, which is not in allowed_hosts"))
+                    }
+                }
+            }));
+        }
+        if let Some(path) = &opts.cacert {
+            for cert in load_certificates(path)? {
+                builder = builder.add_root_certificate(cert);
+            }
+        }
+        let client = builder.build().context("Failed to build HTTP client")?;
+        Ok(Self {
+            client,
+            offline: opts.offline,
+            deadline: opts.deadline.map(|d| Instant::now() + d),
+            max_size: opts.max_size,
+            policy: RequestPolicy {
+                timeout: opts.timeout.unwrap_or(DEFAULT_TIMEOUT),
+                retries: opts.retries.unwrap_or(0),
+                accept: None,
+            },
+            in_flight: AtomicUsize::new(0),
+            stats: Mutex::new(ConcurrencyStats::default()),
+            created: Instant::now(),
+            allowed_hosts: opts.allowed_hosts.clone(),
+            adaptive: opts.adaptive_jobs.then(AdaptiveLimit::new),
+            rate_limit: opts.rate_limit.map(RateLimit::new),
+            cache: None,
+            cache_hits: AtomicUsize::new(0),
+            html_responses: Mutex::new(HashSet::new()),
+        })
+    }
+
+    /// Revalidate remotes against `cache` in [`Fetcher::fetch_cached`]
+    pub fn use_cache(&mut self, cache: ResultCache) {
+        self.cache = Some(Mutex::new(cache));
+    }
+
+    /// The cache with this run's responses, if caching is on
+    pub fn cache(&self) -> Option<ResultCache> {
+        self.cache.as_ref().map(|cache| cache.lock().unwrap().clone())
+    }
+
+    /// Remotes that were not modified since they were cached
+    pub fn cache_hits(&self) -> usize {
+        self.cache_hits.load(Ordering::SeqCst)
+    }
+
+    /// Request concurrency so far
+    pub fn concurrency_stats(&self) -> ConcurrencyStats {
+        let stats = *self.stats.lock().unwrap();
+        ConcurrencyStats {
+            busy: stats.busy.saturating_sub(stats.throttled),
+            wall: self.created.elapsed(),
+            ..stats
+        }
+    }
+
+    /// Final state of `--adaptive-jobs`, if enabled
+    pub fn adaptive_jobs(&self) -> Option<AdaptiveJobs> {
+        self.adaptive.as_ref().map(|a| *a.state.lock().unwrap())
+    }
+
+    /// How much `--rate-limit` held requests back, if enabled
+    pub fn rate_limit_stats(&self) -> Option<RateLimitStats> {
+        self.rate_limit.as_ref().map(|limit| {
+            let state = limit.state.lock().unwrap();
+            RateLimitStats {
+                rate: limit.rate,
+                delayed: state.delayed,
+                waited: state.waited,
+            }
+        })
+    }
+
+    /// Timeout and retries from the command line or global config
+    pub fn policy(&self) -> RequestPolicy<'static> {
+        self.policy
+    }
+
+    /// Whether the run's `--deadline` has passed
+    pub fn deadline_exceeded(&self) -> bool {
+        self.deadline.is_some_and(|d| Instant::now() >= d)
+    }
+
+    /// Fetch the text of a remote PKGBUILD.
+    ///
+    /// `source` is an http(s) URL, an SSH location (see [`SshTarget::parse`]),
+    /// a `file://` URL, an absolute local path, a git repository's tag list
+    /// (see [`git_tags_location`]) or a file in a tarball from any of these
+    /// (see [`tarball_location`]).
+    /// Requests follow `policy` (see [`Fetcher::policy`] for the default).
+    /// Anomalies that don't prevent reading it (redirects, invalid UTF-8) are
+    /// appended to `warnings`.
+    pub fn fetch(
+        &self,
+        source: &str,
+        policy: RequestPolicy,
+        warnings: &mut Vec<String>,
+    ) -> Result<String> {
+        if let Some(path) = local_path(source) {
+            return std::fs::read_to_string(path)
+                .with_context(|| format!("Failed to read local PKGBUILD: {path}"));
+        }
+        let bytes = self.fetch_bytes(source, policy, warnings)?;
+        Ok(decode(bytes, warnings))
+    }
+
+    /// Like [`Fetcher::fetch`] for package `name`, whose local PKGBUILD has
+    /// [`content_hash`](crate::cache::content_hash) `local_hash`. With a
+    /// cache, an http(s) remote cached for the same local PKGBUILD and source
+    /// is reused without a request while within its `max-age`, then requested
+    /// conditionally and reused if the server answers 304 Not Modified;
+    /// downloaded responses with an ETag, Last-Modified or max-age are cached.
+    pub fn fetch_cached(
+        &self,
+        name: &str,
+        local_hash: &str,
+        source: &str,
+        policy: RequestPolicy,
+        warnings: &mut Vec<String>,
+    ) -> Result<String> {
+        let Some(cache) = &self.cache else {
+            return self.fetch(source, policy, warnings);
+        };
+        if local_path(source).is_some()
+            || git_remote(source).is_some()
+            || SshTarget::parse(source).is_some()
+        {
+            return self.fetch(source, policy, warnings);
+        }
+        let cached = cache.lock().unwrap().get(name, local_hash, source).cloned();
+        if let Some(entry) = cached.as_ref().filter(|entry| entry.validators.is_fresh()) {
+            self.cache_hits.fetch_add(1, Ordering::SeqCst);
+            return Ok(entry.content.clone());
+        }
+        let validators = cached.as_ref().map(|entry| &entry.validators);
+        let (bytes, validators) = match self.fetch_raw(source, policy, validators, warnings)? {
+            HttpBody::Fresh(bytes, validators) => (bytes, validators),
+            HttpBody::NotModified => {
+                self.cache_hits.fetch_add(1, Ordering::SeqCst);
+                return Ok(cached.expect("conditional request for a cached entry").content);
+            }
+        };
+        let content = decode(bytes, warnings);
+        let mut cache = cache.lock().unwrap();
+        if validators.is_empty() {
+            cache.packages.remove(name);
+        } else {
+            let entry = CacheEntry {
+                local_hash: local_hash.to_string(),
+                source: source.to_string(),
+                validators,
+                content: content.clone(),
+            };
+            cache.packages.insert(name.to_string(), entry);
+        }
+        Ok(content)
+    }
+
+    /// What to add to the error when the content fetched from `source` can't
+    /// be parsed. An HTML page is usually a web view or login form rather
+    /// than the file, so a response served as `text/html` gets a hint.
+    pub fn parse_hint(&self, source: &str) -> &'static str {
+        let url = tarball(source).map_or(source, |(url, _)| url);
+        match self.html_responses.lock().unwrap().contains(url) {
+            true => {
+                " (the server sent Content-Type: text/html; check the URL, or set `accept` \
+                 in rchan.yaml if the server needs another Accept header)"
+            }
+            false => "",
+        }
+    }
+
+    /// Like [`Fetcher::fetch`], but return the response body as received
+    pub fn fetch_bytes(
+        &self,
+        source: &str,
+        policy: RequestPolicy,
+        warnings: &mut Vec<String>,
+    ) -> Result<Vec<u8>> {
+        match self.fetch_raw(source, policy, None, warnings)? {
+            HttpBody::Fresh(bytes, _) => Ok(bytes),
+            HttpBody::NotModified => bail!("unexpected 304 Not Modified from {source}"),
+        }
+    }
+
+    /// Read `source`; http(s) requests are conditional on `cached`
+    fn fetch_raw(
+        &self,
+        source: &str,
+        policy: RequestPolicy,
+        cached: Option<&Validators>,
+        warnings: &mut Vec<String>,
+    ) -> Result<HttpBody> {
+        // Validators and the cache apply to the tarball as a whole
+        if let Some((url, member)) = tarball(source) {
+            return match self.fetch_raw(url, policy, cached, warnings)? {
+                HttpBody::Fresh(bytes, validators) => {
+                    let content = archive::extract(&bytes, member, self.max_size)
+                        .with_context(|| format!("Failed to read {member} from {url}"))?;
+                    Ok(HttpBody::Fresh(content, validators))
+                }
+                HttpBody::NotModified => Ok(HttpBody::NotModified),
+            };
+        }
+        if let Some(path) = local_path(source) {
+            let bytes = std::fs::read(path)
+                .with_context(|| format!("Failed to read local PKGBUILD: {path}"))?;
+            return Ok(HttpBody::Fresh(bytes, Validators::default()));
+        }
+        // A git repository on this machine is as local as a file
+        let local_repo = git_remote(source).is_some_and(|url| local_path(url).is_some());
+        if let Some(patterns) = self.allowed_hosts.as_ref().filter(|_| !local_repo) {
+            let Some(host) = source_host(source) else {
+                bail!("cannot tell the host of {source}; allowed_hosts is set");
+            };
+            if !host_allowed(&host, patterns) {
+                bail!("host {host} is not in allowed_hosts");
+            }
+        }
+        if self.offline && !local_repo {
+            bail!("offline mode: not fetching {source}");
+        }
+
+        let in_flight = InFlight::start(self);
+        let body = if let Some(url) = git_remote(source) {
+            let read = |timeout| git::ls_remote_tags(url, timeout, self.max_size);
+            HttpBody::Fresh(self.fetch_command(policy, read)?, Validators::default())
+        } else if let Some(target) = SshTarget::parse(source) {
+            let read = |timeout| ssh::read_file(&target, timeout, self.max_size);
+            HttpBody::Fresh(self.fetch_command(policy, read)?, Validators::default())
+        } else {
+            self.fetch_http(source, policy, cached, warnings)?
+        };
+        if let Some(adaptive) = &self.adaptive {
+            adaptive.success(in_flight.started.elapsed());
+        }
+        Ok(body)
+    }
+
+    /// Tell `--adaptive-jobs` a request timed out or hit a connection error or 5xx
+    fn congestion(&self) {
+        if let Some(adaptive) = &self.adaptive {
+            adaptive.congestion();
+        }
+    }
+
+    /// Wait for `--rate-limit` to allow another request (at most until the
+    /// deadline), then give the timeout for it; never let a request outlive
+    /// the deadline
+    fn attempt_timeout(&self, policy: RequestPolicy) -> Result<Duration> {
+        if let Some(limit) = &self.rate_limit {
+            let wait = limit.reserve();
+            let remaining = self.deadline.map(|d| d.saturating_duration_since(Instant::now()));
+            let wait = remaining.map_or(wait, |r| wait.min(r));
+            std::thread::sleep(wait);
+            self.stats.lock().unwrap().throttled += wait;
+        }
+        let Some(deadline) = self.deadline else {
+            return Ok(policy.timeout);
+        };
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining == Duration::ZERO {
+            bail!("deadline exceeded");
+        }
+        Ok(policy.timeout.min(remaining))
+    }
+
+    /// Read a remote with a command (`ssh`, `git`), retrying connection
+    /// failures
+    fn fetch_command(
+        &self,
+        policy: RequestPolicy,
+        read: impl Fn(Duration) -> Result<Vec<u8>, CommandError>,
+    ) -> Result<Vec<u8>> {
+        let mut attempt = 0;
+        loop {
+            let timeout = self.attempt_timeout(policy)?;
+            match read(timeout) {
+                Ok(bytes) => return Ok(bytes),
+                Err(_) if self.deadline_exceeded() => bail!("deadline exceeded"),
+                Err(e) => {
+                    if e.connection {
+                        self.congestion();
+                    }
+                    if !e.connection || attempt >= policy.retries {
+                        // The error names the host or repository, so it reads better bare
+                        return Err(e.into());
+                    }
+                }
+            }
+            attempt += 1;
+            std::thread::sleep(Duration::from_millis(500) * attempt);
+        }
+    }
+
+    /// Whether a HEAD request for `url` succeeds, trying once
+    fn exists(&self, url: &str, policy: RequestPolicy) -> bool {
+        let Ok(timeout) = self.attempt_timeout(policy) else {
+            return false;
+        };
+        let response = self.client.head(url).timeout(timeout).send();
+        response.is_ok_and(|r| r.status().is_success())
+    }
+
+    /// GET an http(s) URL, retrying connection errors and 5xx responses.
+    /// With `cached` validators the request is conditional.
+    fn fetch_http(
+        &self,
+        source: &str,
+        policy: RequestPolicy,
+        cached: Option<&Validators>,
+        warnings: &mut Vec<String>,
+    ) -> Result<HttpBody> {
+        let mut attempt = 0;
+        let response = loop {
+            let timeout = self.attempt_timeout(policy)?;
+            let mut request = self.client.get(source).timeout(timeout);
+            if let Some(etag) = cached.and_then(|v| v.etag.as_deref()) {
+                request = request.header(IF_NONE_MATCH, etag);
+            }
+            if let Some(date) = cached.and_then(|v| v.last_modified.as_deref()) {
+                request = request.header(IF_MODIFIED_SINCE, date);
+            }
+            if let Some(accept) = policy.accept {
+                request = request.header(ACCEPT, accept);
+            }
+            let retryable = match request.send() {
+                Ok(response) if !response.status().is_server_error() => break response,
+                Ok(response) => anyhow::anyhow!("HTTP status {}", response.status()),
+                Err(_) if self.deadline_exceeded() => bail!("deadline exceeded"),
+                Err(e) => anyhow::Error::new(e),
+            };
+            self.congestion();
+            if attempt >= policy.retries {
+                return Err(retryable).with_context(|| format!("Failed to fetch {source}"));
+            }
+            attempt += 1;
+            // Back off a little more before each retry
+            std::thread::sleep(Duration::from_millis(500) * attempt);
+        };
+        // A branch renamed master -> main is the usual reason a raw URL
+        // starts to 404; say so rather than rewriting the config
+        if response.status() == StatusCode::NOT_FOUND
+            && let Some((branch, moved)) = other_default_branch(source)
+            && self.exists(&moved, policy)
+        {
+            bail!(
+                "HTTP error fetching: {source} (404 Not Found); PKGBUILD found on branch \
+                 `{branch}`; update your config"
+            );
+        }
+        let response = response
+            .error_for_status()
+            .with_context(|| format!("HTTP error fetching: {source}"))?;
+
+        // Compared parsed, as reqwest normalizes the URL (host case, default
+        // port, an empty path) whether or not it was redirected
+        if Url::parse(source).ok().as_ref() != Some(response.url()) {
+            warnings.push(format!("remote redirected to {}", response.url()));
+        }
+        if response.status() == StatusCode::NOT_MODIFIED && cached.is_some() {
+            return Ok(HttpBody::NotModified);
+        }
+        let header = |name| {
+            let value = response.headers().get(name)?;
+            value.to_str().ok().map(str::to_string)
+        };
+        // Served as HTML without the package asking for it: remembered, in
+        // case the body then fails to parse
+        let html = header(CONTENT_TYPE)
+            .is_some_and(|t| t.trim_start().to_ascii_lowercase().starts_with("text/html"))
+            && !policy.accept.is_some_and(|a| a.to_ascii_lowercase().contains("text/html"));
+        let mut html_responses = self.html_responses.lock().unwrap();
+        match html {
+            true => html_responses.insert(source.to_string()),
+            false => html_responses.remove(source),
+        };
+        drop(html_responses);
+        let validators = Validators {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+            fresh_until: cache::fresh_until(
+                header(CACHE_CONTROL).as_deref(),
+                header(AGE).as_deref(),
+            ),
+        };
+
+        // A misconfigured URL may point at a tarball; don't buffer it all
+        let too_large = || {
+            anyhow::anyhow!(
+                "response too large (over {} bytes); is the URL a PKGBUILD?",
+                self.max_size
+            )
+        };
+        if response.content_length().is_some_and(|len| len > self.max_size) {
+            return Err(too_large());
+        }
+        let mut bytes = Vec::new();
+        response
+            .take(self.max_size + 1)
+            .read_to_end(&mut bytes)
+            .context("Failed to read response body")?;
+        if bytes.len() as u64 > self.max_size {
+            return Err(too_large());
+        }
+        Ok(HttpBody::Fresh(bytes, validators))
+    }
+}
+
+/// Text of a response; invalid UTF-8 is decoded lossily with a warning
+fn decode(bytes: Vec<u8>, warnings: &mut Vec<String>) -> String {
+    match String::from_utf8(bytes) {
+        Ok(content) => content,
+        Err(e) => {
+            warnings.push("remote PKGBUILD is not valid UTF-8; decoded lossily".to_string());
+            String::from_utf8_lossy(e.as_bytes()).into_owned()
+        }
+    }
+}
+
+/// Host a network source connects to: the URL host, or the SSH destination
+/// without its user. SSH `Host` aliases are matched as written.
+fn source_host(source: &str) -> Option<String> {
+    let source = git_remote(source)
+        .or_else(|| tarball(source).map(|(url, _)| url))
+        .unwrap_or(source);
+    if let Some(target) = SshTarget::parse(source) {
+        let host = target.host.rsplit('@').next().unwrap_or(target.host);
+        return Some(host.to_ascii_lowercase());
+    }
+    Url::parse(source).ok()?.host_str().map(str::to_string)
+}
+
+/// Whether `host` matches an `allowed_hosts` entry: an exact host name, or
+/// `*.example.org` for any subdomain of example.org (but not example.org)
+pub fn host_allowed(host: &str, patterns: &[String]) -> bool {
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    patterns.iter().any(|pattern| {
+        let pattern = pattern.to_ascii_lowercase();
+        match pattern.strip_prefix("*.") {
+            Some(domain) => host
+                .strip_suffix(domain)
+                .is_some_and(|sub| sub.len() > 1 && sub.ends_with('.')),
+            None => host == pattern,
+        }
+    })
+}
+
+/// Read the certificates of a PEM file (one or a bundle) for `--cacert`
+fn load_certificates(path: &std::path::Path) -> Result<Vec<Certificate>> {
+    let pem = std::fs::read(path)
+        .with_context(|| format!("Failed to read CA certificate {}", path.display()))?;
+    let certs = Certificate::from_pem_bundle(&pem)
+        .with_context(|| format!("Invalid CA certificate {}", path.display()))?;
+    if certs.is_empty() {
+        bail!("no PEM certificates found in {}", path.display());
+    }
+    Ok(certs)
+}
+
+/// `url`, a raw GitHub or GitLab file URL, pointed at `git_ref` instead of
+/// the branch, tag or commit it names. None for other URLs, whose ref (if
+/// any) can't be located.
+pub fn with_ref(url: &str, git_ref: &str) -> Option<String> {
+    let (before, _, after) = split_ref(url)?;
+    let git_ref = git_ref.trim_matches('/');
+    Some(format!("{before}/{git_ref}/{after}"))
+}
+
+/// A raw GitHub or GitLab file URL split around its ref: everything up to
+/// the ref, the ref, and the file path in the repository
+fn split_ref(url: &str) -> Option<(String, &str, String)> {
+    let (scheme, rest) = url.split_once("://")?;
+    let (host, path) = rest.split_once('/')?;
+    let segments: Vec<&str> = path.split('/').collect();
+
+    let (before, git_ref, after) = match (host, segments.as_slice()) {
+        // GitHub's "Raw" button links to refs/heads/<branch>
+        ("raw.githubusercontent.com", [owner, repo, "refs", "heads" | "tags", git_ref, file @ ..])
+        | ("raw.githubusercontent.com", [owner, repo, git_ref, file @ ..])
+        | ("github.com", [owner, repo, "raw", git_ref, file @ ..])
+            if !file.is_empty() =>
+        {
+            let raw = if host == "github.com" { "/raw" } else { "" };
+            (format!("{owner}/{repo}{raw}"), *git_ref, file.join("/"))
+        }
+        (h, _) if h == "gitlab.com" || h.starts_with("gitlab.") => {
+            let (project, file) = path.split_once("/-/raw/")?;
+            let (git_ref, file) = file.split_once('/')?;
+            (format!("{project}/-/raw"), git_ref, file.to_string())
+        }
+        _ => return None,
+    };
+    Some((format!("{scheme}://{host}/{before}"), git_ref, after))
+}
+
+/// For a raw GitHub or GitLab URL on `master` or `main`, the other of the
+/// two and the same file's URL on it, to check when a default branch was
+/// renamed
+fn other_default_branch(url: &str) -> Option<(&'static str, String)> {
+    let (before, git_ref, after) = split_ref(url)?;
+    let branch = match git_ref {
+        "master" => "main",
+        "main" => "master",
+        _ => return None,
+    };
+    Some((branch, format!("{before}/{branch}/{after}")))
+}
+
+/// JSON API endpoint describing the releases of a PyPI project
+pub fn pypi_api_url(project: &str) -> String {
+    format!("https://pypi.org/pypi/{project}/json")
+}
+
+/// JSON API endpoint describing the releases of a crates.io crate
+pub fn crates_api_url(name: &str) -> String {
+    format!("https://crates.io/api/v1/crates/{name}")
+}
+
+/// The raw-file URL for a web page URL that shows a PKGBUILD as HTML, if
+/// `url` is one: a GitHub or GitLab `blob` (or `tree`, for the directory
+/// holding it) page, or an AUR package page. Trailing slashes are ignored.
+pub fn raw_url(url: &str) -> Option<String> {
+    let (scheme, rest) = url.split_once("://")?;
+    let rest = rest.split(['?', '#']).next().unwrap_or_default();
+    let rest = rest.trim_end_matches('/');
+    let (host, path) = rest.split_once('/')?;
+    let segments: Vec<&str> = path.split('/').collect();
+
+    match host {
+        "github.com" => match segments.as_slice() {
+            [owner, repo, "blob", git_ref, file @ ..] if !file.is_empty() => Some(format!(
+                "https://raw.githubusercontent.com/{owner}/{repo}/{git_ref}/{}",
+                file.join("/")
+            )),
+            [owner, repo, "tree", git_ref, dir @ ..] => Some(format!(
+                "https://raw.githubusercontent.com/{owner}/{repo}/{git_ref}/{}PKGBUILD",
+                dir.iter().map(|d| format!("{d}/")).collect::<String>()
+            )),
+            _ => None,
+        },
+        h if h == "gitlab.com" || h.starts_with("gitlab.") => {
+            // Projects may sit in nested groups; the page kind follows `/-/`
+            let (project, page) = path.split_once("/-/")?;
+            let (kind, target) = page.split_once('/')?;
+            let target = match kind {
+                "blob" => target.to_string(),
+                "tree" => format!("{target}/PKGBUILD"),
+                _ => return None,
+            };
+            Some(format!("{scheme}://{host}/{project}/-/raw/{target}"))
+        }
+        "aur.archlinux.org" => match segments.as_slice() {
+            ["packages", name] => Some(format!(
+                "https://aur.archlinux.org/cgit/aur.git/plain/PKGBUILD?h={name}"
+            )),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Extract the latest release version from a PyPI JSON API response
+pub fn parse_pypi_version(json: &str) -> Result<String> {
+    let value: serde_json::Value = serde_json::from_str(json).context("invalid PyPI response")?;
+    value["info"]["version"]
+        .as_str()
+        .map(str::to_string)
+        .context("PyPI response has no info.version")
+}
+
+/// Extract the latest stable version from a crates.io JSON API response,
+/// falling back to the newest version when there is no stable release
+pub fn parse_crate_version(json: &str) -> Result<String> {
+    let value: serde_json::Value =
+        serde_json::from_str(json).context("invalid crates.io response")?;
+    let krate = &value["crate"];
+    krate["max_stable_version"]
+        .as_str()
+        .or_else(|| krate["max_version"].as_str())
+        .map(str::to_string)
+        .context("crates.io response has no max_version")
+}
+
+/// Read the version from a file holding nothing else (`VERSION`): one
+/// token of letters, digits and `.`, `_`, `+` or `-`, with at least one digit
+pub fn parse_version_file(content: &str) -> Result<String> {
+    let mut words = content.split_whitespace();
+    let (Some(version), None) = (words.next(), words.next()) else {
+        match content.trim() {
+            "" => bail!("version file is empty"),
+            _ => bail!("version file holds more than one word"),
+        }
+    };
+    let allowed = |c: char| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '+' | '-');
+    if !version.chars().all(allowed) || !version.chars().any(|c| c.is_ascii_digit()) {
+        bail!("version file holds '{version}', which doesn't look like a version");
+    }
+    Ok(version.to_string())
+}
+
+/// Source for the tags of the git repository at `url`, read with
+/// `git ls-remote`
+pub fn git_tags_location(url: &str) -> String {
+    format!("git+{url}")
+}
+
+/// The repository URL of a [`git_tags_location`]
+fn git_remote(source: &str) -> Option<&str> {
+    source.strip_prefix("git+")
+}
+
+/// Source for the file at `path` in the tarball at `url`, which is read
+/// like any other source
+pub fn tarball_location(url: &str, path: &str) -> String {
+    format!("tar+{url}#{path}")
+}
+
+/// The tarball source and the path in it of a [`tarball_location`]
+fn tarball(source: &str) -> Option<(&str, &str)> {
+    source.strip_prefix("tar+")?.split_once('#')
+}
+
+/// The filesystem path of a `file://` URL or absolute path source
+fn local_path(source: &str) -> Option<&str> {
+    match source.strip_prefix("file://") {
+        Some(path) => Some(path),
+        None if source.starts_with('/') => Some(source),
+        None => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread::JoinHandle;
+
+    use super::*;
+    use crate::scratch::scratch_dir;
+
+    #[test]
+    fn test_raw_url() {
+        assert_eq!(
+            raw_url("https://github.com/o/r/blob/main/pkg/PKGBUILD").as_deref(),
+            Some("https://raw.githubusercontent.com/o/r/main/pkg/PKGBUILD")
+        );
+        assert_eq!(
+            raw_url("https://github.com/o/r/tree/v2/pkg/").as_deref(),
+            Some("https://raw.githubusercontent.com/o/r/v2/pkg/PKGBUILD")
+        );
+        assert_eq!(
+            raw_url("https://gitlab.com/g/sub/r/-/blob/main/PKGBUILD?ref_type=heads").as_deref(),
+            Some("https://gitlab.com/g/sub/r/-/raw/main/PKGBUILD")
+        );
+        assert_eq!(
+            raw_url("https://aur.archlinux.org/packages/foo/").as_deref(),
+            Some("https://aur.archlinux.org/cgit/aur.git/plain/PKGBUILD?h=foo")
+        );
+        assert_eq!(raw_url("https://raw.githubusercontent.com/o/r/main/PKGBUILD"), None);
+        assert_eq!(raw_url("https://gitlab.com/g/r/-/raw/main/PKGBUILD"), None);
+        assert_eq!(raw_url("https://example.com/blob/PKGBUILD"), None);
+    }
+
+    #[test]
+    fn test_with_ref() {
+        let url = |u| with_ref(u, "testing");
+        assert_eq!(
+            url("https://raw.githubusercontent.com/o/r/main/pkg/PKGBUILD").as_deref(),
+            Some("https://raw.githubusercontent.com/o/r/testing/pkg/PKGBUILD")
+        );
+        assert_eq!(
+            url("https://raw.githubusercontent.com/o/r/refs/heads/main/PKGBUILD").as_deref(),
+            Some("https://raw.githubusercontent.com/o/r/testing/PKGBUILD")
+        );
+        assert_eq!(
+            url("https://github.com/o/r/raw/main/PKGBUILD").as_deref(),
+            Some("https://github.com/o/r/raw/testing/PKGBUILD")
+        );
+        assert_eq!(
+            url("https://gitlab.com/g/sub/r/-/raw/main/PKGBUILD").as_deref(),
+            Some("https://gitlab.com/g/sub/r/-/raw/testing/PKGBUILD")
+        );
+        assert_eq!(url("https://aur.archlinux.org/cgit/aur.git/plain/PKGBUILD?h=foo"), None);
+        assert_eq!(url("https://example.com/main/PKGBUILD"), None);
+    }
+
+    #[test]
+    fn test_other_default_branch() {
+        assert_eq!(
+            other_default_branch("https://raw.githubusercontent.com/o/r/master/pkg/PKGBUILD"),
+            Some(("main", "https://raw.githubusercontent.com/o/r/main/pkg/PKGBUILD".to_string()))
+        );
+        assert_eq!(
+            other_default_branch("https://gitlab.com/g/r/-/raw/main/PKGBUILD"),
+            Some(("master", "https://gitlab.com/g/r/-/raw/master/PKGBUILD".to_string()))
+        );
+        assert_eq!(other_default_branch("https://github.com/o/r/raw/v1.0/PKGBUILD"), None);
+        assert_eq!(other_default_branch("https://example.com/master/PKGBUILD"), None);
+    }
+
+    #[test]
+    fn test_source_kind_of() {
+        let aur = "https://aur.archlinux.org/cgit/aur.git/plain/PKGBUILD?h=foo";
+        assert_eq!(SourceKind::of(aur), SourceKind::Aur);
+        let github = "https://raw.githubusercontent.com/o/r/main/PKGBUILD";
+        assert_eq!(SourceKind::of(github), SourceKind::GitHub);
+        let gitlab = "https://gitlab.archlinux.org/archlinux/packaging/packages/foo/-/raw/main/PKGBUILD";
+        assert_eq!(SourceKind::of(gitlab), SourceKind::GitLab);
+        assert_eq!(SourceKind::of("https://example.com/PKGBUILD"), SourceKind::Url);
+        assert_eq!(SourceKind::of("file:///srv/PKGBUILD"), SourceKind::Local);
+        assert_eq!(SourceKind::of("upstream/PKGBUILD"), SourceKind::Local);
+        assert_eq!(SourceKind::of("ssh://build.lan/srv/PKGBUILD"), SourceKind::Ssh);
+        assert_eq!(SourceKind::of("me@build.lan:pkgs/foo/PKGBUILD"), SourceKind::Ssh);
+    }
+
+    #[test]
+    fn test_parse_release_versions() {
+        let pypi = r#"{"info": {"name": "requests", "version": "2.32.3"}, "releases": {}}"#;
+        assert_eq!(parse_pypi_version(pypi).unwrap(), "2.32.3");
+        let krate = r#"{"crate": {"max_version": "15.0.0-rc1", "max_stable_version": "14.1.1"}}"#;
+        assert_eq!(parse_crate_version(krate).unwrap(), "14.1.1");
+        let unstable = r#"{"crate": {"max_version": "0.1.0-alpha", "max_stable_version": null}}"#;
+        assert_eq!(parse_crate_version(unstable).unwrap(), "0.1.0-alpha");
+        assert!(parse_pypi_version("<html>").is_err());
+    }
+
+    #[test]
+    fn test_parse_version_file() {
+        assert_eq!(parse_version_file("1.4.2\n").unwrap(), "1.4.2");
+        assert_eq!(parse_version_file("  2024.05.1-beta  ").unwrap(), "2024.05.1-beta");
+        assert!(parse_version_file("\n").is_err());
+        assert!(parse_version_file("1.4.2\n1.4.1\n").is_err());
+        assert!(parse_version_file("<html>").is_err());
+        assert!(parse_version_file("latest").is_err());
+    }
+
+    /// Answer `rounds` HTTP requests on a local port, one per connection,
+    /// with what `respond` returns for the round and the (lowercased)
+    /// request. Returns the URL of a PKGBUILD there and the server thread.
+    fn serve(
+        rounds: usize,
+        mut respond: impl FnMut(usize, &str) -> Vec<u8> + Send + 'static,
+    ) -> (String, JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/PKGBUILD", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            for round in 0..rounds {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let n = stream.read(&mut buf).unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                }
+                let request = String::from_utf8_lossy(&request).to_lowercase();
+                stream.write_all(&respond(round, &request)).unwrap();
+            }
+        });
+        (url, server)
+    }
+
+    #[test]
+    fn test_fetch_normalized_url_not_redirect() {
+        let (url, server) = serve(1, |_, _| {
+            b"HTTP/1.1 200 OK\r\nContent-Length: 9\r\nConnection: close\r\n\r\npkgver=1\n".to_vec()
+        });
+        let port = Url::parse(&url).unwrap().port().unwrap();
+
+        // Uppercase host and an empty path, which reqwest writes as `/`
+        let fetcher = Fetcher::new(&Options::default()).unwrap();
+        let mut warnings = Vec::new();
+        let url = format!("HTTP://LOCALHOST:{port}");
+        let content = fetcher.fetch(&url, fetcher.policy(), &mut warnings).unwrap();
+        server.join().unwrap();
+        assert_eq!(content, "pkgver=1\n");
+        assert!(warnings.is_empty(), "{warnings:?}");
+    }
+
+    #[test]
+    fn test_fetch_gzip_response() {
+        use flate2::Compression;
+        use flate2::write::GzEncoder;
+
+        let pkgbuild = "pkgname=foo\npkgver=1.2.3\npkgrel=1\n";
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(pkgbuild.as_bytes()).unwrap();
+        let body = encoder.finish().unwrap();
+
+        // Serve one gzip-encoded response, but only to a client that asks for it
+        let (url, server) = serve(1, move |_, request| {
+            assert!(request.contains("accept-encoding:") && request.contains("gzip"));
+            let header = format!(
+                "HTTP/1.1 200 OK\r\nContent-Encoding: gzip\r\nContent-Length: {}\r\n\
+                 Connection: close\r\n\r\n",
+                body.len()
+            );
+            [header.as_bytes(), &body].concat()
+        });
+
+        let fetcher = Fetcher::new(&Options::default()).unwrap();
+        let mut warnings = Vec::new();
+        let content = fetcher.fetch(&url, fetcher.policy(), &mut warnings).unwrap();
+        server.join().unwrap();
+        assert_eq!(content, pkgbuild);
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn test_fetch_accept_and_html() {
+        // Echo the Accept header back, served as an HTML page
+        let (url, server) = serve(2, |_, request| {
+            let accept = request
+                .lines()
+                .find_map(|l| l.strip_prefix("accept: "))
+                .unwrap_or_default();
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\n\
+                 Content-Length: {}\r\nConnection: close\r\n\r\n{accept}",
+                accept.len()
+            )
+            .into_bytes()
+        });
+
+        // The body is read whatever its type; a parse error gets the hint
+        let fetcher = Fetcher::new(&Options::default()).unwrap();
+        let content = fetcher.fetch(&url, fetcher.policy(), &mut Vec::new()).unwrap();
+        assert_eq!(content, DEFAULT_ACCEPT);
+        assert!(fetcher.parse_hint(&url).contains("check the URL, or set `accept`"));
+
+        let policy = RequestPolicy {
+            accept: Some("text/html"),
+            ..fetcher.policy()
+        };
+        let content = fetcher.fetch(&url, policy, &mut Vec::new()).unwrap();
+        server.join().unwrap();
+        assert_eq!(content, "text/html");
+        assert_eq!(fetcher.parse_hint(&url), "");
+    }
+
+    #[test]
+    fn test_fetch_cached_revalidates() {
+        // 200 with an ETag, then 304 to a request that sends it back
+        let (url, server) = serve(2, |round, request| {
+            let response = if round == 0 {
+                assert!(!request.contains("if-none-match"));
+                "HTTP/1.1 200 OK\r\nETag: \"v1\"\r\nContent-Length: 9\r\n\
+                 Connection: close\r\n\r\npkgver=2\n"
+            } else {
+                assert!(request.contains("if-none-match: \"v1\""));
+                "HTTP/1.1 304 Not Modified\r\nConnection: close\r\n\r\n"
+            };
+            response.as_bytes().to_vec()
+        });
+
+        let mut fetcher = Fetcher::new(&Options::default()).unwrap();
+        fetcher.use_cache(ResultCache::default());
+        let policy = fetcher.policy();
+        for _ in 0..2 {
+            let content = fetcher.fetch_cached("foo", "h", &url, policy, &mut Vec::new());
+            assert_eq!(content.unwrap(), "pkgver=2\n");
+        }
+        server.join().unwrap();
+        assert_eq!(fetcher.cache_hits(), 1);
+        let cache = fetcher.cache().unwrap();
+        assert_eq!(cache.packages["foo"].validators.etag.as_deref(), Some("\"v1\""));
+    }
+
+    #[test]
+    fn test_fetch_cached_fresh() {
+        // Within max-age the cached copy is used without a second request
+        let (url, server) = serve(1, |_, _| {
+            b"HTTP/1.1 200 OK\r\nCache-Control: max-age=3600\r\n\
+              Content-Length: 9\r\nConnection: close\r\n\r\npkgver=2\n"
+                .to_vec()
+        });
+
+        let mut fetcher = Fetcher::new(&Options::default()).unwrap();
+        fetcher.use_cache(ResultCache::default());
+        let policy = fetcher.policy();
+        for _ in 0..2 {
+            let content = fetcher.fetch_cached("foo", "h", &url, policy, &mut Vec::new());
+            assert_eq!(content.unwrap(), "pkgver=2\n");
+        }
+        server.join().unwrap();
+        assert_eq!(fetcher.concurrency_stats().requests, 1);
+        assert_eq!(fetcher.cache_hits(), 1);
+    }
+
+    #[test]
+    fn test_cacert_must_hold_certificates() {
+        let dir = scratch_dir("cacert");
+        let path = dir.join("cacert.pem");
+        std::fs::write(&path, "not a certificate\n").unwrap();
+        let opts = Options {
+            cacert: Some(path.clone()),
+            ..Options::default()
+        };
+        let err = Fetcher::new(&opts).err().unwrap();
+        assert!(err.to_string().contains("no PEM certificates"));
+        let opts = Options {
+            cacert: Some(path.with_extension("missing")),
+            ..Options::default()
+        };
+        assert!(Fetcher::new(&opts).is_err());
+    }
+
+    #[test]
+    fn test_adaptive_limit() {
+        let adaptive = AdaptiveLimit::new();
+        let limit = || adaptive.state.lock().unwrap().limit;
+        for _ in 0..ADAPTIVE_START_JOBS {
+            adaptive.success(Duration::from_millis(100));
+        }
+        assert_eq!(limit(), ADAPTIVE_START_JOBS + 1);
+        // Slow successes don't raise the limit
+        for _ in 0..10 {
+            adaptive.success(ADAPTIVE_FAST * 2);
+        }
+        assert_eq!(limit(), ADAPTIVE_START_JOBS + 1);
+
+        // A burst of failures halves the limit once
+        adaptive.congestion();
+        adaptive.congestion();
+        assert_eq!(limit(), 1);
+        let state = adaptive.state.lock().unwrap();
+        assert_eq!((state.peak, state.backoffs), (3, 1));
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let limit = RateLimit::new(10.0);
+        assert_eq!(limit.reserve(), Duration::ZERO);
+        // Tokens reserved back to back are handed out 100ms apart
+        let second = limit.reserve();
+        let third = limit.reserve();
+        assert!(second > Duration::from_millis(80) && second <= Duration::from_millis(100));
+        assert!(third > Duration::from_millis(180) && third <= Duration::from_millis(200));
+        let state = limit.state.lock().unwrap();
+        assert_eq!(state.delayed, 2);
+        assert_eq!(state.waited, second + third);
+    }
+
+    #[test]
+    fn test_host_allowed() {
+        let patterns = ["aur.archlinux.org".to_string(), "*.example.org".to_string()];
+        assert!(host_allowed("aur.archlinux.org", &patterns));
+        assert!(host_allowed("AUR.archlinux.org.", &patterns));
+        assert!(host_allowed("git.example.org", &patterns));
+        assert!(host_allowed("a.b.example.org", &patterns));
+        assert!(!host_allowed("example.org", &patterns));
+        assert!(!host_allowed("badexample.org", &patterns));
+        assert!(!host_allowed("archlinux.org", &patterns));
+
+        assert_eq!(source_host("ssh://me@build.lan:22/x").as_deref(), Some("build.lan"));
+        let host = source_host("https://Git.Example.org:8443/x");
+        assert_eq!(host.as_deref(), Some("git.example.org"));
+        let host = source_host(&git_tags_location("git@github.com:o/r.git"));
+        assert_eq!(host.as_deref(), Some("github.com"));
+
+        let opts = Options {
+            allowed_hosts: Some(patterns.to_vec()),
+            ..Options::default()
+        };
+        let fetcher = Fetcher::new(&opts).unwrap();
+        let err = fetcher
+            .fetch("https://evil.test/PKGBUILD", fetcher.policy(), &mut Vec::new())
+            .unwrap_err();
+        assert_eq!(err.to_string(), "host evil.test is not in allowed_hosts");
+    }
+
+    #[test]
+    fn test_fetch_honors_policy_timeout() {
+        // Accepts connections but never answers
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/PKGBUILD", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let _held: Vec<_> = listener.incoming().take(2).collect();
+            std::thread::sleep(Duration::from_secs(30));
+        });
+
+        let fetcher = Fetcher::new(&Options::default()).unwrap();
+        let policy = RequestPolicy {
+            timeout: Duration::from_millis(200),
+            retries: 1,
+            accept: None,
+        };
+        let started = Instant::now();
+        let result = fetcher.fetch(&url, policy, &mut Vec::new());
+        assert!(result.is_err());
+        // Two 200ms attempts and one 500ms backoff, far below the 30s default
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert!(started.elapsed() >= Duration::from_millis(900));
+    }
+}
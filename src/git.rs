@@ -0,0 +1,113 @@
+use std::process::Command;
+use std::time::Duration;
+
+use anyhow::{Result, bail};
+use regex::Regex;
+
+use crate::process::{self, CommandError};
+use crate::vercmp::rpmvercmp;
+
+/// List the tags of the repository at `url` with `git ls-remote --tags`,
+/// giving up after `timeout`. Nothing is cloned, and git never prompts for
+/// credentials. More than `max_size` bytes of output is an error.
+pub fn ls_remote_tags(
+    url: &str,
+    timeout: Duration,
+    max_size: u64,
+) -> Result<Vec<u8>, CommandError> {
+    let mut cmd = Command::new("git");
+    cmd.args(["ls-remote", "--tags", "--", url]).env("GIT_TERMINAL_PROMPT", "0");
+
+    let what = format!("git ls-remote {url}");
+    let finished = process::run(&mut cmd, &what, timeout, max_size)?;
+    match finished.code {
+        Some(0) if finished.overflow => Err(CommandError {
+            message: format!("tag list of {url} too large (over {max_size} bytes)"),
+            connection: false,
+        }),
+        Some(0) => Ok(finished.stdout),
+        // The first fatal error says what went wrong; advice follows it
+        _ => Err(CommandError {
+            message: match finished
+                .stderr
+                .lines()
+                .find(|l| l.starts_with("fatal:"))
+                .map_or(finished.detail(), str::trim)
+            {
+                "" => format!("{what} failed"),
+                detail => format!("{what} failed: {detail}"),
+            },
+            // git exits 128 for unreachable hosts and missing repositories alike
+            connection: finished.code.is_none(),
+        }),
+    }
+}
+
+/// Tag names in `git ls-remote --tags` output (`<sha>\trefs/tags/<name>`),
+/// in order and without duplicates. Annotated tags are listed twice, the
+/// second time peeled with a `^{}` suffix; both count as one tag.
+fn parse_tags(content: &str) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::new();
+    for line in content.lines() {
+        let Some((_, reference)) = line.split_once('\t') else {
+            continue;
+        };
+        let Some(tag) = reference.trim().strip_prefix("refs/tags/") else {
+            continue;
+        };
+        let tag = tag.strip_suffix("^{}").unwrap_or(tag);
+        if !tag.is_empty() && !tags.iter().any(|t| t == tag) {
+            tags.push(tag.to_string());
+        }
+    }
+    tags
+}
+
+/// The newest version among the tags in `git ls-remote --tags` output, by
+/// vercmp. Only version-like tags count: digits separated by dots or
+/// underscores, optionally prefixed with `v` (`v1.2.3`, `2024.05`);
+/// pre-releases and other names (`latest`, `1.0-rc1`) are ignored. The
+/// version is returned without its `v`.
+pub fn newest_version_tag(content: &str) -> Result<String> {
+    let version_re = Regex::new(r"^[vV]?(\d+(?:[._]\d+)*)$").expect("valid version regex");
+    let tags = parse_tags(content);
+    let newest = tags
+        .iter()
+        .filter_map(|tag| version_re.captures(tag))
+        .map(|c| c[1].to_string())
+        .max_by(|a, b| rpmvercmp(a, b));
+    match newest {
+        Some(version) => Ok(version),
+        None if tags.is_empty() => bail!("the repository has no tags"),
+        None => bail!("none of the {} tags looks like a version", tags.len()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LS_REMOTE: &str = "\
+3f1e0d2c\trefs/tags/v1.9.0
+9a8b7c6d\trefs/tags/v1.10.0
+1b2c3d4e\trefs/tags/v1.10.0^{}
+5e6f7a8b\trefs/tags/v2.0.0-rc1
+7c8d9e0f\trefs/tags/latest
+";
+
+    #[test]
+    fn test_parse_tags() {
+        assert_eq!(parse_tags(LS_REMOTE), ["v1.9.0", "v1.10.0", "v2.0.0-rc1", "latest"]);
+        assert!(parse_tags("").is_empty());
+    }
+
+    #[test]
+    fn test_newest_version_tag() {
+        // 1.10 is newer than 1.9, unlike in a string comparison
+        assert_eq!(newest_version_tag(LS_REMOTE).unwrap(), "1.10.0");
+        let calendar = "a\trefs/tags/2024.05\nb\trefs/tags/2023.12\n";
+        assert_eq!(newest_version_tag(calendar).unwrap(), "2024.05");
+        assert!(newest_version_tag("").is_err());
+        assert!(newest_version_tag("a\trefs/tags/nightly\n").is_err());
+    }
+}
@@ -0,0 +1,263 @@
+use std::io::Write;
+use std::path::Path;
+use std::process::ExitCode;
+
+use anyhow::{Context, Result, bail};
+use colored::Colorize;
+use regex::Regex;
+
+use crate::cli::Options;
+use crate::fetch::{self, Fetcher};
+use crate::pkgbuild::{self, ParseOptions};
+
+/// A possible upstream for a package, as an `rchan.yaml` key and value
+#[derive(Debug, Clone, PartialEq)]
+struct Guess {
+    /// `remote_pkgbuild`, `pypi` or `crate`
+    key: &'static str,
+    value: String,
+    /// Why this was suggested
+    reason: String,
+}
+
+/// Run `rchan guess-remote <dir>`: propose a remote for a package from the
+/// `url=` and `source=` fields of its PKGBUILD, check which candidates can be
+/// fetched, and offer to write a draft config
+pub fn run_guess_remote(base: &Path, opts: &Options) -> Result<ExitCode> {
+    let [dir] = opts.args.as_slice() else {
+        bail!("guess-remote needs exactly one package directory");
+    };
+    let dir = base.join(dir);
+    let pkgbuild_path = dir.join("PKGBUILD");
+    let content = std::fs::read_to_string(&pkgbuild_path)
+        .with_context(|| format!("Failed to read {}", pkgbuild_path.display()))?;
+    let Some(pkgname) = pkgbuild::parse_pkgname(&content) else {
+        bail!("no pkgname in {}", pkgbuild_path.display());
+    };
+
+    if !opts.no_banner {
+        println!(
+            "{} {}",
+            "rchan guess-remote".bold().cyan(),
+            "- remote source finder".dimmed()
+        );
+        println!("{} {}\n", "Package:".bold(), dir.display());
+    }
+
+    let guesses = guesses(&content, &pkgname);
+    let fetcher = Fetcher::new(opts)?;
+    let mut chosen = None;
+    for guess in &guesses {
+        let line = format!("{}: {} ({})", guess.key, guess.value, guess.reason);
+        if opts.offline {
+            println!("{} {line}", "GUESS".dimmed());
+            chosen.get_or_insert(guess);
+            continue;
+        }
+        match probe(&fetcher, guess) {
+            Ok(version) => {
+                println!("{} {line}, remote version {version}", "OK".green().bold());
+                chosen.get_or_insert(guess);
+            }
+            Err(e) => println!("{} {line} - {e:#}", "ERROR".red().bold()),
+        }
+    }
+
+    let Some(guess) = chosen else {
+        println!("\nNo candidate could be fetched; not writing {}.", opts.config_name);
+        return Ok(ExitCode::from(crate::EXIT_ERRORS));
+    };
+    let draft = format!("{}: {}\n", guess.key, guess.value);
+    let config_path = dir.join(&opts.config_name);
+    println!("\n{} {}", "Draft".bold(), config_path.display());
+    print!("{draft}");
+
+    if config_path.exists() {
+        println!("{} already exists; not overwriting it.", config_path.display());
+    } else if !opts.dry_run && confirm(&format!("Write {}?", config_path.display()))? {
+        std::fs::write(&config_path, draft)
+            .with_context(|| format!("Failed to write {}", config_path.display()))?;
+        println!("Wrote {}", config_path.display());
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Fetch a candidate and return the version it would report
+fn probe(fetcher: &Fetcher, guess: &Guess) -> Result<String> {
+    let (source, parse): (String, fn(&str) -> Result<String>) = match guess.key {
+        "pypi" => (fetch::pypi_api_url(&guess.value), fetch::parse_pypi_version),
+        "crate" => (fetch::crates_api_url(&guess.value), fetch::parse_crate_version),
+        _ => (guess.value.clone(), |content| {
+            Ok(pkgbuild::parse_remote(content, &ParseOptions::default())?.to_string())
+        }),
+    };
+    let content = fetcher.fetch(&source, fetcher.policy(), &mut Vec::new())?;
+    parse(&content)
+}
+
+/// Ask a yes/no question on stdin; anything but `y`/`yes` (or EOF) is no
+fn confirm(question: &str) -> Result<bool> {
+    print!("{question} [y/N] ");
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    if std::io::stdin().read_line(&mut answer)? == 0 {
+        println!();
+    }
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Candidate remotes, best first: upstream projects named by `url=` and
+/// `source=`, then the AUR package of the same name
+fn guesses(content: &str, pkgname: &str) -> Vec<Guess> {
+    let url_re = Regex::new(r#"https?://[^\s"'()]+"#).expect("valid URL regex");
+    let fields = ["url", "source"]
+        .iter()
+        .filter_map(|name| pkgbuild::parse_field(content, name))
+        .map(|text| expand_vars(&text, content, pkgname))
+        .collect::<Vec<_>>();
+
+    let mut guesses: Vec<Guess> = Vec::new();
+    for field in &fields {
+        for url in url_re.find_iter(field) {
+            if let Some(guess) = guess_from_url(url.as_str())
+                && !guesses.contains(&guess)
+            {
+                guesses.push(guess);
+            }
+        }
+    }
+    guesses.push(Guess {
+        key: "remote_pkgbuild",
+        value: format!("https://aur.archlinux.org/cgit/aur.git/plain/PKGBUILD?h={pkgname}"),
+        reason: format!("AUR package {pkgname}"),
+    });
+    guesses
+}
+
+/// Suggest a remote for a project hosted at `url`, if the host is known
+fn guess_from_url(url: &str) -> Option<Guess> {
+    let rest = url.split_once("://")?.1;
+    let rest = rest.split(['?', '#']).next().unwrap_or_default();
+    let mut parts = rest.split('/').filter(|p| !p.is_empty());
+    let host = parts.next()?;
+    let path: Vec<&str> = parts.collect();
+
+    match host {
+        "github.com" | "raw.githubusercontent.com" | "codeload.github.com" => {
+            let [owner, repo, ..] = path.as_slice() else { return None };
+            let repo = repo.trim_end_matches(".git");
+            Some(Guess {
+                key: "remote_pkgbuild",
+                value: format!("https://raw.githubusercontent.com/{owner}/{repo}/HEAD/PKGBUILD"),
+                reason: format!("PKGBUILD in GitHub project {owner}/{repo}"),
+            })
+        }
+        "gitlab.com" => {
+            let [owner, repo, ..] = path.as_slice() else { return None };
+            let repo = repo.trim_end_matches(".git");
+            Some(Guess {
+                key: "remote_pkgbuild",
+                value: format!("https://gitlab.com/{owner}/{repo}/-/raw/HEAD/PKGBUILD"),
+                reason: format!("PKGBUILD in GitLab project {owner}/{repo}"),
+            })
+        }
+        "files.pythonhosted.org" | "pypi.io" | "pypi.org" => {
+            let project = match path.as_slice() {
+                ["packages", "source", _, project, ..] => project,
+                ["project", project, ..] => project,
+                _ => return None,
+            };
+            Some(Guess {
+                key: "pypi",
+                value: project.to_string(),
+                reason: "PyPI project".to_string(),
+            })
+        }
+        "static.crates.io" | "crates.io" => {
+            let name = match path.as_slice() {
+                ["crates", name, ..] | ["api", "v1", "crates", name, ..] => name,
+                _ => return None,
+            };
+            Some(Guess {
+                key: "crate",
+                value: name.to_string(),
+                reason: "crates.io crate".to_string(),
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Substitute `$var` and `${var}` with the values of top-level assignments,
+/// so `url=https://github.com/foo/$pkgname` points at the project
+fn expand_vars(text: &str, content: &str, pkgname: &str) -> String {
+    let var_re = Regex::new(r"\$\{([A-Za-z_][A-Za-z0-9_]*)\}|\$([A-Za-z_][A-Za-z0-9_]*)")
+        .expect("valid variable regex");
+    let mut text = text.to_string();
+    // A second pass resolves variables defined in terms of others
+    for _ in 0..2 {
+        text = var_re
+            .replace_all(&text, |c: &regex::Captures| {
+                let name = c.get(1).or(c.get(2)).map_or("", |m| m.as_str());
+                let value = match name {
+                    "pkgname" => Some(pkgname.to_string()),
+                    _ => pkgbuild::parse_field(content, name).filter(|v| !v.starts_with('(')),
+                };
+                match value {
+                    Some(v) => v.trim_matches(|c| c == '"' || c == '\'').to_string(),
+                    None => c[0].to_string(),
+                }
+            })
+            .into_owned();
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_guesses() {
+        let content = r#"pkgname=foo
+_owner=acme
+pkgver=1.2.0
+url="https://github.com/${_owner}/$pkgname"
+source=("$pkgname-$pkgver.tar.gz::https://github.com/$_owner/$pkgname/archive/v$pkgver.tar.gz"
+        "https://files.pythonhosted.org/packages/source/f/foo-py/foo-py-$pkgver.tar.gz")
+"#;
+        let found: Vec<(&str, String)> = guesses(content, "foo")
+            .into_iter()
+            .map(|g| (g.key, g.value))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (
+                    "remote_pkgbuild",
+                    "https://raw.githubusercontent.com/acme/foo/HEAD/PKGBUILD".to_string()
+                ),
+                ("pypi", "foo-py".to_string()),
+                (
+                    "remote_pkgbuild",
+                    "https://aur.archlinux.org/cgit/aur.git/plain/PKGBUILD?h=foo".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_guess_from_url() {
+        let value = |url| guess_from_url(url).map(|g| g.value);
+        assert_eq!(
+            value("https://gitlab.com/acme/bar.git").as_deref(),
+            Some("https://gitlab.com/acme/bar/-/raw/HEAD/PKGBUILD")
+        );
+        assert_eq!(
+            value("https://static.crates.io/crates/ripgrep/ripgrep-14.1.0.crate").as_deref(),
+            Some("ripgrep")
+        );
+        assert_eq!(value("https://example.com/foo.tar.gz"), None);
+        assert_eq!(value("https://github.com/acme"), None);
+    }
+}
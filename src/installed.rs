@@ -0,0 +1,190 @@
+use std::cmp::Ordering;
+use std::path::Path;
+use std::process::{Command, ExitCode};
+
+use anyhow::{Context, Result, bail};
+use colored::Colorize;
+
+use crate::cli::Options;
+use crate::pkgbuild::{self, PkgVersion};
+use crate::vercmp::vercmp;
+
+/// Installed state of a local package relative to its PKGBUILD
+enum InstalledState {
+    /// Installed version matches the PKGBUILD
+    Current,
+    /// Installed at an older version than the PKGBUILD
+    Outdated(PkgVersion),
+    /// Installed at a newer version than the PKGBUILD
+    Newer(PkgVersion),
+    /// Not installed, but an artifact for this version is in pkgs/
+    BuiltNotInstalled,
+    /// Neither installed nor built
+    NotInstalled,
+}
+
+/// Run `rchan installed`: compare each local PKGBUILD version with the
+/// version installed on this system (`pacman -Q`)
+pub fn run_installed(base: &Path, opts: &Options) -> Result<ExitCode> {
+    if !opts.no_banner {
+        println!(
+            "{} {}",
+            "rchan installed".bold().cyan(),
+            "- installed version checker".dimmed()
+        );
+        println!("{} {}\n", "Working directory:".bold(), base.display());
+    }
+
+    let mut entries: Vec<_> = std::fs::read_dir(base)?
+        .filter_map(|e| e.ok())
+        .filter(|e| opts.include_hidden || !e.file_name().to_string_lossy().starts_with('.'))
+        .map(|e| e.path())
+        .filter(|path| path.is_dir() && path.join("PKGBUILD").exists())
+        .collect();
+    entries.sort();
+
+    let pkgs_dir = base.join("pkgs");
+    let parse_options = opts.parse_options();
+    let mut counts = [0usize; 5];
+
+    for path in &entries {
+        let name = path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        let pkgbuild_path = path.join("PKGBUILD");
+
+        let state = pkgbuild::parse_local(&pkgbuild_path, &parse_options).and_then(|local| {
+            let content = std::fs::read_to_string(&pkgbuild_path)?;
+            let pkgname = pkgbuild::parse_pkgname(&content).unwrap_or_else(|| name.clone());
+            let state = installed_state(&pkgname, &local, &pkgs_dir)?;
+            Ok((local, state))
+        });
+
+        match state {
+            Ok((local, InstalledState::Current)) => {
+                counts[0] += 1;
+                if !opts.quiet {
+                    println!(
+                        "{} {} ({})",
+                        "INSTALLED".blue().bold(),
+                        name.white(),
+                        local.to_string().dimmed()
+                    );
+                }
+            }
+            Ok((local, InstalledState::Outdated(installed))) => {
+                counts[1] += 1;
+                println!(
+                    "{} {} installed {}, PKGBUILD {}",
+                    "OUTDATED".yellow().bold(),
+                    name.white().bold(),
+                    installed.to_string().yellow(),
+                    local.to_string().green()
+                );
+            }
+            Ok((local, InstalledState::Newer(installed))) => {
+                counts[1] += 1;
+                println!(
+                    "{} {} installed {}, PKGBUILD {}",
+                    "NEWER".magenta().bold(),
+                    name.white().bold(),
+                    installed.to_string().magenta(),
+                    local.to_string().dimmed()
+                );
+            }
+            Ok((local, InstalledState::BuiltNotInstalled)) => {
+                counts[2] += 1;
+                println!(
+                    "{} {} {} is in pkgs/ but not installed",
+                    "BUILT".green().bold(),
+                    name.white().bold(),
+                    local.to_string().dimmed()
+                );
+            }
+            Ok((_, InstalledState::NotInstalled)) => {
+                counts[3] += 1;
+                if !opts.quiet {
+                    println!("{} {}", "NOT INSTALLED".dimmed().bold(), name.white());
+                }
+            }
+            Err(e) => {
+                counts[4] += 1;
+                println!("{} {} - {}", "ERROR".red().bold(), name.white(), e);
+            }
+        }
+    }
+
+    if !opts.quiet {
+        println!();
+    }
+    println!(
+        "{}: {} packages, {} installed, {} differing, {} built but not installed, \
+         {} not installed, {} errors",
+        "Summary".bold(),
+        entries.len(),
+        counts[0].to_string().blue(),
+        counts[1].to_string().yellow(),
+        counts[2].to_string().green(),
+        counts[3],
+        counts[4].to_string().red()
+    );
+
+    Ok(if counts[4] > 0 {
+        ExitCode::from(crate::EXIT_ERRORS)
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+/// Determine the installed state of `pkgname` against the local version
+fn installed_state(pkgname: &str, local: &PkgVersion, pkgs_dir: &Path) -> Result<InstalledState> {
+    match query_installed(pkgname)? {
+        Some(installed) => Ok(match vercmp(&installed, local) {
+            Ordering::Equal => InstalledState::Current,
+            Ordering::Less => InstalledState::Outdated(installed),
+            Ordering::Greater => InstalledState::Newer(installed),
+        }),
+        None if is_built(pkgname, local, pkgs_dir) => Ok(InstalledState::BuiltNotInstalled),
+        None => Ok(InstalledState::NotInstalled),
+    }
+}
+
+/// Query the installed version of a package, `None` when not installed
+fn query_installed(pkgname: &str) -> Result<Option<PkgVersion>> {
+    let output = Command::new("pacman")
+        .arg("-Q")
+        .arg(pkgname)
+        .output()
+        .context("Failed to execute pacman")?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if stderr.contains("was not found") {
+            return Ok(None);
+        }
+        bail!("pacman -Q failed: {}", stderr.trim());
+    }
+
+    // Output format: "<pkgname> <version>"
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let version = stdout
+        .split_whitespace()
+        .nth(1)
+        .context("Unexpected pacman -Q output")?;
+    version.parse().map(Some)
+}
+
+/// Whether pkgs/ holds an artifact of `pkgname` at version `local`
+fn is_built(pkgname: &str, local: &PkgVersion, pkgs_dir: &Path) -> bool {
+    let prefix = format!("{pkgname}-{local}-");
+    std::fs::read_dir(pkgs_dir)
+        .map(|entries| {
+            entries.filter_map(|e| e.ok()).any(|e| {
+                let fname = e.file_name().to_string_lossy().to_string();
+                fname.starts_with(&prefix) && fname.contains(".pkg.tar")
+            })
+        })
+        .unwrap_or(false)
+}
@@ -1,110 +1,578 @@
+mod archive;
+mod bench;
 mod builder;
+mod cache;
+mod cli;
+mod completions;
 mod config;
+mod effective;
+mod fetch;
+mod git;
+mod guess;
+mod installed;
+mod output;
 mod pkgbuild;
+mod process;
+mod prune;
+mod report;
 mod scanner;
+#[cfg(test)]
+mod scratch;
+mod source;
+mod srcinfo;
+mod ssh;
+mod state;
+mod tui;
+mod updater;
+mod vercmp;
+mod watch;
+mod why;
 
-use anyhow::Result;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::process::ExitCode;
+
+use anyhow::{Result, bail};
+use cli::{ColorChoice, Command, Options, OutputFormat};
 use colored::Colorize;
-use scanner::ScanResult;
+use fetch::{Fetcher, SourceKind};
+use output::OutputBuffer;
+use report::{JsonLine, PackageReport, Summary};
+use scanner::{PackageCheck, ProbeStatus, ScanResult};
+use state::State;
+
+/// Exit status when updates are available
+const EXIT_UPDATES: u8 = 2;
+/// Exit status when any package failed to check (or warned, with --strict)
+const EXIT_ERRORS: u8 = 3;
 
-fn main() -> Result<()> {
+fn main() -> Result<ExitCode> {
     let cwd = std::env::current_dir()?;
-    let args: Vec<String> = std::env::args().collect();
 
-    // Subcommand dispatch
-    if args.len() > 1 {
-        match args[1].as_str() {
-            "build" => return builder::run_build(&cwd),
-            "--help" | "-h" => {
-                print_help();
-                return Ok(());
-            }
-            other => {
-                eprintln!("{} unknown command '{}'\n", "error:".red().bold(), other);
-                print_help();
-                std::process::exit(1);
-            }
+    let (command, mut opts) = match cli::parse(std::env::args().skip(1)) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("{} {}\n", "error:".red().bold(), e);
+            cli::print_help();
+            std::process::exit(1);
+        }
+    };
+
+    for dir in &opts.dirs {
+        if !cwd.join(dir).is_dir() {
+            bail!("'-C {}': not a directory", dir.display());
         }
     }
+    let cwd = match opts.dirs.as_slice() {
+        [dir] => cwd.join(dir),
+        _ => cwd,
+    };
 
-    // Default behavior: check for updates
-    println!(
-        "{} {}",
-        "rchan".bold().cyan(),
-        "- PKGBUILD update checker".dimmed()
-    );
-    println!("{} {}\n", "Scanning:".bold(), cwd.display());
+    let global = config::GlobalConfig::load()?;
+    // Which layer each setting comes from is lost once they are merged
+    let origins = opts.dump_config.then(|| effective::Origins::new(&opts, &global));
+    opts.apply_global(&global);
+    match opts.color {
+        ColorChoice::Auto => {}
+        ColorChoice::Always => colored::control::set_override(true),
+        ColorChoice::Never => colored::control::set_override(false),
+    }
+    if opts.insecure {
+        eprintln!(
+            "{} --insecure: HTTPS certificates are NOT verified; remote PKGBUILDs could be \
+             tampered with in transit",
+            "WARNING".red().bold()
+        );
+    }
+
+    // Subcommand dispatch
+    match command {
+        Command::Build => {
+            builder::run_build(&cwd, &opts)?;
+            Ok(ExitCode::SUCCESS)
+        }
+        Command::Update => updater::run_update(&cwd, &opts),
+        Command::Installed => installed::run_installed(&cwd, &opts),
+        Command::Prune => prune::run_prune(&cwd, &opts),
+        Command::Watch => watch::run_watch(&cwd, &opts),
+        Command::Bench => bench::run_bench(&cwd, &opts),
+        Command::GuessRemote => guess::run_guess_remote(&cwd, &opts),
+        Command::RefreshSrcinfo => srcinfo::run_refresh_srcinfo(&cwd, &opts),
+        Command::Why => why::run_why(&cwd, &opts),
+        Command::Completions => completions::run_completions(&opts),
+        Command::Complete => completions::run_complete(&cwd, &opts),
+        Command::Help => {
+            cli::print_help();
+            Ok(ExitCode::SUCCESS)
+        }
+        Command::Check => match (&opts.dump_remote, &origins) {
+            (Some(name), _) => dump_remote(&cwd, &opts, name),
+            (None, Some(origins)) => effective::run_dump_config(&cwd, &opts, origins),
+            (None, None) => run_check(&cwd, &opts),
+        },
+    }
+}
 
-    let results = scanner::scan_directory(&cwd)?;
+/// `--dump-remote NAME`: write the package's remote to stdout exactly as
+/// fetched, with where it came from and any warnings on stderr
+fn dump_remote(cwd: &std::path::Path, opts: &Options, name: &str) -> Result<ExitCode> {
+    let fetcher = Fetcher::new(opts)?;
+    let (source, content, warnings) = scanner::fetch_remote(cwd, opts, &fetcher, name)?;
+    eprintln!("{} {source}", "Fetched".dimmed());
+    for warning in warnings {
+        eprintln!("{} {warning}", "WARN".yellow().bold());
+    }
+    std::io::stdout().write_all(&content)?;
+    Ok(ExitCode::SUCCESS)
+}
 
-    if results.is_empty() {
+/// Default behavior: check for updates
+fn run_check(cwd: &std::path::Path, opts: &Options) -> Result<ExitCode> {
+    if !opts.no_banner {
         println!(
-            "{}",
-            "No subdirectories with rchan.yaml + PKGBUILD found.".yellow()
+            "{} {}",
+            "rchan".bold().cyan(),
+            "- PKGBUILD update checker".dimmed()
         );
-        return Ok(());
-    }
-
-    let mut updated_count = 0;
-    let mut error_count = 0;
-    let mut up_to_date_count = 0;
-
-    for result in &results {
-        match result {
-            ScanResult::Updated {
-                name,
-                local_ver,
-                remote_ver,
-            } => {
-                println!(
-                    "{} {} {} -> {}",
-                    "UPDATED".green().bold(),
-                    name.white().bold(),
-                    local_ver.dimmed(),
-                    remote_ver.green()
-                );
-                updated_count += 1;
+        match &opts.pkgbuild {
+            Some(path) if opts.stdin => {
+                println!("{} {} (config from stdin)\n", "Checking:".bold(), path.display())
             }
-            ScanResult::UpToDate { name, local_ver } => {
-                println!(
-                    "{} {} ({})",
-                    "OK".blue().bold(),
-                    name.white(),
-                    local_ver.dimmed()
-                );
-                up_to_date_count += 1;
+            _ if opts.dirs.len() > 1 => {
+                let dirs: Vec<_> = opts.dirs.iter().map(|dir| cwd.join(dir)).collect();
+                let dirs: Vec<_> = dirs.iter().map(|dir| dir.display().to_string()).collect();
+                println!("{} {}\n", "Scanning:".bold(), dirs.join(", "))
             }
-            ScanResult::Error { name, message } => {
-                println!("{} {} - {}", "ERROR".red().bold(), name.white(), message);
-                error_count += 1;
+            _ => println!("{} {}\n", "Scanning:".bold(), cwd.display()),
+        }
+    }
+
+    let mut output = OutputBuffer::new(!opts.unordered, opts.reverse, opts.max_ok_lines);
+
+    let state_path = cwd.join(state::STATE_FILE);
+    // A broken state file only costs the "since last run" markers. A one-off
+    // `--stdin` check isn't part of the tree's history.
+    let state = match opts.stdin {
+        true => State::default(),
+        false => State::load(&state_path).unwrap_or_else(|e| {
+            eprintln!("{} {e:#}", "WARN".yellow().bold());
+            State::default()
+        }),
+    };
+
+    // The cache only saves requests, so a broken one is just started over
+    let cache_path = cwd.join(cache::CACHE_FILE);
+    let use_cache = !opts.no_cache && !opts.stdin;
+    let mut fetcher = Fetcher::new(opts)?;
+    if use_cache {
+        fetcher.use_cache(cache::ResultCache::load(&cache_path).unwrap_or_default());
+    }
+    let on_result = |index, check: &PackageCheck, warnings: &[String]| {
+        let last_seen = state.changed(&check.result);
+        match opts.format {
+            OutputFormat::Text if opts.summary_only => return,
+            OutputFormat::Text => {}
+            OutputFormat::Json | OutputFormat::Markdown => return,
+            OutputFormat::JsonLines => {
+                report::print_line(&JsonLine::Package(PackageReport {
+                    check,
+                    last_seen,
+                    warnings: warnings.iter().map(String::as_str).collect(),
+                }));
+                return;
             }
         }
+        let result = &check.result;
+        let hidden = opts.quiet
+            && warnings.is_empty()
+            && check.lint.is_empty()
+            && check.notes.is_empty()
+            && last_seen.is_none()
+            && matches!(
+                result,
+                ScanResult::UpToDate { .. }
+                    | ScanResult::VcsPackage { .. }
+                    | ScanResult::Probed { probe: ProbeStatus::Parseable, .. }
+            );
+        if hidden {
+            return;
+        }
+        let mut rendered = render_line(opts, check);
+        if let Some(seen) = last_seen.filter(|_| opts.output_template.is_none()) {
+            rendered.push_str(&format!(" {}", format!("(new since last run: was {seen})").cyan()));
+        }
+        for warning in warnings {
+            rendered.push_str(&format!("\n  {} {}", "WARN".yellow().bold(), warning));
+        }
+        for finding in &check.lint {
+            rendered.push_str(&format!("\n  {} {}", "LINT".cyan().bold(), finding));
+        }
+        for note in &check.notes {
+            rendered.push_str(&format!("\n  {} {}", "NOTE".dimmed(), note));
+        }
+        // Lines with anything to say beyond "up to date" are never collapsed
+        let ok = matches!(result, ScanResult::UpToDate { .. })
+            && warnings.is_empty()
+            && check.lint.is_empty()
+            && check.notes.is_empty()
+            && last_seen.is_none();
+        let key = output::sort_key(opts.sort, index, result, check.priority.unwrap_or(0));
+        output.push(key, rendered, ok);
+    };
+    let scan = if opts.stdin {
+        scanner::scan_stdin(cwd, opts, &fetcher, on_result)?
+    } else if opts.dirs.len() > 1 {
+        scanner::scan_directories(cwd, &opts.dirs, opts, &fetcher, on_result)?
+    } else {
+        scanner::scan_directory(cwd, opts, &fetcher, on_result)?
+    };
+    output.flush();
+
+    let summary = Summary::of(&scan);
+    let updated = if opts.fail_on_error { 0 } else { summary.updated };
+    let mut status = exit_status(updated, summary.errors, summary.warnings, opts.strict);
+    let changed: Vec<&str> = scan
+        .results
+        .iter()
+        .filter(|check| state.changed(&check.result).is_some())
+        .map(|check| check.result.name())
+        .collect();
+    if !opts.no_state && !opts.stdin {
+        let mut next = state.clone();
+        next.record(&scan.results);
+        if let Err(e) = next.save(&state_path) {
+            eprintln!("{} {e:#}", "WARN".yellow().bold());
+        }
+    }
+    if let Some(cache) = fetcher.cache()
+        && let Err(e) = cache.save(&cache_path)
+    {
+        eprintln!("{} {e:#}", "WARN".yellow().bold());
+    }
+    // Losing a history line shouldn't lose the check's results
+    if let Some(path) = &opts.history
+        && let Err(e) = report::append_history(path, &summary)
+    {
+        eprintln!("{} {e:#}", "WARN".yellow().bold());
+    }
+    // A failed hook is an error with --strict, else only a warning
+    let mut hook_failed = false;
+    if let Some(command) = &opts.on_complete {
+        let report = report::report_json(&scan, &summary, &state, false);
+        if let Err(e) = report::run_on_complete(command, &report) {
+            eprintln!("{} {e:#}", "WARN".yellow().bold());
+            hook_failed = true;
+        }
+    }
+    if hook_failed && opts.strict {
+        status = EXIT_ERRORS;
+    }
+    let code = ExitCode::from(status);
+    // Present exactly when the exit status is non-zero
+    if let Some(path) = &opts.touch_on_update
+        && let Err(e) = report::touch_marker(path, status != 0)
+    {
+        eprintln!("{} {e:#}", "WARN".yellow().bold());
+    }
+    if let Some(path) = &opts.summary_json
+        && let Err(e) = report::write_summary(path, &summary, status)
+    {
+        eprintln!("{} {e:#}", "WARN".yellow().bold());
+    }
+    match opts.format {
+        OutputFormat::Text => {}
+        OutputFormat::Json => {
+            report::print_report(&scan, &summary, &state, opts.pretty_json());
+            return Ok(code);
+        }
+        OutputFormat::JsonLines => {
+            for warning in report::unattached_warnings(&scan) {
+                report::print_line(&JsonLine::Warning(warning));
+            }
+            report::print_line(&JsonLine::Summary(&summary));
+            return Ok(code);
+        }
+        OutputFormat::Markdown => {
+            print!("{}", report::markdown_table(&scan.results, opts.quiet));
+            return Ok(code);
+        }
     }
 
-    println!();
+    // Warnings not tied to a checked package (e.g. unconfigured directories)
+    let unattached = report::unattached_warnings(&scan).filter(|_| !opts.summary_only);
+    for warning in unattached {
+        println!(
+            "{} {} - {}",
+            "WARN".yellow().bold(),
+            warning.name.white(),
+            warning.message
+        );
+    }
+
+    if scan.results.is_empty() && !opts.summary_only {
+        let message = match opts.retry_errors {
+            true => "No packages failed in the last run; nothing to retry.".to_string(),
+            false => format!("No subdirectories with {} + PKGBUILD found.", opts.config_name),
+        };
+        println!("{}", message.yellow());
+        return Ok(code);
+    }
+
+    if !opts.quiet {
+        println!();
+    }
+    if opts.probe {
+        println!(
+            "{}: {} probed, {} parseable, {} failed, {} warnings",
+            "Summary".bold(),
+            summary.checked,
+            summary.parseable.to_string().green(),
+            summary.errors.to_string().red(),
+            summary.warnings.to_string().yellow()
+        );
+    } else {
+        println!(
+            "{}: {} checked, {} updated, {} up-to-date, {} vcs, {} errors, {} warnings",
+            "Summary".bold(),
+            summary.checked,
+            summary.updated.to_string().green(),
+            summary.up_to_date.to_string().blue(),
+            summary.vcs.to_string().magenta(),
+            summary.errors.to_string().red(),
+            summary.warnings.to_string().yellow()
+        );
+    }
+
+    if opts.lint && !opts.summary_only {
+        let linted = scan.results.iter().filter(|check| !check.lint.is_empty()).count();
+        let line = format!("Lint: {} findings in {linted} PKGBUILDs", summary.lint);
+        println!("{}", if summary.lint > 0 { line.cyan() } else { line.normal() });
+    }
+
+    if !changed.is_empty() && !opts.summary_only {
+        println!(
+            "{} {}",
+            format!("New since last run ({}):", changed.len()).cyan().bold(),
+            changed.join(", ")
+        );
+    }
+
+    if opts.retry_errors && !opts.summary_only {
+        let (failing, fixed): (Vec<&PackageCheck>, Vec<&PackageCheck>) = scan
+            .results
+            .iter()
+            .partition(|check| matches!(check.result, ScanResult::Error { .. }));
+        let names = |checks: Vec<&PackageCheck>| {
+            checks.iter().map(|c| c.result.name()).collect::<Vec<_>>().join(", ")
+        };
+        println!(
+            "{} {} of {} packages that failed last run now pass",
+            "Retried:".bold(),
+            fixed.len(),
+            scan.results.len()
+        );
+        if !fixed.is_empty() {
+            println!("{} {}", format!("Fixed ({}):", fixed.len()).green().bold(), names(fixed));
+        }
+        if !failing.is_empty() {
+            let label = format!("Still failing ({}):", failing.len());
+            println!("{} {}", label.red().bold(), names(failing));
+        }
+    }
+
+    if scan.deadline_skipped > 0 && !opts.summary_only {
+        println!(
+            "{} {} packages skipped: deadline exceeded",
+            "WARN".yellow().bold(),
+            scan.deadline_skipped
+        );
+    }
+
+    if scan.error_skipped > 0 && !opts.summary_only {
+        println!(
+            "{} aborted after {} errors: {} packages skipped",
+            "WARN".yellow().bold(),
+            opts.max_errors.unwrap_or_default(),
+            scan.error_skipped
+        );
+    }
+
+    if scan.source_skipped > 0 && !opts.summary_only {
+        let message =
+            format!("{} packages skipped: not a selected --source-type", scan.source_skipped);
+        println!("{}", message.dimmed());
+    }
+
+    if scan.unreadable > 0 && !opts.summary_only {
+        println!(
+            "{} {} directory entries skipped: unreadable",
+            "WARN".yellow().bold(),
+            scan.unreadable
+        );
+    }
+
+    if opts.group_by_source {
+        print_source_groups(&scan.results);
+    }
+
+    if fetcher.cache_hits() > 0 && !opts.summary_only {
+        let line = format!("{} remotes unchanged since they were cached", fetcher.cache_hits());
+        println!("{}", line.dimmed());
+    }
+
+    if let Some(adaptive) = fetcher.adaptive_jobs().filter(|_| !opts.summary_only) {
+        println!(
+            "{} settled at {} concurrent requests (peak {}, max {}, backed off {} times)",
+            "Adaptive jobs:".bold(),
+            adaptive.limit,
+            adaptive.peak,
+            fetch::ADAPTIVE_MAX_JOBS,
+            adaptive.backoffs
+        );
+    }
+
+    if opts.concurrency_report {
+        print_concurrency_report(&fetcher, opts.jobs);
+    }
+
+    if opts.stats {
+        print_stats(&fetcher);
+    }
+
+    if !opts.quiet {
+        println!("\n{}", status_banner(&summary, opts));
+    }
+
+    Ok(code)
+}
+
+/// Print per-source-kind counts, e.g. to spot an outage of one host
+fn print_source_groups(results: &[PackageCheck]) {
+    // [checked, updated, up-to-date, vcs, errors] per source kind
+    let mut groups: BTreeMap<Option<SourceKind>, [usize; 5]> = BTreeMap::new();
+    for check in results {
+        let counts = groups.entry(check.result.source()).or_default();
+        counts[0] += 1;
+        match check.result {
+            ScanResult::Updated { .. } => counts[1] += 1,
+            ScanResult::UpToDate { .. } => counts[2] += 1,
+            ScanResult::VcsPackage { .. } => counts[3] += 1,
+            ScanResult::Error { .. } => counts[4] += 1,
+            ScanResult::Probed { probe, .. } if probe != ProbeStatus::Parseable => counts[4] += 1,
+            // Counted as checked only, like in the summary line
+            ScanResult::RemoteOlder { .. } | ScanResult::Probed { .. } => {}
+        }
+    }
+
+    println!("{}", "By source:".bold());
+    for (source, [checked, updated, up_to_date, vcs, errors]) in groups {
+        let label = source.map_or("unknown".to_string(), |s| s.to_string());
+        println!(
+            "  {}: {} checked, {} updated, {} up-to-date, {} vcs, {} errors",
+            label.bold(),
+            checked,
+            updated.to_string().green(),
+            up_to_date.to_string().blue(),
+            vcs.to_string().magenta(),
+            errors.to_string().red()
+        );
+    }
+}
+
+/// Print how well requests overlapped, e.g. to tune `--jobs`
+fn print_concurrency_report(fetcher: &Fetcher, jobs: usize) {
+    let stats = fetcher.concurrency_stats();
+    println!("{}", "Concurrency:".bold());
     println!(
-        "{}: {} checked, {} updated, {} up-to-date, {} errors",
-        "Summary".bold(),
-        results.len(),
-        updated_count.to_string().green(),
-        up_to_date_count.to_string().blue(),
-        error_count.to_string().red()
+        "  {} requests with --jobs {}: max {} in flight, {:.1} on average",
+        stats.requests,
+        jobs,
+        stats.max_in_flight,
+        stats.average_in_flight()
+    );
+    println!(
+        "  {:.2}s wall time vs {:.2}s summed request time: {:.1}x speedup",
+        stats.wall.as_secs_f64(),
+        stats.busy.as_secs_f64(),
+        stats.average_in_flight()
     );
-
-    Ok(())
 }
 
-fn print_help() {
-    let version = env!("CARGO_PKG_VERSION");
+/// Print request totals and how much `--rate-limit` slowed the run
+fn print_stats(fetcher: &Fetcher) {
+    let stats = fetcher.concurrency_stats();
+    println!("{}", "Requests:".bold());
     println!(
-        "{} {} {}",
-        "rchan".bold().cyan(),
-        version,
-        "- PKGBUILD update checker & builder".dimmed()
+        "  {} requests, {:.2}s summed request time in {:.2}s",
+        stats.requests,
+        stats.busy.as_secs_f64(),
+        stats.wall.as_secs_f64()
     );
-    println!();
-    println!("{}", "USAGE:".bold());
-    println!("  rchan              Check PKGBUILD updates for all packages");
-    println!("  rchan build        Build all packages with makepkg");
-    println!("  rchan --help, -h   Show this help message");
+    if let Some(limit) = fetcher.rate_limit_stats() {
+        println!(
+            "  --rate-limit {}: {} requests waited their turn, {:.2}s in total",
+            limit.rate,
+            limit.delayed,
+            limit.waited.as_secs_f64()
+        );
+        if limit.waited * 10 > stats.busy {
+            let note = "The rate limit, not --jobs, set the pace of this run";
+            println!("  {}", note.yellow());
+        }
+    }
+}
+
+/// One line colored by overall result: red for errors (or warnings with
+/// `--strict`), yellow for updates, green when everything is up to date
+fn status_banner(summary: &Summary, opts: &Options) -> String {
+    let strict = opts.strict;
+    let plural = |n: usize, word: &str| format!("{n} {word}{}", if n == 1 { "" } else { "s" });
+    let updates = match summary.updated {
+        0 => String::new(),
+        n => format!(", {} available", plural(n, "update")),
+    };
+    if summary.errors > 0 {
+        format!("FAILED: {}{updates}", plural(summary.errors, "error"))
+            .red()
+            .bold()
+            .to_string()
+    } else if strict && summary.warnings > 0 {
+        format!("FAILED: {} (--strict){updates}", plural(summary.warnings, "warning"))
+            .red()
+            .bold()
+            .to_string()
+    } else if summary.updated > 0 {
+        format!("UPDATES: {}", plural(summary.updated, "update"))
+            .yellow()
+            .bold()
+            .to_string()
+    } else if opts.probe {
+        "OK: all remotes reachable and parseable".green().bold().to_string()
+    } else {
+        "UP TO DATE: all packages current".green().bold().to_string()
+    }
+}
+
+/// Map scan counts to the process exit status (errors win over updates)
+fn exit_status(updated: usize, errors: usize, warnings: usize, strict: bool) -> u8 {
+    if errors > 0 || (strict && warnings > 0) {
+        EXIT_ERRORS
+    } else if updated > 0 {
+        EXIT_UPDATES
+    } else {
+        0
+    }
+}
+
+/// Render a result with `--output-template` if given, else the default line
+fn render_line(opts: &Options, check: &PackageCheck) -> String {
+    let pkgbase = check.pkgbase.as_deref();
+    match (&opts.output_template, &check.label) {
+        (Some(template), _) => output::render_template(template, &check.result, pkgbase),
+        (None, Some(label)) => {
+            let tag = format!("[{label}]").magenta().bold();
+            format!("{tag} {}", output::render(&check.result, pkgbase))
+        }
+        (None, None) => output::render(&check.result, pkgbase),
+    }
 }
@@ -0,0 +1,376 @@
+use std::cmp::Reverse;
+
+use anyhow::{Context, Result, bail};
+use colored::Colorize;
+
+use crate::cli::SortOrder;
+use crate::pkgbuild::PkgVersion;
+use crate::scanner::{ProbeStatus, ScanResult};
+
+/// Where a buffered line goes: keys are compared in order, and the check
+/// index last keeps ties in check order
+pub type SortKey = (Reverse<u8>, u8, usize);
+
+/// Collects rendered per-package output from concurrent checks so lines
+/// from different packages never interleave
+pub struct OutputBuffer {
+    /// Buffer until `flush` and print by sort key
+    ordered: bool,
+    /// Print buffered lines in descending key order (`--reverse`)
+    reverse: bool,
+    pending: Vec<(SortKey, String, bool)>,
+    /// Up-to-date lines to print before collapsing the rest (None: all)
+    max_ok: Option<usize>,
+    ok_printed: usize,
+    ok_collapsed: usize,
+}
+
+impl OutputBuffer {
+    pub fn new(ordered: bool, reverse: bool, max_ok: Option<usize>) -> Self {
+        Self {
+            ordered,
+            reverse,
+            pending: Vec::new(),
+            max_ok,
+            ok_printed: 0,
+            ok_collapsed: 0,
+        }
+    }
+
+    /// Queue the rendered output of a package, placed by `key`.
+    /// In unordered mode it is printed immediately. A plain up-to-date line
+    /// (`ok`) may be collapsed by `--max-ok-lines`.
+    pub fn push(&mut self, key: SortKey, rendered: String, ok: bool) {
+        if self.ordered {
+            self.pending.push((key, rendered, ok));
+        } else if let Some(line) = self.admit(rendered, ok) {
+            println!("{line}");
+        }
+    }
+
+    /// Print everything still buffered, by sort key, then the count of
+    /// collapsed up-to-date lines
+    pub fn flush(&mut self) {
+        for line in self.drain() {
+            println!("{line}");
+        }
+    }
+
+    /// The lines [`OutputBuffer::flush`] prints
+    fn drain(&mut self) -> Vec<String> {
+        self.pending.sort_by_key(|(key, ..)| *key);
+        if self.reverse {
+            self.pending.reverse();
+        }
+        let mut lines: Vec<String> = std::mem::take(&mut self.pending)
+            .into_iter()
+            .filter_map(|(_, rendered, ok)| self.admit(rendered, ok))
+            .collect();
+        if self.ok_collapsed > 0 {
+            let more = format!("... and {} more up-to-date", self.ok_collapsed);
+            lines.push(more.dimmed().to_string());
+            self.ok_collapsed = 0;
+        }
+        lines
+    }
+
+    /// `rendered`, unless it is an up-to-date line past `max_ok`, which is
+    /// only counted
+    fn admit(&mut self, rendered: String, ok: bool) -> Option<String> {
+        if ok && self.max_ok.is_some_and(|max| self.ok_printed >= max) {
+            self.ok_collapsed += 1;
+            return None;
+        }
+        if ok {
+            self.ok_printed += 1;
+        }
+        Some(rendered)
+    }
+}
+
+/// Sort key of the check at `index`: in check order, or with `--sort status`
+/// by descending priority, then errors, updates, older remotes, VCS packages
+/// and the rest
+pub fn sort_key(order: SortOrder, index: usize, result: &ScanResult, priority: u8) -> SortKey {
+    if order == SortOrder::Name {
+        return (Reverse(0), 0, index);
+    }
+    let rank = match result {
+        _ if result.is_failure() => 0,
+        ScanResult::Updated { .. } => 1,
+        ScanResult::RemoteOlder { .. } => 2,
+        ScanResult::VcsPackage { .. } => 3,
+        _ => 4,
+    };
+    (Reverse(priority), rank, index)
+}
+
+/// Placeholders understood by `--output-template`
+pub const TEMPLATE_PLACEHOLDERS: &[&str] =
+    &["name", "pkgbase", "status", "local", "remote", "source", "epoch", "message"];
+
+/// Check that a `--output-template` only uses known placeholders
+pub fn validate_template(template: &str) -> Result<()> {
+    expand(template, |key| {
+        TEMPLATE_PLACEHOLDERS
+            .contains(&key)
+            .then(String::new)
+            .with_context(|| {
+                format!(
+                    "unknown placeholder '{{{key}}}' in output template (known: {})",
+                    TEMPLATE_PLACEHOLDERS.join(", ")
+                )
+            })
+    })
+    .map(|_| ())
+}
+
+/// Render one result with a `--output-template` (without colors).
+/// `{{` and `}}` produce literal braces.
+pub fn render_template(template: &str, result: &ScanResult, pkgbase: Option<&str>) -> String {
+    let (local, remote, message) = match result {
+        ScanResult::Updated {
+            local_ver,
+            remote_ver,
+            ..
+        } => (Some(local_ver), Some(remote_ver), ""),
+        ScanResult::RemoteOlder {
+            local_ver,
+            remote_ver,
+            ..
+        } => (Some(local_ver), Some(remote_ver), "remote is older than local"),
+        ScanResult::UpToDate { local_ver, .. } => (Some(local_ver), Some(local_ver), ""),
+        ScanResult::VcsPackage { local_ver, note, .. } => (Some(local_ver), None, note.as_str()),
+        ScanResult::Probed { detail, .. } => (None, None, detail.as_str()),
+        ScanResult::Error { message, .. } => (None, None, message.as_str()),
+    };
+    let version = |v: Option<&PkgVersion>| v.map(ToString::to_string).unwrap_or_default();
+    let rendered = expand(template, |key| {
+        Ok(match key {
+            "name" => result.name().to_string(),
+            "pkgbase" => pkgbase.unwrap_or_default().to_string(),
+            "status" => status_label(result).to_string(),
+            "local" => version(local),
+            "remote" => version(remote),
+            "source" => result.source().map(|s| s.to_string()).unwrap_or_default(),
+            "epoch" => remote
+                .or(local)
+                .and_then(|v| v.epoch.clone())
+                .unwrap_or_default(),
+            "message" => message.to_string(),
+            // Rejected by validate_template when the options were parsed
+            _ => String::new(),
+        })
+    });
+    rendered.unwrap_or_default()
+}
+
+/// The label printed before a package in the default output
+pub fn status_label(result: &ScanResult) -> &'static str {
+    match result {
+        ScanResult::Updated { .. } => "UPDATED",
+        ScanResult::RemoteOlder { .. } => "OLDER",
+        ScanResult::UpToDate { .. } => "OK",
+        ScanResult::VcsPackage { .. } => "VCS",
+        ScanResult::Probed { probe, .. } => match probe {
+            ProbeStatus::Parseable => "PARSEABLE",
+            ProbeStatus::Reachable => "REACHABLE",
+            ProbeStatus::Unreachable => "UNREACHABLE",
+        },
+        ScanResult::Error { .. } => "ERROR",
+    }
+}
+
+/// Replace each `{key}` in `template` with `value(key)`
+fn expand(template: &str, mut value: impl FnMut(&str) -> Result<String>) -> Result<String> {
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(pos) = rest.find(['{', '}']) {
+        out.push_str(&rest[..pos]);
+        let brace = &rest[pos..pos + 1];
+        rest = &rest[pos + 1..];
+        if let Some(after) = rest.strip_prefix(brace) {
+            out.push_str(brace);
+            rest = after;
+            continue;
+        }
+        if brace == "}" {
+            bail!("unmatched '}}' in output template");
+        }
+        let Some(end) = rest.find('}') else {
+            bail!("unclosed '{{' in output template");
+        };
+        out.push_str(&value(&rest[..end])?);
+        rest = &rest[end + 1..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Render the default colored line for a result, as `rchan` prints it.
+/// A `pkgbase` other than the directory name is shown after it.
+pub fn render(result: &ScanResult, pkgbase: Option<&str>) -> String {
+    let name = display_name(result.name(), pkgbase);
+    match result {
+        ScanResult::Updated {
+            local_ver,
+            remote_ver,
+            ..
+        } => format!(
+            "{} {} {} -> {}",
+            "UPDATED".green().bold(),
+            name.white().bold(),
+            local_ver.to_string().dimmed(),
+            remote_ver.to_string().green()
+        ),
+        ScanResult::RemoteOlder {
+            local_ver,
+            remote_ver,
+            ..
+        } => format!(
+            "{} {} {} -> {} (remote is older)",
+            "OLDER".yellow().bold(),
+            name.white().bold(),
+            local_ver.to_string().dimmed(),
+            remote_ver.to_string().yellow()
+        ),
+        ScanResult::UpToDate { local_ver, .. } => format!(
+            "{} {} ({})",
+            "OK".blue().bold(),
+            name.white(),
+            local_ver.to_string().dimmed()
+        ),
+        ScanResult::VcsPackage { local_ver, note, .. } => format!(
+            "{} {} ({}) - {}",
+            "VCS".magenta().bold(),
+            name.white(),
+            local_ver.to_string().dimmed(),
+            note.dimmed()
+        ),
+        ScanResult::Probed { probe, detail, .. } => {
+            let label = status_label(result);
+            match probe {
+                ProbeStatus::Parseable => {
+                    format!("{} {} ({})", label.green().bold(), name.white(), detail.dimmed())
+                }
+                ProbeStatus::Reachable => {
+                    format!("{} {} - {}", label.yellow().bold(), name.white(), detail)
+                }
+                ProbeStatus::Unreachable => {
+                    format!("{} {} - {}", label.red().bold(), name.white(), detail)
+                }
+            }
+        }
+        ScanResult::Error { message, .. } => {
+            format!("{} {} - {}", "ERROR".red().bold(), name.white(), message)
+        }
+    }
+}
+
+/// `dir (pkgbase)` when a package's pkgbase differs from its directory name,
+/// else just the name. Names may carry a `-C` directory before and a
+/// variant after the directory name; any `/`-separated part may match.
+pub fn display_name(name: &str, pkgbase: Option<&str>) -> String {
+    match pkgbase {
+        Some(pkgbase) if !name.split('/').any(|part| part == pkgbase) => {
+            format!("{name} ({pkgbase})")
+        }
+        _ => name.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetch::SourceKind;
+
+    #[test]
+    fn test_max_ok_lines() {
+        let mut output = OutputBuffer::new(true, false, Some(2));
+        let key = |index| (Reverse(0), 0, index);
+        // Pushed out of order; printed by key
+        for index in [6, 0, 5, 1, 4, 2, 3] {
+            let ok = index % 3 != 1;
+            let line = match ok {
+                true => format!("ok {index}"),
+                false => format!("update {index}"),
+            };
+            output.push(key(index), line, ok);
+        }
+        let lines = output.drain();
+        // Updates after the cutoff still print; ok 3, 5 and 6 are collapsed
+        assert_eq!(lines[..4], ["ok 0", "update 1", "ok 2", "update 4"][..]);
+        assert!(lines[4].contains("... and 3 more up-to-date"), "{}", lines[4]);
+        assert_eq!(lines.len(), 5);
+        assert!(output.drain().is_empty());
+    }
+
+    #[test]
+    fn test_render_template() {
+        let result = ScanResult::Updated {
+            name: "foo".to_string(),
+            source: SourceKind::Aur,
+            local_ver: "1.0-1".parse().unwrap(),
+            remote_ver: "2:1.1-1".parse().unwrap(),
+        };
+        let template = "{status} {name}: {local} -> {remote} [{source}] e={epoch}";
+        let line = render_template(template, &result, None);
+        assert_eq!(line, "UPDATED foo: 1.0-1 -> 2:1.1-1 [AUR] e=2");
+        assert_eq!(render_template("{{{name}}}", &result, None), "{foo}");
+        assert_eq!(render_template("{name} {pkgbase}", &result, Some("foo-bin")), "foo foo-bin");
+
+        let error = ScanResult::Error {
+            name: "bar".to_string(),
+            source: None,
+            message: "boom".to_string(),
+        };
+        let line = render_template("{name}|{remote}|{source}|{message}", &error, None);
+        assert_eq!(line, "bar|||boom");
+    }
+
+    #[test]
+    fn test_sort_key() {
+        let name = |name: &str| name.to_string();
+        let updated = ScanResult::Updated {
+            name: name("a"),
+            source: SourceKind::Aur,
+            local_ver: "1-1".parse().unwrap(),
+            remote_ver: "2-1".parse().unwrap(),
+        };
+        let error = ScanResult::Error {
+            name: name("b"),
+            source: None,
+            message: name("x"),
+        };
+        let ok = ScanResult::UpToDate {
+            name: name("c"),
+            source: SourceKind::Aur,
+            local_ver: "1-1".parse().unwrap(),
+        };
+        let mut keys = [
+            sort_key(SortOrder::Status, 0, &updated, 0),
+            sort_key(SortOrder::Status, 1, &error, 0),
+            sort_key(SortOrder::Status, 2, &ok, 5),
+        ];
+        keys.sort();
+        assert_eq!(keys.map(|key| key.2), [2, 1, 0]);
+        assert!(sort_key(SortOrder::Name, 0, &ok, 5) < sort_key(SortOrder::Name, 1, &error, 0));
+    }
+
+    #[test]
+    fn test_display_name() {
+        assert_eq!(display_name("foo", None), "foo");
+        assert_eq!(display_name("foo", Some("foo")), "foo");
+        assert_eq!(display_name("foo", Some("foo-bin")), "foo (foo-bin)");
+        assert_eq!(display_name("kernel/rchan.lts.yaml", Some("kernel")), "kernel/rchan.lts.yaml");
+        assert_eq!(display_name("aur/foo", Some("foo")), "aur/foo");
+    }
+
+    #[test]
+    fn test_validate_template() {
+        assert!(validate_template("{name} {remote}").is_ok());
+        assert!(validate_template("{nmae}").is_err());
+        assert!(validate_template("{name").is_err());
+        assert!(validate_template("name}").is_err());
+    }
+}
@@ -4,56 +4,443 @@ use regex::Regex;
 /// Version information extracted from a PKGBUILD
 #[derive(Debug, Clone, PartialEq)]
 pub struct PkgVersion {
+    pub epoch: Option<String>,
     pub pkgver: String,
     pub pkgrel: String,
 }
 
 impl std::fmt::Display for PkgVersion {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(epoch) = &self.epoch {
+            write!(f, "{epoch}:")?;
+        }
         write!(f, "{}-{}", self.pkgver, self.pkgrel)
     }
 }
 
-/// Extract pkgver and pkgrel from PKGBUILD text content
+/// Serialized as its display form, e.g. `"1:2.0-1"`
+impl serde::Serialize for PkgVersion {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl std::str::FromStr for PkgVersion {
+    type Err = anyhow::Error;
+
+    /// Parse a full version string as printed by pacman: `[epoch:]pkgver-pkgrel`
+    fn from_str(s: &str) -> Result<Self> {
+        let (epoch, rest) = match s.split_once(':') {
+            Some((epoch, rest)) => (Some(epoch.to_string()), rest),
+            None => (None, s),
+        };
+        let (pkgver, pkgrel) = rest
+            .rsplit_once('-')
+            .with_context(|| format!("Invalid version '{s}': missing pkgrel"))?;
+        if pkgver.is_empty() || pkgrel.is_empty() {
+            anyhow::bail!("Invalid version '{s}'");
+        }
+        Ok(PkgVersion {
+            epoch,
+            pkgver: pkgver.to_string(),
+            pkgrel: pkgrel.to_string(),
+        })
+    }
+}
+
+/// Options controlling how a PKGBUILD is parsed
+#[derive(Debug, Default, Clone)]
+pub struct ParseOptions {
+    /// Error when several `pkgver=` assignments with different values exist
+    /// anywhere in the file (function bodies and heredocs included)
+    pub strict_pkgver: bool,
+    /// Variable holding the authoritative version, read in place of `pkgver`
+    /// (e.g. `_realver` when `pkgver` is a sanitized copy of it)
+    pub pkgver_var: Option<String>,
+}
+
+impl ParseOptions {
+    /// Name of the variable the version is read from
+    pub fn version_var(&self) -> &str {
+        self.pkgver_var.as_deref().unwrap_or("pkgver")
+    }
+}
+
+/// Extract pkgver, pkgrel and the optional epoch from PKGBUILD text content
 ///
 /// Per Arch Linux official spec, the format is unquoted direct assignment:
 ///   pkgver=1.02.3
 ///   pkgrel=1
-pub fn parse_pkgbuild(content: &str) -> Result<PkgVersion> {
-    let ver_re = Regex::new(r"(?m)^pkgver=([0-9][0-9.]*)")?;
-    let rel_re = Regex::new(r"(?m)^pkgrel=([0-9]+)")?;
+///
+/// Only top-level assignments count: lines that are indented, inside a
+/// function body or inside a heredoc are ignored, so a decoy such as
+/// `pkgver=` in `package()` can't shadow the real one.
+///
+/// Empty or whitespace-only content (say, left by a failed checkout) is its
+/// own error rather than a missing pkgver.
+pub fn parse_pkgbuild(content: &str, options: &ParseOptions) -> Result<PkgVersion> {
+    if content.trim().is_empty() {
+        anyhow::bail!("PKGBUILD is empty");
+    }
+    let ver_re = Regex::new(r"^([0-9][0-9.]*)")?;
+    // pkgrel may carry a decimal sub-release, e.g. 1.1
+    let rel_re = Regex::new(r"^([0-9]+(\.[0-9]+)?)")?;
+    let epoch_re = Regex::new(r"^([0-9]+)")?;
+
+    let first_match = |name: &str, re: &Regex| {
+        top_level_values(content, name)
+            .into_iter()
+            .find_map(|value| re.captures(value).map(|c| c[1].to_string()))
+    };
+
+    let var = options.version_var();
+    // makepkg rejects a hyphen in pkgver; the version regex would stop at it
+    // and report a wrong version. A `pkgver_var` may hold the raw upstream one.
+    if var == "pkgver"
+        && let Some(value) = top_level_values(content, var).into_iter().find(|v| ver_re.is_match(v))
+    {
+        let value = value.split(|c: char| c.is_whitespace() || c == ';').next().unwrap_or("");
+        if value.contains('-') {
+            anyhow::bail!("pkgver must not contain '-' (found '{value}')");
+        }
+    }
+    let pkgver =
+        first_match(var, &ver_re).with_context(|| format!("Failed to find {var} in PKGBUILD"))?;
+    let pkgrel = first_match("pkgrel", &rel_re).context("Failed to find pkgrel in PKGBUILD")?;
+    let epoch = first_match("epoch", &epoch_re);
+
+    if options.strict_pkgver {
+        let any_re = Regex::new(&format!(r"(?m)^\s*{}=([0-9][0-9.]*)", regex::escape(var)))?;
+        let mut values: Vec<&str> = any_re
+            .captures_iter(content)
+            .filter_map(|c| c.get(1).map(|m| m.as_str()))
+            .collect();
+        values.dedup();
+        if values.iter().any(|v| *v != pkgver) {
+            anyhow::bail!(
+                "multiple conflicting {var}= assignments: {}",
+                values.join(", ")
+            );
+        }
+    }
 
-    let pkgver = ver_re
-        .captures(content)
-        .and_then(|c| c.get(1))
-        .map(|m| m.as_str().to_string())
-        .context("Failed to find pkgver in PKGBUILD")?;
+    Ok(PkgVersion {
+        epoch,
+        pkgver,
+        pkgrel,
+    })
+}
+
+/// Extract the (first) package name from PKGBUILD text content.
+/// Handles both `pkgname=foo` and split-package arrays `pkgname=(foo foo-docs)`.
+pub fn parse_pkgname(content: &str) -> Option<String> {
+    let value = top_level_values(content, "pkgname").into_iter().next()?;
+    value
+        .trim_start_matches('(')
+        .split(|c: char| c.is_whitespace() || c == ')')
+        .map(|name| name.trim_matches(|c| c == '"' || c == '\''))
+        .find(|name| !name.is_empty())
+        .map(str::to_string)
+}
+
+/// The pkgbase makepkg would use: `pkgbase=` if set, else the first pkgname
+pub fn parse_pkgbase(content: &str) -> Option<String> {
+    let explicit = top_level_values(content, "pkgbase")
+        .into_iter()
+        .next()
+        .and_then(|value| value.split_whitespace().next())
+        .map(|value| value.trim_matches(|c| c == '"' || c == '\''))
+        .filter(|value| !value.is_empty());
+    match explicit {
+        Some(pkgbase) => Some(pkgbase.to_string()),
+        None => parse_pkgname(content),
+    }
+}
+
+/// Raw text of the first top-level `name=` assignment, quotes included.
+/// An array spanning several lines is joined up to its closing parenthesis.
+pub fn parse_field(content: &str, name: &str) -> Option<String> {
+    let (index, value) = top_level_assignments(content, name).into_iter().next()?;
+    if !value.starts_with('(') || value.contains(')') {
+        return Some(value.to_string());
+    }
+    let mut text = value.to_string();
+    for line in content.lines().skip(index + 1) {
+        text.push('\n');
+        text.push_str(line);
+        if line.contains(')') {
+            break;
+        }
+    }
+    Some(text)
+}
 
-    let pkgrel = rel_re
-        .captures(content)
-        .and_then(|c| c.get(1))
-        .map(|m| m.as_str().to_string())
-        .context("Failed to find pkgrel in PKGBUILD")?;
+/// The unquoted words of a top-level array `name=(...)` (or of a plain
+/// `name=value`), skipping comments
+pub fn parse_array(content: &str, name: &str) -> Vec<String> {
+    let Some(field) = parse_field(content, name) else {
+        return Vec::new();
+    };
+    field
+        .lines()
+        // Comments may follow an entry on its line
+        .map(|line| line.split('#').next().unwrap_or_default())
+        .flat_map(|line| line.split(|c: char| c.is_whitespace() || c == '(' || c == ')'))
+        .map(|word| word.trim_matches(|c| c == '"' || c == '\''))
+        .filter(|word| !word.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Fingerprints listed in `validpgpkeys=(...)`, upper-cased. Entries that are
+/// not hex fingerprints (e.g. unexpanded variables) are skipped.
+pub fn parse_validpgpkeys(content: &str) -> Vec<String> {
+    parse_array(content, "validpgpkeys")
+        .into_iter()
+        .filter(|key| key.len() >= 16 && key.bytes().all(|b| b.is_ascii_hexdigit()))
+        .map(|key| key.to_ascii_uppercase())
+        .collect()
+}
+
+/// Package names in `depends=(...)` and `makedepends=(...)`, without version
+/// constraints and without repeats. Entries with unexpanded variables are
+/// skipped.
+pub fn parse_build_depends(content: &str) -> Vec<String> {
+    let mut depends: Vec<String> = Vec::new();
+    for entry in ["depends", "makedepends"].iter().flat_map(|name| parse_array(content, name)) {
+        let name = entry.split(['<', '>', '=']).next().unwrap_or_default();
+        if !name.is_empty() && !name.contains('$') && !depends.iter().any(|d| d == name) {
+            depends.push(name.to_string());
+        }
+    }
+    depends
+}
 
-    Ok(PkgVersion { pkgver, pkgrel })
+/// Version hygiene findings for `--lint`: quoted or repeated version fields,
+/// `pkgrel=0`, a redundant `epoch=0`, a `-` in pkgver, and a literal pkgver
+/// made from a commit without a `pkgver()` function to keep it current
+pub fn lint(content: &str) -> Vec<String> {
+    // An abbreviated hash, as in `r42.1a2b3c4` or `git describe`'s `g1a2b3c4`
+    let is_hash = |part: &str| {
+        let hash = part.strip_prefix('g').unwrap_or(part);
+        (7..=40).contains(&hash.len())
+            && hash.chars().all(|c| c.is_ascii_hexdigit() && !c.is_ascii_uppercase())
+            && hash.chars().any(|c| c.is_ascii_alphabetic())
+    };
+    let mut findings = Vec::new();
+    for name in ["pkgver", "pkgrel", "epoch"] {
+        let values = top_level_values(content, name);
+        if values.len() > 1 {
+            findings.push(format!("{name} is assigned {} times at top level", values.len()));
+        }
+        let Some(raw) = values.first().and_then(|v| v.split_whitespace().next()) else {
+            continue;
+        };
+        let value = raw.trim_matches(|c| c == '"' || c == '\'');
+        if value != raw {
+            findings.push(format!("{name} is quoted; write it bare, as {name}={value}"));
+        }
+        match name {
+            "epoch" if value == "0" => {
+                findings.push("epoch=0 is the default; drop the line".to_string())
+            }
+            "pkgrel" if value == "0" => {
+                findings.push("pkgrel=0 is invalid; pkgrel starts at 1".to_string())
+            }
+            "pkgver" if value.contains('-') => {
+                findings.push(format!("pkgver {value} contains '-', which pkgver can't"))
+            }
+            _ => {}
+        }
+        let from_commit =
+            value.to_lowercase().contains("commit") || value.split(['.', '_', '+']).any(is_hash);
+        if name == "pkgver" && from_commit && !has_pkgver_function(content) {
+            findings.push(format!(
+                "pkgver {value} looks derived from a commit, but there is no pkgver() \
+                 function to keep it current"
+            ));
+        }
+    }
+    findings
+}
+
+/// Values of the top-level `name=value` assignments in a PKGBUILD, in order.
+/// Indented lines, function bodies and heredoc contents are skipped.
+fn top_level_values<'a>(content: &'a str, name: &str) -> Vec<&'a str> {
+    top_level_assignments(content, name)
+        .into_iter()
+        .map(|(_, value)| value)
+        .collect()
+}
+
+/// Line indices and values of the top-level `name=value` assignments
+fn top_level_assignments<'a>(content: &'a str, name: &str) -> Vec<(usize, &'a str)> {
+    let func_re = Regex::new(r"^\s*(function\s+)?[A-Za-z_][A-Za-z0-9_-]*\s*\(\s*\)")
+        .expect("valid function regex");
+    let heredoc_re =
+        Regex::new(r#"<<-?\s*['"]?([A-Za-z_][A-Za-z0-9_]*)['"]?"#).expect("valid heredoc regex");
+    let prefix = format!("{name}=");
+
+    let mut values = Vec::new();
+    let mut depth: i32 = 0;
+    let mut heredoc: Option<String> = None;
+
+    for (index, line) in content.lines().enumerate() {
+        if let Some(delimiter) = &heredoc {
+            if line.trim() == delimiter {
+                heredoc = None;
+            }
+            continue;
+        }
+        if depth == 0
+            && !func_re.is_match(line)
+            && let Some(value) = line.strip_prefix(&prefix)
+        {
+            values.push((index, value));
+        }
+        if let Some(c) = heredoc_re.captures(line) {
+            heredoc = Some(c[1].to_string());
+        }
+        // Braces in `${var}` expansions balance out on the same line
+        depth += line.matches('{').count() as i32 - line.matches('}').count() as i32;
+        depth = depth.max(0);
+    }
+
+    values
+}
+
+/// Extract pkgver, pkgrel and epoch from .SRCINFO text content
+///
+/// .SRCINFO is generated by `makepkg --printsrcinfo` and holds the resolved
+/// values as `key = value` lines, with the pkgbase section first:
+///   pkgbase = example
+///     pkgver = 1.2.3
+///     pkgrel = 1
+///     epoch = 1
+pub fn parse_srcinfo_str(content: &str) -> Result<PkgVersion> {
+    let mut epoch = None;
+    let mut pkgver = None;
+    let mut pkgrel = None;
+
+    for line in content.lines() {
+        let Some((key, value)) = line.trim().split_once(" = ") else {
+            continue;
+        };
+        // Split packages repeat keys in their own sections; the first
+        // (pkgbase) occurrence is authoritative.
+        let slot = match key {
+            "epoch" => &mut epoch,
+            "pkgver" => &mut pkgver,
+            "pkgrel" => &mut pkgrel,
+            _ => continue,
+        };
+        slot.get_or_insert_with(|| value.trim().to_string());
+    }
+
+    Ok(PkgVersion {
+        epoch,
+        pkgver: pkgver.context("Failed to find pkgver in .SRCINFO")?,
+        pkgrel: pkgrel.context("Failed to find pkgrel in .SRCINFO")?,
+    })
+}
+
+/// Parse a .SRCINFO from a local file
+pub fn parse_srcinfo(path: &std::path::Path) -> Result<PkgVersion> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read .SRCINFO: {}", path.display()))?;
+    parse_srcinfo_str(&content)
+}
+
+/// Whether a .SRCINFO recording version `srcinfo` still describes a PKGBUILD
+/// whose `pkgver=`, `pkgrel=` and `epoch=` parse as `pkgbuild`. One not
+/// regenerated after a version change records the old version; file times
+/// can't tell, as a clone or copy sets them arbitrarily. A version the
+/// PKGBUILD computes (None) can't be compared, so the .SRCINFO is trusted.
+pub fn srcinfo_is_fresh(srcinfo: &PkgVersion, pkgbuild: Option<&PkgVersion>) -> bool {
+    pkgbuild.is_none_or(|pkgbuild| pkgbuild == srcinfo)
+}
+
+/// Rewrite the top-level `pkgver=` (or `version_var=`, see
+/// [`ParseOptions::version_var`]), `pkgrel=` and `epoch=` assignments of a
+/// PKGBUILD to `target`, keeping everything else (including trailing
+/// comments on those lines) intact. A missing `epoch=` line is inserted after
+/// `pkgrel=` when `target` has an epoch; an existing one is never removed.
+pub fn set_version(content: &str, target: &PkgVersion, version_var: &str) -> Result<String> {
+    let token_re = Regex::new(r"^[0-9][0-9.]*")?;
+    let mut lines: Vec<String> = content.lines().map(str::to_string).collect();
+
+    let mut replace = |name: &str, new_value: &str| -> Option<usize> {
+        let (index, value) = top_level_assignments(content, name)
+            .into_iter()
+            .find(|(_, value)| token_re.is_match(value))?;
+        let rest = &value[token_re.find(value)?.end()..];
+        lines[index] = format!("{name}={new_value}{rest}");
+        Some(index)
+    };
+
+    replace(version_var, &target.pkgver)
+        .with_context(|| format!("Failed to find {version_var} in PKGBUILD"))?;
+    let pkgrel_line =
+        replace("pkgrel", &target.pkgrel).context("Failed to find pkgrel in PKGBUILD")?;
+    if let Some(epoch) = &target.epoch
+        && replace("epoch", epoch).is_none()
+    {
+        lines.insert(pkgrel_line + 1, format!("epoch={epoch}"));
+    }
+
+    let mut rewritten = lines.join("\n");
+    if content.ends_with('\n') {
+        rewritten.push('\n');
+    }
+    Ok(rewritten)
+}
+
+/// Parse a fetched remote PKGBUILD. A server (or a CDN in front of it) may
+/// answer 200 OK with an error page or API response instead; that is
+/// reported as such rather than as a missing pkgver.
+pub fn parse_remote(content: &str, options: &ParseOptions) -> Result<PkgVersion> {
+    if let Some(kind) = non_pkgbuild_content(content) {
+        anyhow::bail!("remote returned non-PKGBUILD content (looks like {kind})");
+    }
+    parse_pkgbuild(content, options)
+}
+
+/// What `content` obviously is if it can't be a PKGBUILD: an HTML page, a
+/// JSON document or binary data
+fn non_pkgbuild_content(content: &str) -> Option<&'static str> {
+    let start = content.trim_start_matches('\u{feff}').trim_start();
+    let head: String = start.chars().take(16).collect::<String>().to_ascii_lowercase();
+    if ["<!doctype html", "<html", "<head", "<body"].iter().any(|tag| head.starts_with(tag)) {
+        return Some("HTML");
+    }
+    if start.starts_with(['{', '[']) && serde_json::from_str::<serde_json::Value>(start).is_ok() {
+        return Some("JSON");
+    }
+    // NUL never appears in text; a few undecodable bytes might, but not a
+    // tenth of the content
+    let garbled = content
+        .chars()
+        .filter(|&c| c == '\u{fffd}' || (c.is_control() && !c.is_ascii_whitespace()))
+        .count();
+    if content.contains('\0') || garbled * 10 > content.chars().count() {
+        return Some("binary data");
+    }
+    None
 }
 
 /// Parse a PKGBUILD from a local file
-pub fn parse_local(path: &std::path::Path) -> Result<PkgVersion> {
+pub fn parse_local(path: &std::path::Path, options: &ParseOptions) -> Result<PkgVersion> {
     let content = std::fs::read_to_string(path)
         .with_context(|| format!("Failed to read PKGBUILD: {}", path.display()))?;
-    parse_pkgbuild(&content)
+    parse_pkgbuild(&content, options)
 }
 
-/// Fetch and parse a PKGBUILD from a remote URL
-pub fn parse_remote(url: &str) -> Result<PkgVersion> {
-    let content = reqwest::blocking::get(url)
-        .with_context(|| format!("Failed to fetch remote PKGBUILD: {url}"))?
-        .error_for_status()
-        .with_context(|| format!("HTTP error fetching: {url}"))?
-        .text()
-        .context("Failed to read response body")?;
-    parse_pkgbuild(&content)
+/// Whether a PKGBUILD computes its version with a `pkgver()` function
+/// (VCS packages such as `-git`), making the literal `pkgver=` a placeholder
+pub fn has_pkgver_function(content: &str) -> bool {
+    Regex::new(r"(?m)^\s*(function\s+)?pkgver\s*\(\s*\)")
+        .map(|re| re.is_match(content))
+        .unwrap_or(false)
 }
 
 #[cfg(test)]
@@ -68,7 +455,7 @@ pkgver=1.2.3
 pkgrel=2
 pkgdesc=\"An example package\"
 ";
-        let ver = parse_pkgbuild(content).unwrap();
+        let ver = parse_pkgbuild(content, &ParseOptions::default()).unwrap();
         assert_eq!(ver.pkgver, "1.2.3");
         assert_eq!(ver.pkgrel, "2");
         assert_eq!(ver.to_string(), "1.2.3-2");
@@ -77,7 +464,7 @@ pkgdesc=\"An example package\"
     #[test]
     fn test_parse_pkgbuild_long_version() {
         let content = "pkgver=1.02.3.4\npkgrel=10\n";
-        let ver = parse_pkgbuild(content).unwrap();
+        let ver = parse_pkgbuild(content, &ParseOptions::default()).unwrap();
         assert_eq!(ver.pkgver, "1.02.3.4");
         assert_eq!(ver.pkgrel, "10");
     }
@@ -85,20 +472,298 @@ pkgdesc=\"An example package\"
     #[test]
     fn test_parse_pkgbuild_single_digit() {
         let content = "pkgver=3\npkgrel=1\n";
-        let ver = parse_pkgbuild(content).unwrap();
+        let ver = parse_pkgbuild(content, &ParseOptions::default()).unwrap();
         assert_eq!(ver.pkgver, "3");
         assert_eq!(ver.pkgrel, "1");
     }
 
+    #[test]
+    fn test_parse_pkgbuild_epoch() {
+        let content = "pkgver=1.0\npkgrel=3\nepoch=2\n";
+        let ver = parse_pkgbuild(content, &ParseOptions::default()).unwrap();
+        assert_eq!(ver.epoch.as_deref(), Some("2"));
+        assert_eq!(ver.to_string(), "2:1.0-3");
+    }
+
+    #[test]
+    fn test_parse_srcinfo() {
+        let content = "\
+pkgbase = example-git
+\tpkgdesc = An example package
+\tpkgver = 1.2.3.r4.gabcdef
+\tpkgrel = 1
+\tepoch = 1
+
+pkgname = example-git
+";
+        let ver = parse_srcinfo_str(content).unwrap();
+        assert_eq!(ver.pkgver, "1.2.3.r4.gabcdef");
+        assert_eq!(ver.pkgrel, "1");
+        assert_eq!(ver.epoch.as_deref(), Some("1"));
+    }
+
+    #[test]
+    fn test_srcinfo_is_fresh() {
+        let srcinfo: PkgVersion = "1:1.2-1".parse().unwrap();
+        assert!(srcinfo_is_fresh(&srcinfo, Some(&"1:1.2-1".parse().unwrap())));
+        assert!(!srcinfo_is_fresh(&srcinfo, Some(&"1:1.3-1".parse().unwrap())));
+        assert!(!srcinfo_is_fresh(&srcinfo, Some(&"1.2-1".parse().unwrap())));
+        assert!(srcinfo_is_fresh(&srcinfo, None));
+    }
+
+    #[test]
+    fn test_parse_srcinfo_missing_pkgver() {
+        let content = "pkgbase = example\n\tpkgrel = 1\n";
+        assert!(parse_srcinfo_str(content).is_err());
+    }
+
+    #[test]
+    fn test_has_pkgver_function() {
+        let content = "\
+pkgver=0.1.r0.g0000000
+pkgrel=1
+
+pkgver() {
+  cd \"$srcdir/example\"
+  git describe --long --tags
+}
+";
+        assert!(has_pkgver_function(content));
+        assert!(!has_pkgver_function("pkgver=1.0\npkgrel=1\n"));
+    }
+
+    #[test]
+    fn test_parse_pkgbuild_decoy_in_function() {
+        let content = "\
+pkgname=example
+build() {
+pkgver=9.9.9
+  make
+}
+pkgver=1.2.3
+pkgrel=1
+
+package() {
+  cat > \"$pkgdir/VERSION\" <<EOF
+pkgver=0.0.1
+EOF
+}
+";
+        let ver = parse_pkgbuild(content, &ParseOptions::default()).unwrap();
+        assert_eq!(ver.pkgver, "1.2.3");
+
+        let strict = ParseOptions {
+            strict_pkgver: true,
+            ..ParseOptions::default()
+        };
+        let err = parse_pkgbuild(content, &strict).unwrap_err();
+        assert!(err.to_string().contains("9.9.9, 1.2.3, 0.0.1"));
+    }
+
+    #[test]
+    fn test_parse_pkgbuild_strict_consistent() {
+        let content = "pkgver=1.0\npkgrel=1\npackage() {\n  pkgver=1.0\n}\n";
+        let strict = ParseOptions {
+            strict_pkgver: true,
+            ..ParseOptions::default()
+        };
+        assert_eq!(parse_pkgbuild(content, &strict).unwrap().pkgver, "1.0");
+    }
+
+    #[test]
+    fn test_pkgver_var() {
+        let content = "_realver=2.0.1\npkgver=${_realver//-/_}\npkgrel=3\n";
+        let options = ParseOptions {
+            pkgver_var: Some("_realver".to_string()),
+            ..ParseOptions::default()
+        };
+        assert_eq!(parse_pkgbuild(content, &options).unwrap().to_string(), "2.0.1-3");
+        assert!(parse_pkgbuild(content, &ParseOptions::default()).is_err());
+
+        let target = "2.1.0-1".parse().unwrap();
+        assert_eq!(
+            set_version(content, &target, "_realver").unwrap(),
+            "_realver=2.1.0\npkgver=${_realver//-/_}\npkgrel=1\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_pkgbase() {
+        assert_eq!(parse_pkgbase("pkgname=foo\n").as_deref(), Some("foo"));
+        let split = "pkgbase='python-foo' # split\npkgname=(python-foo python-foo-docs)\n";
+        assert_eq!(parse_pkgbase(split).as_deref(), Some("python-foo"));
+        assert_eq!(parse_pkgbase("pkgver=1\n"), None);
+    }
+
+    #[test]
+    fn test_lint() {
+        let clean = "pkgname=foo\npkgver=1.2.3\npkgrel=1\nepoch=1\n";
+        assert!(lint(clean).is_empty());
+
+        let messy = "pkgver=\"1.2.3\"\npkgrel=0\nepoch=0\npkgrel=1\n";
+        assert_eq!(
+            lint(messy),
+            [
+                "pkgver is quoted; write it bare, as pkgver=1.2.3",
+                "pkgrel is assigned 2 times at top level",
+                "pkgrel=0 is invalid; pkgrel starts at 1",
+                "epoch=0 is the default; drop the line",
+            ]
+        );
+
+        let snapshot = "pkgver=1.0.r42.g1a2b3c4\npkgrel=1\n";
+        assert_eq!(lint(snapshot).len(), 1);
+        assert!(lint(&format!("{snapshot}pkgver() {{\n  git describe\n}}\n")).is_empty());
+        assert_eq!(lint("pkgver=r10.${_commit:0:7}\npkgrel=1\n").len(), 1);
+        // Plain version numbers and dates are not hashes
+        assert!(lint("pkgver=20240101\npkgrel=1\n").is_empty());
+        assert_eq!(lint("pkgver=1.0-rc1\npkgrel=1\n").len(), 1);
+    }
+
+    #[test]
+    fn test_set_version() {
+        let content = "\
+pkgname=example
+pkgver=1.2.3 # upstream release
+pkgrel=4
+package() {
+  pkgver=0.0.1
+}
+";
+        let target = PkgVersion {
+            epoch: Some("1".to_string()),
+            pkgver: "1.3.0".to_string(),
+            pkgrel: "1".to_string(),
+        };
+        let rewritten = set_version(content, &target, "pkgver").unwrap();
+        assert_eq!(
+            rewritten,
+            "\
+pkgname=example
+pkgver=1.3.0 # upstream release
+pkgrel=1
+epoch=1
+package() {
+  pkgver=0.0.1
+}
+"
+        );
+        let ver = parse_pkgbuild(&rewritten, &ParseOptions::default()).unwrap();
+        assert_eq!(ver, target);
+    }
+
+    #[test]
+    fn test_parse_pkgname() {
+        assert_eq!(parse_pkgname("pkgname=foo\n").as_deref(), Some("foo"));
+        assert_eq!(
+            parse_pkgname("pkgname=('foo' 'foo-docs')\n").as_deref(),
+            Some("foo")
+        );
+        assert_eq!(parse_pkgname("pkgver=1\n"), None);
+    }
+
+    #[test]
+    fn test_parse_validpgpkeys() {
+        let content = "pkgname=foo\n\
+                       validpgpkeys=('ABAF11C65A2970B130ABE3C479BE3E4300411886'  # Linus\n\
+                       \"647f28654894e3bd457199be38dbbdc86092693e\"\n\
+                       \"$_extra_key\")\n";
+        assert_eq!(
+            parse_validpgpkeys(content),
+            [
+                "ABAF11C65A2970B130ABE3C479BE3E4300411886",
+                "647F28654894E3BD457199BE38DBBDC86092693E"
+            ]
+        );
+        assert!(parse_validpgpkeys("pkgname=foo\n").is_empty());
+    }
+
+    #[test]
+    fn test_parse_build_depends() {
+        let content = "pkgname=foo\n\
+                       depends=('glibc' \"openssl>=3.0\"  # TLS\n\
+                       \"${_py}-requests\")\n\
+                       makedepends=(cmake glibc 'ninja=1.12')\n\
+                       optdepends=('bash: completions')\n";
+        assert_eq!(parse_build_depends(content), ["glibc", "openssl", "cmake", "ninja"]);
+        assert!(parse_build_depends("pkgname=foo\n").is_empty());
+    }
+
+    #[test]
+    fn test_parse_field() {
+        let content =
+            "url='https://example.com'\nsource=(\"a.tar.gz\"\n        b.patch)\nsha256sums=()\n";
+        assert_eq!(parse_field(content, "url").as_deref(), Some("'https://example.com'"));
+        assert_eq!(
+            parse_field(content, "source").as_deref(),
+            Some("(\"a.tar.gz\"\n        b.patch)")
+        );
+        assert_eq!(parse_field(content, "sha256sums").as_deref(), Some("()"));
+        assert_eq!(parse_field(content, "depends"), None);
+    }
+
+    #[test]
+    fn test_version_from_str() {
+        let ver: PkgVersion = "1:2.0.1-3".parse().unwrap();
+        assert_eq!(ver.epoch.as_deref(), Some("1"));
+        assert_eq!(ver.pkgver, "2.0.1");
+        assert_eq!(ver.pkgrel, "3");
+        assert_eq!(ver.to_string(), "1:2.0.1-3");
+        assert!("2.0.1".parse::<PkgVersion>().is_err());
+    }
+
+    #[test]
+    fn test_parse_pkgbuild_decimal_pkgrel() {
+        let content = "pkgver=1.0\npkgrel=1.1\n";
+        let ver = parse_pkgbuild(content, &ParseOptions::default()).unwrap();
+        assert_eq!(ver.pkgrel, "1.1");
+        assert_eq!(ver.to_string(), "1.0-1.1");
+    }
+
     #[test]
     fn test_parse_pkgbuild_missing_pkgver() {
         let content = "pkgrel=1\n";
-        assert!(parse_pkgbuild(content).is_err());
+        assert!(parse_pkgbuild(content, &ParseOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_parse_pkgbuild_hyphen_in_pkgver() {
+        let content = "pkgver=1.2-3\npkgrel=1\n";
+        let e = parse_pkgbuild(content, &ParseOptions::default()).unwrap_err();
+        assert_eq!(e.to_string(), "pkgver must not contain '-' (found '1.2-3')");
+
+        // Only the value itself counts, not a comment after it
+        let content = "pkgver=1.2 # was 1.1-beta\npkgrel=1\n";
+        assert_eq!(parse_pkgbuild(content, &ParseOptions::default()).unwrap().pkgver, "1.2");
+    }
+
+    #[test]
+    fn test_parse_remote_non_pkgbuild() {
+        let looks_like = |content: &str| {
+            let e = parse_remote(content, &ParseOptions::default()).unwrap_err();
+            e.to_string()
+        };
+        let page = "\n<!DOCTYPE html>\n<html><body><pre>\npkgver=1.0\npkgrel=1\n</pre>";
+        assert_eq!(looks_like(page), "remote returned non-PKGBUILD content (looks like HTML)");
+        assert!(looks_like(r#"{"message": "Not Found"}"#).ends_with("(looks like JSON)"));
+        let binary = "\u{1f}\u{fffd}\u{8}\0pkgver=1\n";
+        assert!(looks_like(binary).ends_with("(looks like binary data)"));
+        assert!(looks_like("# comment\n").contains("pkgver"));
+        let content = "pkgver=1.0\npkgrel=1\nsource=(\"$pkgname-\u{fffd}.tar.gz\")\n# ...\n";
+        assert_eq!(parse_remote(content, &ParseOptions::default()).unwrap().to_string(), "1.0-1");
+    }
+
+    #[test]
+    fn test_parse_pkgbuild_empty() {
+        for content in ["", " \n\t\n"] {
+            let e = parse_pkgbuild(content, &ParseOptions::default()).unwrap_err();
+            assert_eq!(e.to_string(), "PKGBUILD is empty");
+        }
     }
 
     #[test]
     fn test_parse_pkgbuild_missing_pkgrel() {
         let content = "pkgver=1.0.0\n";
-        assert!(parse_pkgbuild(content).is_err());
+        assert!(parse_pkgbuild(content, &ParseOptions::default()).is_err());
     }
 }
@@ -0,0 +1,129 @@
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// Why running a command to read a remote failed
+#[derive(Debug)]
+pub struct CommandError {
+    pub message: String,
+    /// The connection failed or timed out, so retrying may help
+    pub connection: bool,
+}
+
+impl std::fmt::Display for CommandError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+/// A command that exited before its timeout
+pub struct Finished {
+    /// Exit code; None when killed by a signal
+    pub code: Option<i32>,
+    /// At most `max_size` bytes
+    pub stdout: Vec<u8>,
+    /// stdout had more than `max_size` bytes; the rest was discarded
+    pub overflow: bool,
+    /// At most 64 KiB of stderr
+    pub stderr: String,
+}
+
+impl Finished {
+    /// Last non-empty line of stderr, trimmed
+    pub fn detail(&self) -> &str {
+        self.stderr.lines().rfind(|l| !l.trim().is_empty()).unwrap_or("").trim()
+    }
+}
+
+/// Run `cmd` with stdin closed and its output captured, killing it after
+/// `timeout`. `what` names the operation in the timeout message
+/// (`ssh to build.lan`).
+pub fn run(
+    cmd: &mut Command,
+    what: &str,
+    timeout: Duration,
+    max_size: u64,
+) -> Result<Finished, CommandError> {
+    let program = cmd.get_program().to_string_lossy().into_owned();
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| CommandError {
+            message: format!("failed to run {program}: {e}"),
+            connection: false,
+        })?;
+
+    // Drain both pipes while waiting so a chatty child can't block
+    let stdout = child.stdout.take().unwrap();
+    let stderr = child.stderr.take().unwrap();
+    let out = std::thread::spawn(move || read_capped(stdout, max_size));
+    let err = std::thread::spawn(move || read_capped(stderr, 64 * 1024));
+
+    let started = Instant::now();
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if started.elapsed() >= timeout => {
+                let _ = child.kill();
+                let _ = child.wait();
+                let secs = timeout.as_secs_f64();
+                return Err(CommandError {
+                    message: format!("{what} timed out after {secs:.0}s"),
+                    connection: true,
+                });
+            }
+            Ok(None) => std::thread::sleep(Duration::from_millis(20)),
+            Err(e) => {
+                return Err(CommandError {
+                    message: format!("failed to wait for {program}: {e}"),
+                    connection: false,
+                });
+            }
+        }
+    };
+    let (stdout, overflow) = out.join().unwrap_or_default();
+    let (stderr, _) = err.join().unwrap_or_default();
+    Ok(Finished {
+        code: status.code(),
+        stdout,
+        overflow,
+        stderr: String::from_utf8_lossy(&stderr).into_owned(),
+    })
+}
+
+/// Read `reader` to the end, keeping the first `limit` bytes. The rest is
+/// read and discarded rather than left in the pipe, so the child neither
+/// blocks nor dies of a broken pipe; the flag says whether there was any.
+fn read_capped(mut reader: impl Read, limit: u64) -> (Vec<u8>, bool) {
+    let mut bytes = Vec::new();
+    let _ = reader.by_ref().take(limit).read_to_end(&mut bytes);
+    let discarded = std::io::copy(&mut reader, &mut std::io::sink()).unwrap_or(0);
+    (bytes, discarded > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_overflow() {
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "head -c 100000 /dev/zero; echo done >&2"]);
+        let finished = run(&mut cmd, "sh", Duration::from_secs(10), 1000).unwrap();
+        // The child wrote everything and exited normally
+        assert_eq!(finished.code, Some(0));
+        assert!(finished.overflow);
+        assert_eq!(finished.stdout.len(), 1000);
+        assert_eq!(finished.detail(), "done");
+
+        let mut cmd = Command::new("sh");
+        cmd.args(["-c", "printf 1.2.3"]);
+        let finished = run(&mut cmd, "sh", Duration::from_secs(10), 1000).unwrap();
+        assert!(!finished.overflow);
+        assert_eq!(finished.stdout, b"1.2.3");
+    }
+}
@@ -0,0 +1,168 @@
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use anyhow::{Context, Result};
+use colored::Colorize;
+
+use crate::cli::Options;
+use crate::pkgbuild::PkgVersion;
+use crate::vercmp::vercmp;
+
+/// Sidecar files that belong to a package artifact (`.json` is `build
+/// --metadata`)
+const SIDECAR_EXTENSIONS: [&str; 3] = [".sig", ".sha256", ".json"];
+
+/// A package artifact in pkgs/, identified from its file name
+#[derive(Debug)]
+struct Artifact {
+    path: PathBuf,
+    pkgname: String,
+    version: PkgVersion,
+}
+
+/// Split an artifact file name `pkgname-pkgver-pkgrel-arch.pkg.tar.*` into
+/// its package name and version
+pub fn parse_artifact_name(fname: &str) -> Option<(String, PkgVersion)> {
+    let stem = &fname[..fname.find(".pkg.tar")?];
+    // pkgname may itself contain dashes, so split from the right
+    let mut parts = stem.rsplitn(4, '-');
+    let _arch = parts.next()?;
+    let pkgrel = parts.next()?;
+    let pkgver = parts.next()?;
+    let pkgname = parts.next()?;
+    let version = format!("{pkgver}-{pkgrel}").parse().ok()?;
+    Some((pkgname.to_string(), version))
+}
+
+/// Run `rchan prune`: keep the `opts.keep` newest versions of each package
+/// in pkgs/ and delete older ones together with their sidecars
+pub fn run_prune(base: &Path, opts: &Options) -> Result<ExitCode> {
+    let pkgs_dir = base.join("pkgs");
+    if !opts.no_banner {
+        println!(
+            "{} {}",
+            "rchan prune".bold().cyan(),
+            "- stale artifact cleaner".dimmed()
+        );
+        println!("{} {}\n", "Packages directory:".bold(), pkgs_dir.display());
+    }
+
+    let mut by_name: BTreeMap<String, Vec<Artifact>> = BTreeMap::new();
+    for entry in std::fs::read_dir(&pkgs_dir)
+        .with_context(|| format!("Failed to read {}", pkgs_dir.display()))?
+    {
+        let path = entry?.path();
+        let fname = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        if SIDECAR_EXTENSIONS.iter().any(|ext| fname.ends_with(ext)) {
+            continue;
+        }
+        if let Some((pkgname, version)) = parse_artifact_name(&fname) {
+            by_name.entry(pkgname.clone()).or_default().push(Artifact {
+                path,
+                pkgname,
+                version,
+            });
+        }
+    }
+
+    let mut pruned = HashSet::new();
+    for artifacts in by_name.values_mut() {
+        // Newest first; equal versions (other arches) are kept together
+        artifacts.sort_by(|a, b| vercmp(&b.version, &a.version));
+        let mut versions_seen = 0;
+        let mut previous: Option<&PkgVersion> = None;
+        for artifact in artifacts.iter() {
+            if previous.is_none_or(|p| vercmp(p, &artifact.version) != Ordering::Equal) {
+                versions_seen += 1;
+            }
+            previous = Some(&artifact.version);
+            if versions_seen <= opts.keep {
+                continue;
+            }
+            println!(
+                "{} {} {}",
+                if opts.dry_run { "WOULD REMOVE" } else { "REMOVE" }.red().bold(),
+                artifact.pkgname.white(),
+                artifact.path.file_name().unwrap_or_default().to_string_lossy().dimmed()
+            );
+            remove(&artifact.path, opts.dry_run)?;
+            pruned.insert(artifact.path.clone());
+        }
+    }
+
+    // Sidecars whose artifact is gone (just pruned or already missing)
+    let mut orphans = 0;
+    let mut sidecars: Vec<PathBuf> = std::fs::read_dir(&pkgs_dir)?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .collect();
+    sidecars.sort();
+    for path in sidecars {
+        let fname = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let Some(artifact) = SIDECAR_EXTENSIONS
+            .iter()
+            .find_map(|ext| fname.strip_suffix(ext))
+        else {
+            continue;
+        };
+        // Other JSON files may live in pkgs/ too
+        if fname.ends_with(".json") && parse_artifact_name(artifact).is_none() {
+            continue;
+        }
+        let artifact_path = pkgs_dir.join(artifact);
+        if !artifact_path.exists() || pruned.contains(&artifact_path) {
+            if !opts.quiet {
+                println!(
+                    "{} {}",
+                    if opts.dry_run { "WOULD REMOVE" } else { "REMOVE" }.red(),
+                    fname.dimmed()
+                );
+            }
+            remove(&path, opts.dry_run)?;
+            orphans += 1;
+        }
+    }
+
+    println!();
+    println!(
+        "{}: {} packages, {} artifacts {}, {} sidecars {}",
+        "Summary".bold(),
+        by_name.len(),
+        pruned.len().to_string().red(),
+        if opts.dry_run { "would be removed" } else { "removed" },
+        orphans.to_string().red(),
+        if opts.dry_run { "would be removed" } else { "removed" }
+    );
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Delete a file unless this is a dry run
+fn remove(path: &Path, dry_run: bool) -> Result<()> {
+    if !dry_run {
+        std::fs::remove_file(path)
+            .with_context(|| format!("Failed to remove {}", path.display()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_artifact_name() {
+        let (name, ver) = parse_artifact_name("python-foo-bar-1:2.0.1-3-any.pkg.tar.zst").unwrap();
+        assert_eq!(name, "python-foo-bar");
+        assert_eq!(ver.to_string(), "1:2.0.1-3");
+
+        let (name, ver) = parse_artifact_name("foo-1.0-1.1-x86_64.pkg.tar.xz").unwrap();
+        assert_eq!(name, "foo");
+        assert_eq!(ver.to_string(), "1.0-1.1");
+
+        assert!(parse_artifact_name("foo.db.tar.gz").is_none());
+        assert!(parse_artifact_name("foo-x86_64.pkg.tar.zst").is_none());
+    }
+}
@@ -0,0 +1,463 @@
+use std::io::Write;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::output;
+use crate::scanner::{PackageCheck, ProbeStatus, Scan, ScanResult, Warning};
+use crate::state::State;
+
+/// Counts printed at the end of a check
+#[derive(Debug, Default, Serialize)]
+pub struct Summary {
+    pub checked: usize,
+    pub updated: usize,
+    pub up_to_date: usize,
+    /// Remote version lower than the local one (counted in `warnings` too)
+    pub remote_older: usize,
+    pub vcs: usize,
+    /// Remotes fetched and parsed by `--probe`; the others count as errors
+    pub parseable: usize,
+    pub errors: usize,
+    pub warnings: usize,
+    /// `--lint` findings, which don't affect the exit status
+    pub lint: usize,
+    pub deadline_skipped: usize,
+    /// Packages not checked once `--max-errors` was reached (counted as errors)
+    pub error_skipped: usize,
+    /// Packages left out by `--source-type`
+    pub source_skipped: usize,
+    pub unreadable: usize,
+}
+
+impl Summary {
+    /// Tally a finished scan
+    pub fn of(scan: &Scan) -> Self {
+        let mut summary = Summary {
+            checked: scan.results.len(),
+            warnings: scan.warnings.len(),
+            deadline_skipped: scan.deadline_skipped,
+            error_skipped: scan.error_skipped,
+            source_skipped: scan.source_skipped,
+            unreadable: scan.unreadable,
+            ..Default::default()
+        };
+        for check in &scan.results {
+            summary.lint += check.lint.len();
+            match check.result {
+                ScanResult::Updated { .. } => summary.updated += 1,
+                ScanResult::RemoteOlder { .. } => summary.remote_older += 1,
+                ScanResult::UpToDate { .. } => summary.up_to_date += 1,
+                ScanResult::VcsPackage { .. } => summary.vcs += 1,
+                ScanResult::Probed { probe, .. } => match probe {
+                    ProbeStatus::Parseable => summary.parseable += 1,
+                    ProbeStatus::Reachable | ProbeStatus::Unreachable => summary.errors += 1,
+                },
+                ScanResult::Error { .. } => summary.errors += 1,
+            }
+        }
+        summary
+    }
+}
+
+/// One package in JSON output: its result plus the warnings raised for it
+#[derive(Serialize)]
+pub struct PackageReport<'a> {
+    #[serde(flatten)]
+    pub check: &'a PackageCheck,
+    /// Remote version seen by the previous check, when it has changed since
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_seen: Option<&'a str>,
+    pub warnings: Vec<&'a str>,
+}
+
+/// A line of `--json-lines` output, tagged by `"type"`
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JsonLine<'a> {
+    Package(PackageReport<'a>),
+    Warning(&'a Warning),
+    Summary(&'a Summary),
+}
+
+/// The whole `--json` document
+#[derive(Serialize)]
+pub struct Report<'a> {
+    pub packages: Vec<PackageReport<'a>>,
+    /// Warnings not tied to a checked package (e.g. unconfigured directories)
+    pub warnings: Vec<&'a Warning>,
+    pub summary: &'a Summary,
+}
+
+/// One line of the `--history` file
+#[derive(Serialize)]
+struct HistoryEntry<'a> {
+    /// Seconds since the Unix epoch
+    timestamp: u64,
+    /// The same instant as UTC `YYYY-MM-DDTHH:MM:SSZ`
+    date: String,
+    #[serde(flatten)]
+    summary: &'a Summary,
+}
+
+/// The `--summary-json` file
+#[derive(Serialize)]
+struct SummaryFile<'a> {
+    /// Seconds since the Unix epoch
+    timestamp: u64,
+    /// The same instant as UTC `YYYY-MM-DDTHH:MM:SSZ`
+    date: String,
+    /// The exit status of the check
+    exit_status: u8,
+    #[serde(flatten)]
+    summary: &'a Summary,
+}
+
+/// Write a run's summary and exit status to the `--summary-json` file,
+/// replacing it only once fully written
+pub fn write_summary(path: &Path, summary: &Summary, exit_status: u8) -> Result<()> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let file = SummaryFile {
+        timestamp,
+        date: utc_date(timestamp),
+        exit_status,
+        summary,
+    };
+    let mut content = to_json(&file, true);
+    content.push('\n');
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    std::fs::write(&tmp, content)
+        .with_context(|| format!("Failed to write {}", Path::new(&tmp).display()))?;
+    std::fs::rename(&tmp, path).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Append a run's summary to the `--history` file, creating it if needed.
+/// The file is only ever appended to, one JSON object per line.
+pub fn append_history(path: &Path, summary: &Summary) -> Result<()> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+    let entry = HistoryEntry {
+        timestamp,
+        date: utc_date(timestamp),
+        summary,
+    };
+    let mut line = serde_json::to_string(&entry).expect("serializable report");
+    line.push('\n');
+
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    // Don't glue onto a last line that was cut short
+    let content = std::fs::read(path).unwrap_or_default();
+    if content.last().is_some_and(|&b| b != b'\n') {
+        line.insert(0, '\n');
+    }
+    file.write_all(line.as_bytes())
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// Create the `--touch-on-update` file, or refresh its mtime, when a check
+/// needs attention; remove it when it doesn't. A missing file stays missing.
+pub fn touch_marker(path: &Path, attention: bool) -> Result<()> {
+    if attention {
+        std::fs::write(path, "").with_context(|| format!("Failed to write {}", path.display()))
+    } else {
+        match std::fs::remove_file(path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("Failed to remove {}", path.display()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Format Unix seconds as a UTC RFC 3339 date
+pub fn utc_date(timestamp: u64) -> String {
+    let (days, secs) = (timestamp / 86400, timestamp % 86400);
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        secs / 3600,
+        secs / 60 % 60,
+        secs % 60
+    )
+}
+
+/// Print one NDJSON line
+pub fn print_line(line: &JsonLine) {
+    // Serializing these plain structs cannot fail
+    println!("{}", serde_json::to_string(line).expect("serializable report"));
+}
+
+/// Serialize a `--json` document, indented or on one line
+pub fn to_json(document: &impl Serialize, pretty: bool) -> String {
+    // Serializing these plain structs cannot fail
+    match pretty {
+        true => serde_json::to_string_pretty(document),
+        false => serde_json::to_string(document),
+    }
+    .expect("serializable report")
+}
+
+/// Print the `--json` document for a finished scan
+pub fn print_report(scan: &Scan, summary: &Summary, state: &State, pretty: bool) {
+    println!("{}", report_json(scan, summary, state, pretty));
+}
+
+/// The `--json` document for a finished scan
+pub fn report_json(scan: &Scan, summary: &Summary, state: &State, pretty: bool) -> String {
+    let packages = scan
+        .results
+        .iter()
+        .map(|check| PackageReport {
+            check,
+            last_seen: state.changed(&check.result),
+            warnings: scan
+                .warnings
+                .iter()
+                .filter(|w| w.name == check.result.name())
+                .map(|w| w.message.as_str())
+                .collect(),
+        })
+        .collect();
+    let report = Report {
+        packages,
+        warnings: unattached_warnings(scan).collect(),
+        summary,
+    };
+    to_json(&report, pretty)
+}
+
+/// Run the `--on-complete` command with `sh -c`, writing `report` to its
+/// stdin. Its output goes to stderr so that stdout stays rchan's own.
+pub fn run_on_complete(command: &str, report: &str) -> Result<()> {
+    let mut child = std::process::Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::io::stderr())
+        .spawn()
+        .context("Failed to run the --on-complete command")?;
+    let mut stdin = child.stdin.take().expect("piped stdin");
+    // A command that doesn't read the report closes the pipe early
+    match stdin.write_all(report.as_bytes()).and_then(|()| stdin.write_all(b"\n")) {
+        Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe => {
+            let _ = child.wait();
+            return Err(e).context("Failed to write the report to the --on-complete command");
+        }
+        _ => drop(stdin),
+    }
+    let status = child.wait().context("Failed to wait for the --on-complete command")?;
+    if !status.success() {
+        anyhow::bail!("--on-complete command failed ({status})");
+    }
+    Ok(())
+}
+
+/// `--format markdown`: a GitHub-flavored Markdown table of the results,
+/// for pasting into a PR. `quiet` keeps only updates and errors.
+pub fn markdown_table(results: &[PackageCheck], quiet: bool) -> String {
+    let mut table = "| Package | Local | Remote | Status |\n|---|---|---|---|\n".to_string();
+    for check in results {
+        let (local, remote, status) = match &check.result {
+            ScanResult::Updated { local_ver, remote_ver, .. } => {
+                (local_ver.to_string(), remote_ver.to_string(), "updated".to_string())
+            }
+            ScanResult::Error { message, .. } => {
+                (String::new(), String::new(), format!("error: {message}"))
+            }
+            _ if quiet => continue,
+            ScanResult::RemoteOlder { local_ver, remote_ver, .. } => {
+                (local_ver.to_string(), remote_ver.to_string(), "remote older".to_string())
+            }
+            ScanResult::UpToDate { local_ver, .. } => {
+                (local_ver.to_string(), local_ver.to_string(), "up to date".to_string())
+            }
+            ScanResult::VcsPackage { local_ver, .. } => {
+                (local_ver.to_string(), String::new(), "vcs".to_string())
+            }
+            ScanResult::Probed { probe: ProbeStatus::Parseable, detail, .. } => {
+                (String::new(), detail.clone(), "parseable".to_string())
+            }
+            ScanResult::Probed { probe, detail, .. } => {
+                let status = match probe {
+                    ProbeStatus::Reachable => "reachable",
+                    _ => "unreachable",
+                };
+                (String::new(), String::new(), format!("{status}: {detail}"))
+            }
+        };
+        let name = output::display_name(check.result.name(), check.pkgbase.as_deref());
+        let cells = [name, local, remote, status].map(|cell| markdown_cell(&cell));
+        table.push_str(&format!("| {} |\n", cells.join(" | ")));
+    }
+    table
+}
+
+/// Escape text for a Markdown table cell, which can't hold `|` or newlines
+fn markdown_cell(text: &str) -> String {
+    text.replace('|', "\\|").replace('\n', " ")
+}
+
+/// Warnings for directories that produced no result
+pub fn unattached_warnings(scan: &Scan) -> impl Iterator<Item = &Warning> {
+    scan.warnings
+        .iter()
+        .filter(|w| !scan.results.iter().any(|c| c.result.name() == w.name))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::scratch::scratch_dir;
+    use crate::fetch::SourceKind;
+    use crate::pkgbuild::PkgVersion;
+
+    #[test]
+    fn test_utc_date() {
+        assert_eq!(utc_date(0), "1970-01-01T00:00:00Z");
+        assert_eq!(utc_date(951_782_400), "2000-02-29T00:00:00Z");
+        assert_eq!(utc_date(1_767_225_599), "2025-12-31T23:59:59Z");
+    }
+
+    #[test]
+    fn test_write_summary() {
+        let dir = scratch_dir("summary");
+        let path = dir.join("summary.json");
+        let summary = Summary { checked: 3, updated: 1, ..Default::default() };
+        write_summary(&path, &summary, 2).unwrap();
+        let written: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(written["exit_status"], 2);
+        assert_eq!(written["updated"], 1);
+        assert!(written["timestamp"].as_u64().unwrap() > 0);
+    }
+
+    #[test]
+    fn test_touch_marker() {
+        let dir = scratch_dir("attention");
+        let path = dir.join("attention");
+        touch_marker(&path, false).unwrap();
+        assert!(!path.exists());
+        touch_marker(&path, true).unwrap();
+        touch_marker(&path, true).unwrap();
+        assert!(path.exists());
+        touch_marker(&path, false).unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_run_on_complete() {
+        let dir = scratch_dir("on-complete");
+        let path = dir.join("on-complete");
+        let command = format!("cat > '{}'", path.display());
+        run_on_complete(&command, r#"{"packages":[]}"#).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "{\"packages\":[]}\n");
+        run_on_complete("true", "{}").unwrap();
+        let err = run_on_complete("exit 4", "{}").unwrap_err();
+        assert!(err.to_string().contains("exit status: 4"), "{err}");
+    }
+
+    #[test]
+    fn test_package_line_shape() {
+        let result = ScanResult::Updated {
+            name: "foo".to_string(),
+            source: SourceKind::GitHub,
+            local_ver: "1.0-1".parse::<PkgVersion>().unwrap(),
+            remote_ver: "1:1.1-1".parse::<PkgVersion>().unwrap(),
+        };
+        let check = PackageCheck {
+            result,
+            remote: Some("https://github.com/acme/foo/raw/main/PKGBUILD".to_string()),
+            upstream_version: Some("1:1.1-1".to_string()),
+            elapsed: Duration::from_millis(250),
+            pkgbuild_path: "foo/PKGBUILD".into(),
+            pkgver_var: None,
+            pkgbase: None,
+            lint: Vec::new(),
+            notes: Vec::new(),
+            label: None,
+            priority: None,
+        };
+        let line = JsonLine::Package(PackageReport {
+            check: &check,
+            last_seen: Some("1.0-1"),
+            warnings: vec!["remote redirected"],
+        });
+        let value = serde_json::to_value(&line).unwrap();
+        assert_eq!(value["type"], "package");
+        assert_eq!(value["status"], "updated");
+        assert_eq!(value["name"], "foo");
+        assert_eq!(value["source"], "github");
+        assert_eq!(value["local_ver"], "1.0-1");
+        assert_eq!(value["remote_ver"], "1:1.1-1");
+        assert_eq!(value["upstream_version"], "1:1.1-1");
+        assert_eq!(value["elapsed_ms"], 250);
+        assert_eq!(value["last_seen"], "1.0-1");
+        assert_eq!(value["warnings"][0], "remote redirected");
+    }
+
+    #[test]
+    fn test_markdown_table() {
+        let check = |result| PackageCheck {
+            result,
+            remote: None,
+            upstream_version: None,
+            elapsed: Duration::ZERO,
+            pkgbuild_path: "foo/PKGBUILD".into(),
+            pkgver_var: None,
+            pkgbase: None,
+            lint: Vec::new(),
+            notes: Vec::new(),
+            label: None,
+            priority: None,
+        };
+        let version = |v: &str| v.parse::<PkgVersion>().unwrap();
+        let results = [
+            check(ScanResult::Updated {
+                name: "foo".to_string(),
+                source: SourceKind::Aur,
+                local_ver: version("1.0-1"),
+                remote_ver: version("1.1-1"),
+            }),
+            check(ScanResult::UpToDate {
+                name: "bar".to_string(),
+                source: SourceKind::Aur,
+                local_ver: version("2.0-1"),
+            }),
+            check(ScanResult::Error {
+                name: "baz".to_string(),
+                source: None,
+                message: "bad | config\nhere".to_string(),
+            }),
+        ];
+        assert_eq!(
+            markdown_table(&results, false),
+            "| Package | Local | Remote | Status |\n\
+             |---|---|---|---|\n\
+             | foo | 1.0-1 | 1.1-1 | updated |\n\
+             | bar | 2.0-1 | 2.0-1 | up to date |\n\
+             | baz |  |  | error: bad \\| config here |\n"
+        );
+        assert!(!markdown_table(&results, true).contains("| bar |"));
+    }
+}
@@ -1,51 +1,658 @@
-use std::path::Path;
+use std::collections::{BTreeMap, HashSet};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, mpsc};
+use std::time::{Duration, Instant};
 
-use anyhow::Result;
+use anyhow::{Context, Result, bail};
+use serde::{Serialize, Serializer};
 
+use crate::cache;
+use crate::cli::Options;
 use crate::config::RchanConfig;
-use crate::pkgbuild;
+use crate::fetch::{self, Fetcher, SourceKind};
+use crate::pkgbuild::{self, ParseOptions, PkgVersion};
+use crate::source;
+use crate::state::{self, State};
+use crate::vercmp::vercmp;
 
 /// Scan result enum
+#[derive(Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
 pub enum ScanResult {
     /// Remote version has been updated
     Updated {
         name: String,
-        local_ver: String,
-        remote_ver: String,
+        source: SourceKind,
+        local_ver: PkgVersion,
+        remote_ver: PkgVersion,
+    },
+    /// Remote version is lower than the local one, which usually means the
+    /// remote is the wrong file (an error with `--strict`)
+    RemoteOlder {
+        name: String,
+        source: SourceKind,
+        local_ver: PkgVersion,
+        remote_ver: PkgVersion,
     },
     /// Versions match, no update needed
     UpToDate {
         name: String,
-        local_ver: String,
+        source: SourceKind,
+        local_ver: PkgVersion,
+    },
+    /// VCS package whose version comes from `pkgver()`; comparing the
+    /// literal `pkgver=` placeholders is not meaningful
+    VcsPackage {
+        name: String,
+        source: SourceKind,
+        local_ver: PkgVersion,
+        note: String,
+    },
+    /// Remote fetched and parsed without a comparison (`--probe`)
+    Probed {
+        name: String,
+        source: SourceKind,
+        probe: ProbeStatus,
+        /// The remote version when parseable, else why not
+        detail: String,
     },
     /// An error occurred during processing
     Error {
         name: String,
+        /// Unknown when the config itself could not be read
+        source: Option<SourceKind>,
         message: String,
     },
 }
 
+/// How far `--probe` got with a remote
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProbeStatus {
+    /// Fetched and a version parsed from it
+    Parseable,
+    /// Fetched, but no version could be parsed
+    Reachable,
+    /// Could not be fetched
+    Unreachable,
+}
+
+/// Everything known about one checked package: the [`ScanResult`] the CLI
+/// renders plus details for other front ends
+#[derive(Serialize)]
+pub struct PackageCheck {
+    #[serde(flatten)]
+    pub result: ScanResult,
+    /// Resolved source the package was checked against: a URL, a local path
+    /// or a release API endpoint
+    pub remote: Option<String>,
+    /// Upstream version as published, before becoming a PKGBUILD version
+    pub upstream_version: Option<String>,
+    /// Time spent checking the package
+    #[serde(rename = "elapsed_ms", serialize_with = "serialize_millis")]
+    pub elapsed: Duration,
+    /// Local PKGBUILD the package was checked with
+    #[serde(skip)]
+    pub pkgbuild_path: PathBuf,
+    /// Variable the version was read from, if not `pkgver`
+    #[serde(skip)]
+    pub pkgver_var: Option<String>,
+    /// pkgbase of the local PKGBUILD, which may differ from the directory name
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pkgbase: Option<String>,
+    /// `--lint` findings for the local PKGBUILD; informational only
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub lint: Vec<String>,
+    /// `--verbose` notices, such as a rewritten remote URL; informational only
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub notes: Vec<String>,
+    /// `label` from the package's config
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    /// `priority` from the package's config
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority: Option<u8>,
+}
+
+/// Serialize a duration as whole milliseconds
+pub fn serialize_millis<S: Serializer>(
+    duration: &Duration,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(duration.as_millis() as u64)
+}
+
+/// Details gathered by `check_package` besides its result
+#[derive(Default)]
+struct CheckTrace {
+    remote: Option<String>,
+    upstream_version: Option<String>,
+    pkgver_var: Option<String>,
+    pkgbase: Option<String>,
+    lint: Vec<String>,
+    notes: Vec<String>,
+    label: Option<String>,
+    priority: Option<u8>,
+    /// The local version came from `.SRCINFO` rather than the PKGBUILD
+    srcinfo: bool,
+}
+
+impl CheckTrace {
+    /// The report for a package checked against `pkgbuild_path`
+    fn into_check(
+        self,
+        result: ScanResult,
+        elapsed: Duration,
+        pkgbuild_path: PathBuf,
+    ) -> PackageCheck {
+        PackageCheck {
+            result,
+            remote: self.remote,
+            upstream_version: self.upstream_version,
+            elapsed,
+            pkgbuild_path,
+            pkgver_var: self.pkgver_var,
+            pkgbase: self.pkgbase,
+            lint: self.lint,
+            notes: self.notes,
+            label: self.label,
+            priority: self.priority,
+        }
+    }
+}
+
+/// A non-fatal anomaly noticed while scanning
+#[derive(Serialize)]
+pub struct Warning {
+    pub name: String,
+    pub message: String,
+}
+
+/// Everything produced by a directory scan
+pub struct Scan {
+    /// Per-package results, in check order
+    pub results: Vec<PackageCheck>,
+    /// Warnings for all packages and unconfigured directories, sorted by name
+    pub warnings: Vec<Warning>,
+    /// Packages not checked at all because `--deadline` had passed
+    pub deadline_skipped: usize,
+    /// Packages not checked because `--max-errors` packages had failed
+    pub error_skipped: usize,
+    /// Packages left out because their remote isn't a `--source-type`
+    pub source_skipped: usize,
+    /// Directory entries skipped because they could not be read
+    pub unreadable: usize,
+}
+
+impl ScanResult {
+    /// Package name this result belongs to
+    pub fn name(&self) -> &str {
+        match self {
+            ScanResult::Updated { name, .. } => name,
+            ScanResult::RemoteOlder { name, .. } => name,
+            ScanResult::UpToDate { name, .. } => name,
+            ScanResult::VcsPackage { name, .. } => name,
+            ScanResult::Probed { name, .. } => name,
+            ScanResult::Error { name, .. } => name,
+        }
+    }
+
+    /// Kind of remote source the package is configured with, if known
+    pub fn source(&self) -> Option<SourceKind> {
+        match self {
+            ScanResult::Updated { source, .. } => Some(*source),
+            ScanResult::RemoteOlder { source, .. } => Some(*source),
+            ScanResult::UpToDate { source, .. } => Some(*source),
+            ScanResult::VcsPackage { source, .. } => Some(*source),
+            ScanResult::Probed { source, .. } => Some(*source),
+            ScanResult::Error { source, .. } => *source,
+        }
+    }
+
+    /// Whether the check failed: an error, or a remote `--probe` couldn't parse
+    pub fn is_failure(&self) -> bool {
+        match self {
+            ScanResult::Probed { probe, .. } => *probe != ProbeStatus::Parseable,
+            _ => matches!(self, ScanResult::Error { .. }),
+        }
+    }
+}
+
+/// A package directory queued for checking
+struct PackageDir {
+    name: String,
+    rchan_yaml: PathBuf,
+    pkgbuild_path: PathBuf,
+    /// Why a `--packages-from` entry cannot be checked
+    problem: Option<String>,
+    /// The parsed config, once `--source-type` filtering has read it
+    config: Option<Result<RchanConfig>>,
+}
+
 /// Scan all subdirectories (one level deep) under the current directory
-/// looking for those containing both rchan.yaml and PKGBUILD
-pub fn scan_directory(base: &Path) -> Result<Vec<ScanResult>> {
+/// looking for those containing both a config file (`opts.config_name`,
+/// rchan.yaml by default) and PKGBUILD. Variant configs such as
+/// `rchan.lts.yaml` are checked too, as packages named `dir/rchan.lts.yaml`.
+/// With `--packages-from`, only the listed directories are checked, in
+/// listed order.
+///
+/// Packages are checked on `opts.jobs` worker threads. `on_result` is called on
+/// the calling thread as each check completes (in completion order) with the
+/// package's position in check order and the warnings raised for it.
+pub fn scan_directory(
+    base: &Path,
+    opts: &Options,
+    fetcher: &Fetcher,
+    on_result: impl FnMut(usize, &PackageCheck, &[String]),
+) -> Result<Scan> {
+    let (packages, warnings, unreadable) = match &opts.packages_from {
+        Some(list) => (listed_packages(base, list, &opts.config_name)?, Vec::new(), 0),
+        None => discover_packages(base, &opts.config_name, opts.include_hidden)?,
+    };
+    check_packages(base, (packages, warnings, unreadable), opts, fetcher, on_result)
+}
+
+/// Like [`scan_directory`] over several trees (`-C DIR` given more than
+/// once), relative to `base`. Packages are named `DIR/name` as DIR was
+/// given and checked tree by tree in the order given; a package reached
+/// through more than one DIR (the same tree twice, or a symlink) is checked
+/// once. The state file for `--retry-errors` is the one in `base`.
+pub fn scan_directories(
+    base: &Path,
+    dirs: &[PathBuf],
+    opts: &Options,
+    fetcher: &Fetcher,
+    on_result: impl FnMut(usize, &PackageCheck, &[String]),
+) -> Result<Scan> {
+    let mut packages = Vec::new();
+    let mut warnings = Vec::new();
+    let mut unreadable = 0;
+    let mut seen = HashSet::new();
+    for dir in dirs {
+        let tree = base.join(dir);
+        if !seen.insert(tree.canonicalize().unwrap_or_else(|_| tree.clone())) {
+            continue;
+        }
+        let (found, found_warnings, skipped) =
+            discover_packages(&tree, &opts.config_name, opts.include_hidden)
+                .with_context(|| format!("Failed to read {}", tree.display()))?;
+        let prefix = dir.to_string_lossy();
+        let prefix = prefix.trim_end_matches('/');
+        for mut package in found {
+            let path = &package.rchan_yaml;
+            if !seen.insert(path.canonicalize().unwrap_or_else(|_| path.clone())) {
+                continue;
+            }
+            package.name = format!("{prefix}/{}", package.name);
+            packages.push(package);
+        }
+        warnings.extend(found_warnings.into_iter().map(|warning| Warning {
+            name: format!("{prefix}/{}", warning.name),
+            ..warning
+        }));
+        unreadable += skipped;
+    }
+    check_packages(base, (packages, warnings, unreadable), opts, fetcher, on_result)
+}
+
+/// Check discovered packages (with the discovery warnings and unreadable
+/// count) for [`scan_directory`] and [`scan_directories`]
+fn check_packages(
+    base: &Path,
+    (mut packages, mut warnings, unreadable): (Vec<PackageDir>, Vec<Warning>, usize),
+    opts: &Options,
+    fetcher: &Fetcher,
+    mut on_result: impl FnMut(usize, &PackageCheck, &[String]),
+) -> Result<Scan> {
+    if opts.retry_errors {
+        let failed = State::load(&base.join(state::STATE_FILE))?.errors;
+        packages.retain(|package| failed.contains(&package.name));
+        warnings.retain(|warning| failed.contains(&warning.name));
+    }
+    let mut source_skipped = 0;
+    if !opts.source_types.is_empty() {
+        packages.retain_mut(|package| {
+            let selected = package.problem.is_some() || {
+                let config = RchanConfig::from_file(&package.rchan_yaml);
+                let selected = source_selected(config.as_ref().ok(), &opts.source_types);
+                package.config = Some(config);
+                selected
+            };
+            source_skipped += usize::from(!selected);
+            selected
+        });
+    }
+
+    let queue = Mutex::new(packages.into_iter().enumerate());
+    let (tx, rx) = mpsc::channel();
     let mut results = Vec::new();
+    let mut deadline_skipped = 0;
+    let mut error_skipped = 0;
+    let failures = AtomicUsize::new(0);
+    let mut remotes = Vec::new();
 
-    let entries = std::fs::read_dir(base)?;
+    // With --adaptive-jobs the fetcher limits concurrent requests itself
+    let workers = match opts.adaptive_jobs {
+        true => fetch::ADAPTIVE_MAX_JOBS,
+        false => opts.jobs.max(1),
+    };
+    std::thread::scope(|s| {
+        for _ in 0..workers {
+            let tx = tx.clone();
+            let queue = &queue;
+            let failures = &failures;
+            s.spawn(move || {
+                loop {
+                    let next = queue.lock().unwrap().next();
+                    let Some((index, mut package)) = next else {
+                        break;
+                    };
+                    let started = Instant::now();
+                    let mut warnings = Vec::new();
+                    let mut trace = CheckTrace::default();
+                    let unchecked = |message| ScanResult::Error {
+                        name: package.name.clone(),
+                        source: None,
+                        message,
+                    };
+                    let max_errors = opts.max_errors.unwrap_or(usize::MAX);
+                    let (result, skipped) = if let Some(message) = &package.problem {
+                        (unchecked(message.clone()), None)
+                    } else if fetcher.deadline_exceeded() {
+                        (unchecked("deadline exceeded".to_string()), Some(Skip::Deadline))
+                    } else if failures.load(Ordering::Relaxed) >= max_errors {
+                        let message = format!("aborted after {max_errors} errors");
+                        (unchecked(message), Some(Skip::MaxErrors))
+                    } else {
+                        let path = &package.rchan_yaml;
+                        let config_source = match package.config.take() {
+                            Some(config) => ConfigSource::Parsed { path, config: Box::new(config) },
+                            None => ConfigSource::File(path),
+                        };
+                        let result = check_package(
+                            fetcher,
+                            opts,
+                            &package.name,
+                            config_source,
+                            &package.pkgbuild_path,
+                            &mut warnings,
+                            &mut trace,
+                        );
+                        (result, None)
+                    };
+                    if skipped.is_none() && result.is_failure() {
+                        failures.fetch_add(1, Ordering::Relaxed);
+                    }
+                    let check =
+                        trace.into_check(result, started.elapsed(), package.pkgbuild_path);
+                    if tx.send((index, check, warnings, skipped)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        // Only the workers hold senders now, so the receiver ends with them
+        drop(tx);
 
-    for entry in entries {
-        let entry = entry?;
-        let path = entry.path();
+        for (index, check, package_warnings, skipped) in rx {
+            match skipped {
+                Some(Skip::Deadline) => deadline_skipped += 1,
+                Some(Skip::MaxErrors) => error_skipped += 1,
+                None => {}
+            }
+            if let Some(remote) = &check.remote {
+                remotes.push((check.result.name().to_string(), remote.clone()));
+            }
+            on_result(index, &check, &package_warnings);
+            warnings.extend(package_warnings.into_iter().map(|message| Warning {
+                name: check.result.name().to_string(),
+                message,
+            }));
+            results.push((index, check));
+        }
+    });
 
-        // Only process directories
-        if !path.is_dir() {
-            continue;
+    warnings.extend(duplicate_remotes(remotes));
+
+    // Report in check order (by name unless listed)
+    results.sort_by_key(|(index, _)| *index);
+    warnings.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(Scan {
+        results: results.into_iter().map(|(_, result)| result).collect(),
+        warnings,
+        deadline_skipped,
+        error_skipped,
+        source_skipped,
+        unreadable,
+    })
+}
+
+/// Why a package was reported without being checked
+enum Skip {
+    Deadline,
+    MaxErrors,
+}
+
+/// Whether the remote a config names is one of `kinds`. A config that
+/// can't be read (`None`) is kept, so the check reports why.
+fn source_selected(config: Option<&RchanConfig>, kinds: &[SourceKind]) -> bool {
+    config
+        .and_then(|config| config.remote().ok().map(|remote| remote.kind()))
+        .is_none_or(|kind| kinds.contains(&kind))
+}
+
+/// Check a single package whose rchan.yaml document is read from stdin
+/// (`check --stdin`) against the local PKGBUILD `opts.pkgbuild`, without a
+/// package directory. Reports like [`scan_directory`]; an `include` in the
+/// document is relative to `base`.
+pub fn scan_stdin(
+    base: &Path,
+    opts: &Options,
+    fetcher: &Fetcher,
+    mut on_result: impl FnMut(usize, &PackageCheck, &[String]),
+) -> Result<Scan> {
+    let Some(pkgbuild_path) = &opts.pkgbuild else {
+        bail!("--stdin needs --pkgbuild PATH");
+    };
+    let name = pkgbuild_path.display().to_string();
+    let pkgbuild_path = base.join(pkgbuild_path);
+    let local = std::fs::read_to_string(&pkgbuild_path)
+        .with_context(|| format!("Failed to read {}", pkgbuild_path.display()))?;
+    let name = pkgbuild::parse_pkgname(&local).unwrap_or(name);
+    let mut config = String::new();
+    std::io::stdin()
+        .read_to_string(&mut config)
+        .context("Failed to read config from stdin")?;
+
+    let started = Instant::now();
+    let mut warnings = Vec::new();
+    let mut trace = CheckTrace::default();
+    let source = ConfigSource::Stdin { content: &config, dir: base };
+    let result =
+        check_package(fetcher, opts, &name, source, &pkgbuild_path, &mut warnings, &mut trace);
+    let check = trace.into_check(result, started.elapsed(), pkgbuild_path);
+    on_result(0, &check, &warnings);
+    let warnings = warnings
+        .into_iter()
+        .map(|message| Warning { name: name.clone(), message })
+        .collect();
+    Ok(Scan {
+        results: vec![check],
+        warnings,
+        deadline_skipped: 0,
+        error_skipped: 0,
+        source_skipped: 0,
+        unreadable: 0,
+    })
+}
+
+/// Check the package called `name` in `check` output on its own, for
+/// `rchan why`. Returns its report, the warnings raised for it and whether
+/// the local version came from `.SRCINFO`.
+pub fn check_named(
+    base: &Path,
+    opts: &Options,
+    fetcher: &Fetcher,
+    name: &str,
+) -> Result<(PackageCheck, Vec<String>, bool)> {
+    let package = find_package(base, opts, name)?;
+    let started = Instant::now();
+    let mut warnings = Vec::new();
+    let mut trace = CheckTrace::default();
+    let config = ConfigSource::File(&package.rchan_yaml);
+    let path = &package.pkgbuild_path;
+    let result = check_package(fetcher, opts, name, config, path, &mut warnings, &mut trace);
+    let srcinfo = trace.srcinfo;
+    let check = trace.into_check(result, started.elapsed(), package.pkgbuild_path);
+    Ok((check, warnings, srcinfo))
+}
+
+/// The package called `name` in `check` output, which must have a config
+fn find_package(base: &Path, opts: &Options, name: &str) -> Result<PackageDir> {
+    let packages = match &opts.packages_from {
+        Some(list) => listed_packages(base, list, &opts.config_name)?,
+        None => discover_packages(base, &opts.config_name, opts.include_hidden)?.0,
+    };
+    let Some(package) = packages.into_iter().find(|p| p.name == name) else {
+        bail!("no package named '{name}' in {}", base.display());
+    };
+    if let Some(problem) = package.problem {
+        bail!("{name}: {problem}");
+    }
+    Ok(package)
+}
+
+/// The config file of every package `check` would check, or only of the one
+/// called `name`, for `--dump-config`. A package that can't be checked comes
+/// with the reason. `--source-type` applies as it does to `check`.
+pub fn config_files(
+    base: &Path,
+    opts: &Options,
+    name: Option<&str>,
+) -> Result<Vec<(String, PathBuf, Option<String>)>> {
+    let packages = match name {
+        Some(name) => vec![find_package(base, opts, name)?],
+        None => match &opts.packages_from {
+            Some(list) => listed_packages(base, list, &opts.config_name)?,
+            None => discover_packages(base, &opts.config_name, opts.include_hidden)?.0,
+        },
+    };
+    Ok(packages
+        .into_iter()
+        .filter(|package| {
+            opts.source_types.is_empty()
+                || package.problem.is_some()
+                || source_selected(
+                    RchanConfig::from_file(&package.rchan_yaml).ok().as_ref(),
+                    &opts.source_types,
+                )
+        })
+        .map(|package| (package.name, package.rchan_yaml, package.problem))
+        .collect())
+}
+
+/// Fetch the remote of the package called `name` in `check` output the way
+/// `check` would, for `--dump-remote`. Returns the source it was read from,
+/// the content exactly as received and any warnings.
+pub fn fetch_remote(
+    base: &Path,
+    opts: &Options,
+    fetcher: &Fetcher,
+    name: &str,
+) -> Result<(String, Vec<u8>, Vec<String>)> {
+    let package = find_package(base, opts, name)?;
+    let config_name = package.rchan_yaml.file_name().unwrap_or_default().to_string_lossy();
+    let config = RchanConfig::from_file(&package.rchan_yaml)
+        .with_context(|| format!("Failed to parse {config_name}"))?;
+    let mut warnings = Vec::new();
+    check_env(&config, &config_name, opts, &mut warnings).map_err(anyhow::Error::msg)?;
+    let upstream = config.remote().with_context(|| format!("Invalid {config_name}"))?;
+    let parse_options = opts.parse_options();
+    // The source printed with the content already shows a rewritten URL
+    let source = source::for_remote(
+        &config,
+        upstream,
+        &package.pkgbuild_path,
+        &parse_options,
+        &mut Vec::new(),
+    )
+    .map_err(anyhow::Error::msg)?;
+    let policy = config.request_policy(fetcher.policy());
+    let content = fetcher.fetch_bytes(source.location(), policy, &mut warnings)?;
+    Ok((source.location().to_string(), content, warnings))
+}
+
+/// Fail on `${VAR}` references in a config to unset environment variables,
+/// or with `--allow-undefined-env` only warn that they were left in place
+fn check_env(
+    config: &RchanConfig,
+    config_name: &str,
+    opts: &Options,
+    warnings: &mut Vec<String>,
+) -> Result<(), String> {
+    for var in &config.undefined_env {
+        if !opts.allow_undefined_env {
+            return Err(format!(
+                "{config_name}: environment variable {var} is not set \
+                 (--allow-undefined-env leaves ${{{var}}} as is)"
+            ));
         }
+        warnings.push(format!("environment variable {var} is not set; left ${{{var}}} as is"));
+    }
+    Ok(())
+}
 
-        let rchan_yaml = path.join("rchan.yaml");
-        let pkgbuild_path = path.join("PKGBUILD");
+/// Find the package directories one level below `base`, sorted by name.
+/// Hidden directories (`.git`, ...) are skipped unless `include_hidden`.
+/// Directories with a PKGBUILD but no config file are only warned about.
+/// Entries that cannot be stat'd or read are warned about and skipped; their
+/// count is returned last.
+fn discover_packages(
+    base: &Path,
+    config_name: &str,
+    include_hidden: bool,
+) -> Result<(Vec<PackageDir>, Vec<Warning>, usize)> {
+    let mut packages = Vec::new();
+    let mut warnings = Vec::new();
+    let mut unreadable = 0;
+    let mut skip = |name: String, e: std::io::Error, warnings: &mut Vec<Warning>| {
+        unreadable += 1;
+        warnings.push(Warning {
+            name,
+            message: format!("cannot read directory entry: {e}; skipped"),
+        });
+    };
+
+    for entry in std::fs::read_dir(base)? {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                skip(base.display().to_string(), e, &mut warnings);
+                continue;
+            }
+        };
+        let path = entry.path();
+        let entry_name = entry.file_name().to_string_lossy().to_string();
+        if entry_name.starts_with('.') && !include_hidden {
+            continue;
+        }
 
-        // Skip directories without rchan.yaml or PKGBUILD
-        if !rchan_yaml.exists() || !pkgbuild_path.exists() {
+        // Only process directories (a dangling symlink is just not one)
+        match path.metadata() {
+            Ok(metadata) if metadata.is_dir() => {}
+            Ok(_) => continue,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => {
+                skip(entry_name, e, &mut warnings);
+                continue;
+            }
+        }
+        // Without this, an unlistable directory would look like an empty one
+        if let Err(e) = std::fs::read_dir(&path) {
+            skip(entry_name, e, &mut warnings);
             continue;
         }
 
@@ -55,70 +662,753 @@ pub fn scan_directory(base: &Path) -> Result<Vec<ScanResult>> {
             .to_string_lossy()
             .to_string();
 
-        let result = check_package(&name, &rchan_yaml, &pkgbuild_path);
-        results.push(result);
+        let configs = package_configs(&path, config_name);
+        if configs.is_empty() {
+            if is_present(&path.join("PKGBUILD")) {
+                warnings.push(Warning {
+                    name,
+                    message: format!("PKGBUILD without {config_name}; not checked"),
+                });
+            }
+            continue;
+        }
+        // Configs without a PKGBUILD to check are skipped
+        packages.extend(
+            configs
+                .into_iter()
+                .filter_map(|(rchan_yaml, variant)| package_dir(&name, rchan_yaml, variant)),
+        );
     }
 
-    // Sort by name for cleaner output
-    results.sort_by(|a, b| {
-        let name_a = match a {
-            ScanResult::Updated { name, .. } => name,
-            ScanResult::UpToDate { name, .. } => name,
-            ScanResult::Error { name, .. } => name,
-        };
-        let name_b = match b {
-            ScanResult::Updated { name, .. } => name,
-            ScanResult::UpToDate { name, .. } => name,
-            ScanResult::Error { name, .. } => name,
+    // Hand out packages in name order so early results come first
+    packages.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok((packages, warnings, unreadable))
+}
+
+/// Read the package directories named in `list` (one per line; blank lines
+/// and `#` comments are ignored), keeping their order. Entries that are not
+/// package directories are kept and reported as errors.
+fn listed_packages(base: &Path, list: &Path, config_name: &str) -> Result<Vec<PackageDir>> {
+    let content = std::fs::read_to_string(list)
+        .with_context(|| format!("Failed to read package list {}", list.display()))?;
+    let mut packages: Vec<PackageDir> = Vec::new();
+    let mut seen = HashSet::new();
+
+    for line in content.lines() {
+        let name = line.trim().trim_end_matches('/');
+        if name.is_empty() || name.starts_with('#') || !seen.insert(name) {
+            continue;
+        }
+
+        let path = base.join(name);
+        let unchecked = |problem: String| PackageDir {
+            name: name.to_string(),
+            rchan_yaml: path.join(config_name),
+            pkgbuild_path: path.join("PKGBUILD"),
+            problem: Some(problem),
+            config: None,
         };
-        name_a.cmp(name_b)
-    });
+        let configs = package_configs(&path, config_name);
+        if !path.is_dir() {
+            packages.push(unchecked(format!("listed in {} but no such directory", list.display())));
+        } else if configs.is_empty() {
+            let problem = format!("listed in {} but has no {config_name}", list.display());
+            packages.push(unchecked(problem));
+        }
+        let mut missing_pkgbuild = false;
+        for (rchan_yaml, variant) in configs {
+            match package_dir(name, rchan_yaml, variant) {
+                Some(package) => packages.push(package),
+                None => missing_pkgbuild = true,
+            }
+        }
+        if missing_pkgbuild {
+            packages.push(unchecked(format!("listed in {} but has no PKGBUILD", list.display())));
+        }
+    }
+
+    Ok(packages)
+}
+
+/// Config files in a package directory: `config_name` first if present, then
+/// variants named like it with an extra part (`rchan.lts.yaml` for
+/// `rchan.yaml`), sorted. The flag marks variants.
+fn package_configs(dir: &Path, config_name: &str) -> Vec<(PathBuf, bool)> {
+    let mut configs = Vec::new();
+    let default = dir.join(config_name);
+    if is_present(&default) {
+        configs.push((default, false));
+    }
+
+    let (prefix, suffix) = match config_name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => (format!("{stem}."), format!(".{ext}")),
+        _ => (format!("{config_name}."), String::new()),
+    };
+    let mut variants: Vec<PathBuf> = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            let file_name = entry.file_name().to_string_lossy().to_string();
+            file_name.len() > prefix.len() + suffix.len()
+                && file_name.starts_with(&prefix)
+                && file_name.ends_with(&suffix)
+        })
+        .map(|entry| entry.path())
+        .collect();
+    variants.sort();
+    configs.extend(variants.into_iter().map(|path| (path, true)));
+    configs
+}
+
+/// The package a config file describes, named after its directory (plus the
+/// config file name for a variant). The PKGBUILD is the one the config names,
+/// else `PKGBUILD`; None when that default is missing.
+fn package_dir(dir_name: &str, rchan_yaml: PathBuf, variant: bool) -> Option<PackageDir> {
+    let name = match variant {
+        true => format!(
+            "{dir_name}/{}",
+            rchan_yaml.file_name().unwrap_or_default().to_string_lossy()
+        ),
+        false => dir_name.to_string(),
+    };
+    let mut problem = file_problem(&rchan_yaml);
+    // An unparsable config is reported when the package is checked
+    let named = match problem {
+        None => RchanConfig::from_file(&rchan_yaml).ok().and_then(|c| c.pkgbuild),
+        Some(_) => None,
+    };
+    let pkgbuild_path = rchan_yaml.with_file_name(named.as_deref().unwrap_or("PKGBUILD"));
+    if !is_present(&pkgbuild_path) {
+        let named = named?;
+        problem.get_or_insert(format!("pkgbuild {named} does not exist"));
+    }
+    let problem = problem.or_else(|| file_problem(&pkgbuild_path));
+    Some(PackageDir {
+        name,
+        rchan_yaml,
+        pkgbuild_path,
+        problem,
+        config: None,
+    })
+}
+
+/// Whether anything (even a dangling symlink) exists at `path`
+fn is_present(path: &Path) -> bool {
+    path.symlink_metadata().is_ok()
+}
 
-    Ok(results)
+/// Why an existing `path` can't be read as a regular file, if it can't
+fn file_problem(path: &Path) -> Option<String> {
+    if path.is_file() {
+        return None;
+    }
+    let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+    let what = match path.metadata() {
+        Err(_) if path.is_symlink() => "a broken symlink",
+        Ok(metadata) if metadata.is_dir() => "a directory",
+        _ => "not a regular file",
+    };
+    Some(format!("{file_name} is {what}"))
+}
+
+/// Warn about packages configured with the same remote, which usually means
+/// a config was copied from another package and not edited.
+/// One warning per shared remote, named after all packages using it.
+fn duplicate_remotes(remotes: Vec<(String, String)>) -> Vec<Warning> {
+    let mut by_remote: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for (name, remote) in remotes {
+        by_remote.entry(remote).or_default().push(name);
+    }
+    by_remote
+        .into_iter()
+        .filter(|(_, names)| names.len() > 1)
+        .map(|(remote, mut names)| {
+            names.sort();
+            Warning {
+                name: names.join(", "),
+                message: format!("same remote_pkgbuild: {remote}"),
+            }
+        })
+        .collect()
 }
 
-/// Check a single package: compare local and remote PKGBUILD versions
-fn check_package(name: &str, rchan_yaml: &Path, pkgbuild_path: &Path) -> ScanResult {
-    let config = match RchanConfig::from_file(rchan_yaml) {
+/// Where `check_package` reads a package's rchan.yaml from
+enum ConfigSource<'a> {
+    File(&'a Path),
+    /// A config file already parsed, with the outcome
+    Parsed { path: &'a Path, config: Box<Result<RchanConfig>> },
+    /// A document read from stdin; `include` is relative to `dir`
+    Stdin { content: &'a str, dir: &'a Path },
+}
+
+/// Check a single package: compare local and remote PKGBUILD versions.
+/// `trace` records the resolved remote source once the config is read and
+/// the upstream version once it is parsed.
+fn check_package(
+    fetcher: &Fetcher,
+    opts: &Options,
+    name: &str,
+    config_source: ConfigSource,
+    pkgbuild_path: &Path,
+    warnings: &mut Vec<String>,
+    trace: &mut CheckTrace,
+) -> ScanResult {
+    let (config_name, config) = match config_source {
+        ConfigSource::File(path) => (
+            path.file_name().unwrap_or_default().to_string_lossy(),
+            RchanConfig::from_file(path),
+        ),
+        ConfigSource::Parsed { path, config } => {
+            (path.file_name().unwrap_or_default().to_string_lossy(), *config)
+        }
+        ConfigSource::Stdin { content, dir } => {
+            ("stdin config".into(), RchanConfig::from_yaml(content, dir))
+        }
+    };
+    let config = match config {
         Ok(c) => c,
         Err(e) => {
             return ScanResult::Error {
                 name: name.to_string(),
-                message: format!("Failed to parse rchan.yaml: {e}"),
+                source: None,
+                message: format!("Failed to parse {config_name}: {e}"),
             }
         }
     };
 
-    let local_ver = match pkgbuild::parse_local(pkgbuild_path) {
+    trace.label = config.label.clone();
+    trace.priority = config.priority;
+    for key in config.unknown_keys() {
+        warnings.push(format!(
+            "unknown key '{key}' in {config_name} (prefix custom keys with x-)"
+        ));
+    }
+    if let Err(message) = check_env(&config, &config_name, opts, warnings) {
+        return ScanResult::Error {
+            name: name.to_string(),
+            source: None,
+            message,
+        };
+    }
+
+    let upstream = match config.remote() {
+        Ok(upstream) => upstream,
+        Err(e) => {
+            return ScanResult::Error {
+                name: name.to_string(),
+                source: None,
+                message: format!("Invalid {config_name}: {e}"),
+            }
+        }
+    };
+    let source_kind = upstream.kind();
+    let parse_options = &ParseOptions {
+        pkgver_var: config.pkgver_var.clone(),
+        ..opts.parse_options()
+    };
+    trace.pkgver_var = config.pkgver_var.clone();
+    let mut notes = Vec::new();
+    let source = source::for_remote(&config, upstream, pkgbuild_path, parse_options, &mut notes);
+    let source = match source {
+        Ok(source) => source,
+        Err(message) => {
+            return ScanResult::Error {
+                name: name.to_string(),
+                source: Some(source_kind),
+                message,
+            }
+        }
+    };
+    trace.remote = Some(source.location().to_string());
+    if opts.verbose {
+        trace.notes = notes;
+    }
+    let what = source.description();
+    let policy = config.request_policy(fetcher.policy());
+
+    // A health check of the remote alone; the local PKGBUILD isn't read
+    if opts.probe {
+        let (probe, detail) = match fetcher.fetch(source.location(), policy, warnings) {
+            Err(e) => (ProbeStatus::Unreachable, format!("Failed to fetch {what}: {e}")),
+            Ok(content) => match source.upstream_version(&content) {
+                Ok(version) => {
+                    trace.upstream_version = Some(version.clone());
+                    (ProbeStatus::Parseable, version)
+                }
+                Err(e) => {
+                    let hint = fetcher.parse_hint(source.location());
+                    (ProbeStatus::Reachable, format!("Failed to parse {what}: {e}{hint}"))
+                }
+            },
+        };
+        return ScanResult::Probed {
+            name: name.to_string(),
+            source: source_kind,
+            probe,
+            detail,
+        };
+    }
+    let local_content = std::fs::read_to_string(pkgbuild_path).unwrap_or_default();
+    trace.pkgbase = pkgbuild::parse_pkgbase(&local_content);
+    // Before parsing, as a finding may explain why parsing fails
+    if opts.lint {
+        trace.lint = pkgbuild::lint(&local_content);
+    }
+    let (local_ver, from_srcinfo) = match local_version(pkgbuild_path, parse_options, warnings) {
         Ok(v) => v,
         Err(e) => {
             return ScanResult::Error {
                 name: name.to_string(),
+                source: Some(source_kind),
                 message: format!("Failed to parse local PKGBUILD: {e}"),
             }
         }
     };
 
-    let remote_ver = match pkgbuild::parse_remote(&config.remote_pkgbuild) {
+    trace.srcinfo = from_srcinfo;
+
+    // Without a resolved .SRCINFO, a pkgver() package only has a placeholder
+    let vcs_package = |local_ver: &PkgVersion| ScanResult::VcsPackage {
+        name: name.to_string(),
+        source: source_kind,
+        local_ver: local_ver.clone(),
+        note: "pkgver() package; add a .SRCINFO to track its version".to_string(),
+    };
+    if !from_srcinfo && pkgbuild::has_pkgver_function(&local_content) {
+        return vcs_package(&local_ver);
+    }
+
+    let local_hash = cache::content_hash(local_content.as_bytes());
+    let location = source.location();
+    let remote_content = match fetcher.fetch_cached(name, &local_hash, location, policy, warnings) {
+        Ok(c) => c,
+        Err(e) => {
+            return ScanResult::Error {
+                name: name.to_string(),
+                source: Some(source_kind),
+                message: format!("Failed to fetch {what}: {e}"),
+            }
+        }
+    };
+
+    if !from_srcinfo && source.is_vcs(&remote_content) {
+        return vcs_package(&local_ver);
+    }
+
+    let remote_ver = source
+        .upstream_version(&remote_content)
+        .inspect(|version| trace.upstream_version = Some(version.clone()))
+        .and_then(|version| source.resolve_version(&version, &local_ver));
+    let remote_ver = match remote_ver {
         Ok(v) => v,
         Err(e) => {
             return ScanResult::Error {
                 name: name.to_string(),
-                message: format!("Failed to fetch remote PKGBUILD: {e}"),
+                source: Some(source_kind),
+                message: format!("Failed to parse {what}: {e}{}", fetcher.parse_hint(location)),
             }
         }
     };
 
-    if local_ver == remote_ver {
+    if same_version(&local_ver, &remote_ver, opts.ignore_pkgrel) {
         ScanResult::UpToDate {
             name: name.to_string(),
-            local_ver: local_ver.to_string(),
+            source: source_kind,
+            local_ver,
+        }
+    } else if vercmp(&remote_ver, &local_ver).is_lt() {
+        let message = format!("remote version {remote_ver} is older than local {local_ver}");
+        if opts.strict {
+            return ScanResult::Error {
+                name: name.to_string(),
+                source: Some(source_kind),
+                message,
+            };
+        }
+        warnings.push(format!("{message}; does the remote point at the right file?"));
+        ScanResult::RemoteOlder {
+            name: name.to_string(),
+            source: source_kind,
+            local_ver,
+            remote_ver,
         }
     } else {
         ScanResult::Updated {
             name: name.to_string(),
-            local_ver: local_ver.to_string(),
-            remote_ver: remote_ver.to_string(),
+            source: source_kind,
+            local_ver,
+            remote_ver,
         }
     }
 }
+
+/// Whether two versions count as equal; with `ignore_pkgrel` (`--ignore-pkgrel`)
+/// only epoch and pkgver are compared
+fn same_version(local: &PkgVersion, remote: &PkgVersion, ignore_pkgrel: bool) -> bool {
+    if ignore_pkgrel {
+        local.epoch == remote.epoch && local.pkgver == remote.pkgver
+    } else {
+        local == remote
+    }
+}
+
+/// Determine the local version, preferring a committed .SRCINFO (which holds
+/// the resolved `pkgver()` output) over regex-parsing the PKGBUILD.
+/// Falls back to the PKGBUILD when .SRCINFO is absent, stale or unparsable.
+/// The flag tells whether the version came from .SRCINFO.
+fn local_version(
+    pkgbuild_path: &Path,
+    parse_options: &ParseOptions,
+    warnings: &mut Vec<String>,
+) -> Result<(PkgVersion, bool)> {
+    let srcinfo = pkgbuild_path.with_file_name(".SRCINFO");
+    // .SRCINFO describes `PKGBUILD`, not variants like `PKGBUILD.lts`, and
+    // only records pkgver, not a `pkgver_var`
+    let default_pkgbuild = pkgbuild_path.file_name().is_some_and(|f| f == "PKGBUILD");
+    let local = pkgbuild::parse_local(pkgbuild_path, parse_options);
+    if default_pkgbuild && parse_options.pkgver_var.is_none() && srcinfo.is_file() {
+        match pkgbuild::parse_srcinfo(&srcinfo) {
+            Ok(ver) if pkgbuild::srcinfo_is_fresh(&ver, local.as_ref().ok()) => {
+                return Ok((ver, true));
+            }
+            Ok(ver) => warnings.push(format!(
+                ".SRCINFO records {ver}, not the PKGBUILD's version; using PKGBUILD"
+            )),
+            Err(e) => warnings.push(format!("{e}; using PKGBUILD")),
+        }
+    }
+    Ok((local?, false))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::scratch_dir;
+
+    #[test]
+    fn test_listed_packages() {
+        let dir = scratch_dir("listed");
+        for pkg in ["zeta", "alpha", "noconf"] {
+            std::fs::create_dir_all(dir.join(pkg)).unwrap();
+            std::fs::write(dir.join(pkg).join("PKGBUILD"), "pkgver=1\npkgrel=1\n").unwrap();
+        }
+        for pkg in ["zeta", "alpha"] {
+            std::fs::write(dir.join(pkg).join("rchan.yaml"), "remote_pkgbuild: x\n").unwrap();
+        }
+        let list = dir.join("list");
+        std::fs::write(&list, "# curated\nzeta\n\nalpha/\nzeta\nnoconf\nmissing\n").unwrap();
+
+        let packages = listed_packages(&dir, &list, "rchan.yaml").unwrap();
+        let names: Vec<_> = packages.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["zeta", "alpha", "noconf", "missing"]);
+        assert!(packages[0].problem.is_none() && packages[1].problem.is_none());
+        assert!(packages[2].problem.as_ref().unwrap().contains("no rchan.yaml"));
+        assert!(packages[3].problem.as_ref().unwrap().contains("no such directory"));
+    }
+
+    #[test]
+    fn test_remote_older() {
+        let dir = scratch_dir("older");
+        let pkg = dir.join("foo");
+        std::fs::create_dir_all(&pkg).unwrap();
+        std::fs::write(pkg.join("PKGBUILD"), "pkgver=1.2\npkgrel=1\n").unwrap();
+        std::fs::write(pkg.join("upstream"), "pkgver=1.10\npkgrel=1\n").unwrap();
+        std::fs::write(pkg.join("rchan.yaml"), "remote_pkgbuild: upstream\n").unwrap();
+
+        let scan = |opts: &Options| {
+            let fetcher = Fetcher::new(opts).unwrap();
+            scan_directory(&dir, opts, &fetcher, |_, _, _| {}).unwrap()
+        };
+        let normal = scan(&Options::default());
+        assert!(matches!(normal.results[0].result, ScanResult::Updated { .. }));
+
+        std::fs::write(pkg.join("upstream"), "pkgver=1.1\npkgrel=1\n").unwrap();
+        let normal = scan(&Options::default());
+        assert!(matches!(normal.results[0].result, ScanResult::RemoteOlder { .. }));
+        assert!(normal.warnings[0].message.contains("remote version 1.1-1 is older than local"));
+
+        let strict = scan(&Options {
+            strict: true,
+            ..Options::default()
+        });
+        let ScanResult::Error { message, .. } = &strict.results[0].result else {
+            panic!("expected an error with --strict");
+        };
+        assert_eq!(message, "remote version 1.1-1 is older than local 1.2-1");
+    }
+
+    #[test]
+    fn test_probe() {
+        let dir = scratch_dir("probe");
+        let remotes = [("good", "pkgver=2\npkgrel=1\n"), ("junk", "<html>\n"), ("gone", "")];
+        for (pkg, remote) in remotes {
+            let pkg = dir.join(pkg);
+            std::fs::create_dir_all(&pkg).unwrap();
+            // The local PKGBUILD isn't parsed
+            std::fs::write(pkg.join("PKGBUILD"), "not a PKGBUILD\n").unwrap();
+            std::fs::write(pkg.join("rchan.yaml"), "remote_pkgbuild: upstream\n").unwrap();
+            if !remote.is_empty() {
+                std::fs::write(pkg.join("upstream"), remote).unwrap();
+            }
+        }
+
+        let opts = Options {
+            probe: true,
+            ..Options::default()
+        };
+        let fetcher = Fetcher::new(&opts).unwrap();
+        let scan = scan_directory(&dir, &opts, &fetcher, |_, _, _| {}).unwrap();
+        let probes: Vec<_> = scan
+            .results
+            .iter()
+            .map(|check| match &check.result {
+                ScanResult::Probed { name, probe, .. } => (name.as_str(), *probe),
+                _ => panic!("expected a probe result"),
+            })
+            .collect();
+        assert_eq!(
+            probes,
+            [
+                ("gone", ProbeStatus::Unreachable),
+                ("good", ProbeStatus::Parseable),
+                ("junk", ProbeStatus::Reachable),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_empty_pkgbuild() {
+        let dir = scratch_dir("empty");
+        let pkgbuild = "pkgver=1\npkgrel=1\n";
+        for (pkg, local, remote) in [("local", "\n", pkgbuild), ("remote", pkgbuild, "")] {
+            let pkg = dir.join(pkg);
+            std::fs::create_dir_all(&pkg).unwrap();
+            std::fs::write(pkg.join("PKGBUILD"), local).unwrap();
+            std::fs::write(pkg.join("upstream"), remote).unwrap();
+            std::fs::write(pkg.join("rchan.yaml"), "remote_pkgbuild: upstream\n").unwrap();
+        }
+
+        let opts = Options::default();
+        let fetcher = Fetcher::new(&opts).unwrap();
+        let scan = scan_directory(&dir, &opts, &fetcher, |_, _, _| {}).unwrap();
+        let messages: Vec<&str> = scan
+            .results
+            .iter()
+            .map(|check| match &check.result {
+                ScanResult::Error { message, .. } => message.as_str(),
+                _ => panic!("expected an error"),
+            })
+            .collect();
+        assert_eq!(
+            messages,
+            [
+                "Failed to parse local PKGBUILD: PKGBUILD is empty",
+                "Failed to parse remote PKGBUILD: PKGBUILD is empty",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_source_types() {
+        let dir = scratch_dir("source-types");
+        let remotes = [("local", "upstream"), ("web", "https://example.invalid/PKGBUILD")];
+        for (pkg, remote) in remotes {
+            let pkg = dir.join(pkg);
+            std::fs::create_dir_all(&pkg).unwrap();
+            std::fs::write(pkg.join("PKGBUILD"), "pkgver=1\npkgrel=1\n").unwrap();
+            std::fs::write(pkg.join("upstream"), "pkgver=1\npkgrel=1\n").unwrap();
+            std::fs::write(pkg.join("rchan.yaml"), format!("remote_pkgbuild: {remote}\n"))
+                .unwrap();
+        }
+
+        // The URL remote is never fetched
+        let opts = Options {
+            source_types: vec![SourceKind::Local],
+            ..Options::default()
+        };
+        let fetcher = Fetcher::new(&opts).unwrap();
+        let scan = scan_directory(&dir, &opts, &fetcher, |_, _, _| {}).unwrap();
+        let names: Vec<&str> = scan.results.iter().map(|c| c.result.name()).collect();
+        assert_eq!(names, ["local"]);
+        assert_eq!(scan.source_skipped, 1);
+    }
+
+    #[test]
+    fn test_max_errors() {
+        let dir = scratch_dir("max-errors");
+        for pkg in ["a", "b", "c", "d"] {
+            let pkg = dir.join(pkg);
+            std::fs::create_dir_all(&pkg).unwrap();
+            std::fs::write(pkg.join("PKGBUILD"), "pkgver=1\npkgrel=1\n").unwrap();
+            // No upstream file, so every check fails
+            std::fs::write(pkg.join("rchan.yaml"), "remote_pkgbuild: upstream\n").unwrap();
+        }
+
+        let opts = Options {
+            jobs: 1,
+            max_errors: Some(2),
+            ..Options::default()
+        };
+        let fetcher = Fetcher::new(&opts).unwrap();
+        let scan = scan_directory(&dir, &opts, &fetcher, |_, _, _| {}).unwrap();
+        assert_eq!(scan.results.len(), 4);
+        assert_eq!(scan.error_skipped, 2);
+        assert!(matches!(
+            &scan.results[3].result,
+            ScanResult::Error { message, .. } if message == "aborted after 2 errors"
+        ));
+    }
+
+    #[test]
+    fn test_fetch_remote() {
+        let dir = scratch_dir("dump");
+        let pkg = dir.join("foo");
+        std::fs::create_dir_all(&pkg).unwrap();
+        std::fs::write(pkg.join("PKGBUILD"), "pkgver=1\npkgrel=1\n").unwrap();
+        std::fs::write(pkg.join("rchan.yaml"), "remote_pkgbuild: upstream\n").unwrap();
+        // Not valid UTF-8, and passed through as is
+        std::fs::write(pkg.join("upstream"), b"pkgver=2\xff\n").unwrap();
+
+        let opts = Options::default();
+        let fetcher = Fetcher::new(&opts).unwrap();
+        let (source, content, _) = fetch_remote(&dir, &opts, &fetcher, "foo").unwrap();
+        assert_eq!(source, pkg.join("upstream").to_string_lossy());
+        assert_eq!(content, b"pkgver=2\xff\n");
+        assert!(fetch_remote(&dir, &opts, &fetcher, "bar").is_err());
+    }
+
+    #[test]
+    fn test_scan_directories() {
+        let dir = scratch_dir("trees");
+        for tree in ["one", "two"] {
+            let pkg = dir.join(tree).join("foo");
+            std::fs::create_dir_all(&pkg).unwrap();
+            std::fs::write(pkg.join("PKGBUILD"), "pkgver=1\npkgrel=1\n").unwrap();
+            std::fs::write(pkg.join("rchan.yaml"), "remote_pkgbuild: PKGBUILD\n").unwrap();
+        }
+
+        let opts = Options::default();
+        let fetcher = Fetcher::new(&opts).unwrap();
+        // The same tree twice, once through `..`, is only scanned once
+        let dirs = ["one/", "two", "two/../one"].map(PathBuf::from);
+        let scan = scan_directories(&dir, &dirs, &opts, &fetcher, |_, _, _| {}).unwrap();
+        let names: Vec<&str> = scan.results.iter().map(|c| c.result.name()).collect();
+        assert_eq!(names, ["one/foo", "two/foo"]);
+        assert!(scan.results.iter().all(|c| matches!(c.result, ScanResult::UpToDate { .. })));
+
+        let missing = [PathBuf::from("three")];
+        assert!(scan_directories(&dir, &missing, &opts, &fetcher, |_, _, _| {}).is_err());
+    }
+
+    #[test]
+    fn test_variant_configs() {
+        let dir = scratch_dir("variants");
+        let pkg = dir.join("kernel");
+        std::fs::create_dir_all(&pkg).unwrap();
+        for file in ["PKGBUILD", "PKGBUILD.lts"] {
+            std::fs::write(pkg.join(file), "pkgver=1\npkgrel=1\n").unwrap();
+        }
+        std::fs::write(pkg.join("rchan.yaml"), "remote_pkgbuild: x\n").unwrap();
+        std::fs::write(pkg.join("rchan.lts.yaml"), "remote_pkgbuild: y\npkgbuild: PKGBUILD.lts\n")
+            .unwrap();
+        std::fs::write(pkg.join("rchan.rt.yaml"), "remote_pkgbuild: z\npkgbuild: PKGBUILD.rt\n")
+            .unwrap();
+
+        let (packages, _, _) = discover_packages(&dir, "rchan.yaml", false).unwrap();
+        let names: Vec<_> = packages.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, ["kernel", "kernel/rchan.lts.yaml", "kernel/rchan.rt.yaml"]);
+        assert_eq!(packages[1].pkgbuild_path, pkg.join("PKGBUILD.lts"));
+        assert!(packages[1].problem.is_none());
+        assert!(packages[2].problem.as_ref().unwrap().contains("PKGBUILD.rt does not exist"));
+    }
+
+    #[test]
+    fn test_hidden_dirs() {
+        let dir = scratch_dir("hidden");
+        for pkg in ["foo", ".git", ".attic"] {
+            std::fs::create_dir_all(dir.join(pkg)).unwrap();
+            std::fs::write(dir.join(pkg).join("PKGBUILD"), "pkgver=1\npkgrel=1\n").unwrap();
+        }
+        std::fs::write(dir.join(".attic").join("rchan.yaml"), "remote_pkgbuild: x\n").unwrap();
+
+        let (packages, warnings, _) = discover_packages(&dir, "rchan.yaml", false).unwrap();
+        assert!(packages.is_empty());
+        let names: Vec<_> = warnings.iter().map(|w| w.name.as_str()).collect();
+        assert_eq!(names, ["foo"]);
+
+        let (packages, warnings, _) = discover_packages(&dir, "rchan.yaml", true).unwrap();
+        assert_eq!(packages[0].name, ".attic");
+        let names: Vec<_> = warnings.iter().map(|w| w.name.as_str()).collect();
+        assert_eq!(names, [".git", "foo"]);
+    }
+
+    #[test]
+    fn test_same_version() {
+        let local: PkgVersion = "1.2.0-1".parse().unwrap();
+        let rebuilt: PkgVersion = "1.2.0-2".parse().unwrap();
+        assert!(!same_version(&local, &rebuilt, false));
+        assert!(same_version(&local, &rebuilt, true));
+        for other in ["1.3.0-1", "1:1.2.0-1"] {
+            assert!(!same_version(&local, &other.parse().unwrap(), true));
+        }
+    }
+
+    #[test]
+    fn test_file_problems() {
+        let dir = scratch_dir("not-files");
+        for pkg in ["dirbuild", "dangling"] {
+            std::fs::create_dir_all(dir.join(pkg)).unwrap();
+            std::fs::write(dir.join(pkg).join("rchan.yaml"), "remote_pkgbuild: x\n").unwrap();
+        }
+        std::fs::create_dir(dir.join("dirbuild").join("PKGBUILD")).unwrap();
+        std::os::unix::fs::symlink("gone", dir.join("dangling").join("PKGBUILD")).unwrap();
+
+        let (packages, warnings, _) = discover_packages(&dir, "rchan.yaml", false).unwrap();
+        assert!(warnings.is_empty());
+        let problems: Vec<_> = packages.iter().map(|p| p.problem.as_deref()).collect();
+        assert_eq!(
+            problems,
+            [Some("PKGBUILD is a broken symlink"), Some("PKGBUILD is a directory")]
+        );
+    }
+
+    #[test]
+    fn test_duplicate_remotes() {
+        let remotes = vec![
+            ("foo".to_string(), "https://example.com/a/PKGBUILD".to_string()),
+            ("baz".to_string(), "https://example.com/b/PKGBUILD".to_string()),
+            ("bar".to_string(), "https://example.com/a/PKGBUILD".to_string()),
+        ];
+        let warnings = duplicate_remotes(remotes);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].name, "bar, foo");
+        assert!(warnings[0].message.contains("example.com/a/PKGBUILD"));
+    }
+
+    #[test]
+    fn test_local_version_srcinfo() {
+        let dir = scratch_dir("srcinfo-fresh");
+        let pkgbuild = dir.join("PKGBUILD");
+        std::fs::write(dir.join(".SRCINFO"), "pkgbase = foo\n\tpkgver = 1.2\n\tpkgrel = 1\n")
+            .unwrap();
+        std::fs::write(&pkgbuild, "pkgver=1.2\npkgrel=1\n").unwrap();
+        // As after a checkout: the PKGBUILD is newer but says the same
+        let later = std::time::SystemTime::now() + std::time::Duration::from_secs(60);
+        let file = std::fs::File::options().write(true).open(&pkgbuild).unwrap();
+        file.set_modified(later).unwrap();
+        let mut warnings = Vec::new();
+        let (ver, from_srcinfo) =
+            local_version(&pkgbuild, &ParseOptions::default(), &mut warnings).unwrap();
+        assert_eq!((ver.to_string().as_str(), from_srcinfo), ("1.2-1", true));
+        assert!(warnings.is_empty());
+
+        // The version was bumped without regenerating .SRCINFO
+        std::fs::write(&pkgbuild, "pkgver=1.3\npkgrel=1\n").unwrap();
+        let (ver, from_srcinfo) =
+            local_version(&pkgbuild, &ParseOptions::default(), &mut warnings).unwrap();
+        assert_eq!((ver.to_string().as_str(), from_srcinfo), ("1.3-1", false));
+        let stale = ".SRCINFO records 1.2-1, not the PKGBUILD's version; using PKGBUILD";
+        assert_eq!(warnings, [stale]);
+    }
+}
@@ -0,0 +1,28 @@
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+
+/// An empty directory for one test, removed with everything in it when
+/// dropped
+pub struct ScratchDir(PathBuf);
+
+/// Create an empty scratch directory unique to one test (and run)
+pub fn scratch_dir(test: &str) -> ScratchDir {
+    let dir = std::env::temp_dir().join(format!("rchan-{}-{test}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    ScratchDir(dir)
+}
+
+impl Deref for ScratchDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
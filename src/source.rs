@@ -0,0 +1,474 @@
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::config::{RchanConfig, Remote};
+use crate::fetch::{self, SourceKind};
+use crate::git;
+use crate::pkgbuild::{self, ParseOptions, PkgVersion};
+use crate::ssh::SshTarget;
+
+/// Where a package's upstream version comes from, as resolved from its
+/// config: what to fetch, and how to read a version from what was fetched.
+/// Fetching itself is left to [`fetch::Fetcher`], which handles every
+/// transport (HTTP, local files, SSH) and the result cache.
+///
+/// Resolving takes two steps instead of one fetching `resolve_version()`:
+/// `--probe` stops at the upstream version, and only the check knows the
+/// local version a release source keeps the pkgrel of.
+pub trait PkgbuildSource {
+    /// Kind of remote, for reports and `--source-type`
+    fn kind(&self) -> SourceKind;
+
+    /// What is fetched: a URL, path or release API endpoint
+    fn location(&self) -> &str;
+
+    /// What the fetched content is, for messages ("remote PKGBUILD")
+    fn description(&self) -> String;
+
+    /// Whether the fetched content gets its version from `pkgver()`, so its
+    /// literal `pkgver=` means nothing
+    fn is_vcs(&self, _content: &str) -> bool {
+        false
+    }
+
+    /// The version as published upstream in the fetched content
+    fn upstream_version(&self, content: &str) -> Result<String>;
+
+    /// The PKGBUILD version an [`upstream_version`](Self::upstream_version)
+    /// amounts to, next to the local version
+    fn resolve_version(&self, upstream: &str, local: &PkgVersion) -> Result<PkgVersion>;
+}
+
+/// A PKGBUILD to parse: on the AUR, GitHub, GitLab or another web server, a
+/// local file or one read over SSH
+#[derive(Debug)]
+pub struct RemotePkgbuild {
+    kind: SourceKind,
+    location: String,
+    parse_options: ParseOptions,
+}
+
+impl PkgbuildSource for RemotePkgbuild {
+    fn kind(&self) -> SourceKind {
+        self.kind
+    }
+
+    fn location(&self) -> &str {
+        &self.location
+    }
+
+    fn description(&self) -> String {
+        "remote PKGBUILD".to_string()
+    }
+
+    fn is_vcs(&self, content: &str) -> bool {
+        pkgbuild::has_pkgver_function(content)
+    }
+
+    fn upstream_version(&self, content: &str) -> Result<String> {
+        Ok(pkgbuild::parse_remote(content, &self.parse_options)?.to_string())
+    }
+
+    fn resolve_version(&self, upstream: &str, _local: &PkgVersion) -> Result<PkgVersion> {
+        upstream.parse()
+    }
+}
+
+/// The latest release of a PyPI project
+#[derive(Debug)]
+pub struct PyPiRelease {
+    location: String,
+}
+
+impl PkgbuildSource for PyPiRelease {
+    fn kind(&self) -> SourceKind {
+        SourceKind::PyPi
+    }
+
+    fn location(&self) -> &str {
+        &self.location
+    }
+
+    fn description(&self) -> String {
+        format!("{} release info", self.kind())
+    }
+
+    fn upstream_version(&self, content: &str) -> Result<String> {
+        fetch::parse_pypi_version(content)
+    }
+
+    fn resolve_version(&self, upstream: &str, local: &PkgVersion) -> Result<PkgVersion> {
+        Ok(release_version(local, upstream))
+    }
+}
+
+/// The latest stable release of a crates.io crate
+#[derive(Debug)]
+pub struct CrateRelease {
+    location: String,
+}
+
+impl PkgbuildSource for CrateRelease {
+    fn kind(&self) -> SourceKind {
+        SourceKind::Crates
+    }
+
+    fn location(&self) -> &str {
+        &self.location
+    }
+
+    fn description(&self) -> String {
+        format!("{} release info", self.kind())
+    }
+
+    fn upstream_version(&self, content: &str) -> Result<String> {
+        fetch::parse_crate_version(content)
+    }
+
+    fn resolve_version(&self, upstream: &str, local: &PkgVersion) -> Result<PkgVersion> {
+        Ok(release_version(local, upstream))
+    }
+}
+
+/// The newest version tag of a git repository
+#[derive(Debug)]
+pub struct GitTagRelease {
+    location: String,
+}
+
+impl PkgbuildSource for GitTagRelease {
+    fn kind(&self) -> SourceKind {
+        SourceKind::Git
+    }
+
+    fn location(&self) -> &str {
+        &self.location
+    }
+
+    fn description(&self) -> String {
+        "git tags".to_string()
+    }
+
+    fn upstream_version(&self, content: &str) -> Result<String> {
+        git::newest_version_tag(content)
+    }
+
+    fn resolve_version(&self, upstream: &str, local: &PkgVersion) -> Result<PkgVersion> {
+        Ok(release_version(local, upstream))
+    }
+}
+
+/// A file holding just the version, like a `VERSION` file published upstream
+#[derive(Debug)]
+pub struct VersionFile {
+    kind: SourceKind,
+    location: String,
+}
+
+impl PkgbuildSource for VersionFile {
+    fn kind(&self) -> SourceKind {
+        self.kind
+    }
+
+    fn location(&self) -> &str {
+        &self.location
+    }
+
+    fn description(&self) -> String {
+        "remote version file".to_string()
+    }
+
+    fn upstream_version(&self, content: &str) -> Result<String> {
+        fetch::parse_version_file(content)
+    }
+
+    fn resolve_version(&self, upstream: &str, local: &PkgVersion) -> Result<PkgVersion> {
+        Ok(release_version(local, upstream))
+    }
+}
+
+/// The source for a config's `upstream` (from [`RchanConfig::remote`]).
+/// Remote PKGBUILD web pages are turned into raw file URLs (with a note),
+/// `ref` is applied and relative local paths are resolved against the
+/// package directory. PKGBUILDs are parsed with `parse_options`.
+pub fn for_remote(
+    config: &RchanConfig,
+    upstream: Remote,
+    pkgbuild_path: &Path,
+    parse_options: &ParseOptions,
+    notes: &mut Vec<String>,
+) -> Result<Box<dyn PkgbuildSource>, String> {
+    let kind = upstream.kind();
+    let unsupported_ref =
+        |source: &str| format!("ref is only supported with raw GitHub or GitLab URLs: {source}");
+    if kind == SourceKind::Local && config.git_ref.is_some() {
+        return Err(unsupported_ref(config.remote_pkgbuild.as_deref().unwrap_or_default()));
+    }
+
+    let location = match upstream {
+        Remote::Pkgbuild(url) if url.contains("://") || kind == SourceKind::Ssh => {
+            let url = match fetch::raw_url(url) {
+                Some(raw) => {
+                    notes.push(format!("remote_pkgbuild is a web page, not the file; using {raw}"));
+                    raw
+                }
+                None => url.to_string(),
+            };
+            match &config.git_ref {
+                None => url,
+                Some(git_ref) => match fetch::with_ref(&url, git_ref) {
+                    Some(url) => url,
+                    None => return Err(unsupported_ref(&url)),
+                },
+            }
+        }
+        // Relative local sources are relative to the package directory
+        Remote::Pkgbuild(path) => pkgbuild_path
+            .with_file_name(path)
+            .to_string_lossy()
+            .into_owned(),
+        Remote::PyPi(project) => {
+            return Ok(Box::new(PyPiRelease { location: fetch::pypi_api_url(project) }));
+        }
+        Remote::Crate(crate_name) => {
+            return Ok(Box::new(CrateRelease { location: fetch::crates_api_url(crate_name) }));
+        }
+        Remote::GitTags(url) => {
+            return Ok(Box::new(GitTagRelease {
+                location: fetch::git_tags_location(&relative_to(pkgbuild_path, url)),
+            }));
+        }
+        Remote::Tarball(tarball) => {
+            let url = relative_to(pkgbuild_path, &tarball.url);
+            fetch::tarball_location(&url, &tarball.path)
+        }
+        Remote::VersionFile(url) => {
+            let location = relative_to(pkgbuild_path, url);
+            return Ok(Box::new(VersionFile { kind, location }));
+        }
+    };
+    Ok(Box::new(RemotePkgbuild {
+        kind,
+        location,
+        parse_options: parse_options.clone(),
+    }))
+}
+
+/// `source` with a relative local path resolved against the package
+/// directory, like a local `remote_pkgbuild`
+fn relative_to(pkgbuild_path: &Path, source: &str) -> String {
+    match source.contains("://") || SshTarget::parse(source).is_some() {
+        true => source.to_string(),
+        false => pkgbuild_path.with_file_name(source).to_string_lossy().into_owned(),
+    }
+}
+
+/// The version a PKGBUILD would have for an upstream release.
+/// Release sources only give a pkgver: an unchanged pkgver keeps the local
+/// pkgrel, a new one starts again at pkgrel 1. `-` is not allowed in pkgver
+/// and is replaced with `_`.
+fn release_version(local: &PkgVersion, release: &str) -> PkgVersion {
+    let pkgver = release.replace('-', "_");
+    if pkgver == local.pkgver {
+        return local.clone();
+    }
+    PkgVersion {
+        epoch: local.epoch.clone(),
+        pkgver,
+        pkgrel: "1".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::Options;
+    use crate::fetch::Fetcher;
+    use crate::scratch::scratch_dir;
+
+    fn source(yaml: &str, notes: &mut Vec<String>) -> Box<dyn PkgbuildSource> {
+        let config = RchanConfig::from_yaml(yaml, Path::new("/srv/pkgs")).unwrap();
+        let upstream = config.remote().unwrap();
+        let pkgbuild_path = Path::new("/srv/pkgs/foo/PKGBUILD");
+        for_remote(&config, upstream, pkgbuild_path, &ParseOptions::default(), notes).unwrap()
+    }
+
+    /// Fetch `source` as a check does and resolve its version next to `local`
+    fn resolve(source: &dyn PkgbuildSource, local: &str) -> String {
+        let fetcher = Fetcher::new(&Options::default()).unwrap();
+        let content = fetcher.fetch(source.location(), fetcher.policy(), &mut Vec::new());
+        let upstream = source.upstream_version(&content.unwrap()).unwrap();
+        source.resolve_version(&upstream, &local.parse().unwrap()).unwrap().to_string()
+    }
+
+    #[test]
+    fn test_remote_pkgbuild() {
+        let dir = scratch_dir("source");
+        let path = dir.join("PKGBUILD");
+        std::fs::write(&path, "pkgver=1.2\npkgrel=3\n").unwrap();
+
+        let mut warnings = Vec::new();
+        let yaml = format!("remote_pkgbuild: file://{}\n", path.display());
+        let remote = source(&yaml, &mut warnings);
+        assert_eq!(remote.kind(), SourceKind::Local);
+        let fetcher = Fetcher::new(&Options::default()).unwrap();
+        let content = fetcher.fetch(remote.location(), fetcher.policy(), &mut warnings).unwrap();
+        let upstream = remote.upstream_version(&content).unwrap();
+        assert_eq!(upstream, "1.2-3");
+        let local = "1.0-1".parse().unwrap();
+        assert_eq!(remote.resolve_version(&upstream, &local).unwrap().to_string(), "1.2-3");
+        assert!(!remote.is_vcs(&content));
+        assert!(remote.is_vcs("pkgver=1\npkgrel=1\npkgver() {\n  echo 2\n}\n"));
+
+        // Relative to the package directory
+        let local = source("remote_pkgbuild: upstream\n", &mut warnings);
+        assert_eq!(local.location(), "/srv/pkgs/foo/upstream");
+        assert!(warnings.is_empty());
+
+        let page = "remote_pkgbuild: https://github.com/o/r/blob/main/PKGBUILD\n";
+        let mut notes = Vec::new();
+        let remote = source(page, &mut notes);
+        assert_eq!(remote.kind(), SourceKind::GitHub);
+        assert_eq!(remote.location(), "https://raw.githubusercontent.com/o/r/main/PKGBUILD");
+        assert_eq!(
+            notes,
+            ["remote_pkgbuild is a web page, not the file; \
+              using https://raw.githubusercontent.com/o/r/main/PKGBUILD"]
+        );
+    }
+
+    #[test]
+    fn test_unsupported_ref() {
+        let yaml = "remote_pkgbuild: upstream\nref: dev\n";
+        let config = RchanConfig::from_yaml(yaml, Path::new("/")).unwrap();
+        let upstream = config.remote().unwrap();
+        let path = Path::new("/srv/foo/PKGBUILD");
+        let result = for_remote(&config, upstream, path, &ParseOptions::default(), &mut Vec::new());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_pypi_release() {
+        let pypi = source("pypi: requests\n", &mut Vec::new());
+        assert_eq!(pypi.kind(), SourceKind::PyPi);
+        assert_eq!(pypi.location(), fetch::pypi_api_url("requests"));
+        assert_eq!(pypi.description(), "PyPI release info");
+        let content = r#"{"info": {"name": "requests", "version": "2.32.3"}, "releases": {}}"#;
+        let upstream = pypi.upstream_version(content).unwrap();
+        let local = "2.32.3-2".parse().unwrap();
+        assert_eq!(pypi.resolve_version(&upstream, &local).unwrap(), local);
+
+        // The API response served from a file
+        let dir = scratch_dir("pypi-release");
+        std::fs::write(dir.join("json"), content.replace("2.32.3", "2.33.0")).unwrap();
+        let pypi = PyPiRelease { location: format!("file://{}/json", dir.display()) };
+        assert_eq!(resolve(&pypi, "2.32.3-2"), "2.33.0-1");
+    }
+
+    #[test]
+    fn test_crate_release() {
+        let krate = source("crate: ripgrep\n", &mut Vec::new());
+        assert_eq!(krate.kind(), SourceKind::Crates);
+        assert_eq!(krate.location(), fetch::crates_api_url("ripgrep"));
+        let content = r#"{"crate": {"max_version": "15.0.0", "max_stable_version": "14.1.1"}}"#;
+        let upstream = krate.upstream_version(content).unwrap();
+        let local = "14.1.0-3".parse().unwrap();
+        assert_eq!(krate.resolve_version(&upstream, &local).unwrap().to_string(), "14.1.1-1");
+        assert!(krate.upstream_version("<html>").is_err());
+
+        // The API response served from a file
+        let dir = scratch_dir("crate-release");
+        std::fs::write(dir.join("ripgrep"), content).unwrap();
+        let krate = CrateRelease { location: format!("file://{}/ripgrep", dir.display()) };
+        assert_eq!(resolve(&krate, "14.1.1-3"), "14.1.1-3");
+    }
+
+    #[test]
+    fn test_git_tag_release() {
+        let tags = source("git_tags: https://git.example.org/foo.git\n", &mut Vec::new());
+        assert_eq!(tags.kind(), SourceKind::Git);
+        assert_eq!(tags.location(), "git+https://git.example.org/foo.git");
+        let content = "a1\trefs/tags/v0.9.0\nb2\trefs/tags/v0.10.0\nc3\trefs/tags/v0.10.0^{}\n";
+        let upstream = tags.upstream_version(content).unwrap();
+        assert_eq!(upstream, "0.10.0");
+        let local = "0.9.0-2".parse().unwrap();
+        assert_eq!(tags.resolve_version(&upstream, &local).unwrap().to_string(), "0.10.0-1");
+
+        let local = source("git_tags: ../upstream.git\n", &mut Vec::new());
+        assert_eq!(local.location(), "git+/srv/pkgs/foo/../upstream.git");
+
+        // A repository on disk, listed with git ls-remote
+        let dir = scratch_dir("git-tag-release");
+        let git = |args: &[&str]| {
+            let status = std::process::Command::new("git")
+                .args(["-c", "user.name=rchan", "-c", "user.email=rchan@localhost", "-C"])
+                .arg(&*dir)
+                .args(args)
+                .stdout(std::process::Stdio::null())
+                .status()
+                .unwrap();
+            assert!(status.success(), "git {args:?}");
+        };
+        git(&["init", "-q"]);
+        git(&["commit", "-q", "--allow-empty", "-m", "release"]);
+        git(&["tag", "v1.1.0"]);
+        git(&["tag", "v1.2.0"]);
+        let tags = GitTagRelease { location: fetch::git_tags_location(&dir.to_string_lossy()) };
+        assert_eq!(resolve(&tags, "1.1.0-2"), "1.2.0-1");
+    }
+
+    #[test]
+    fn test_remote_tarball() {
+        let dir = scratch_dir("tarball");
+        let mut builder = tar::Builder::new(Vec::new());
+        let content = "pkgver=2.0\npkgrel=1\n";
+        let mut header = tar::Header::new_gnu();
+        header.set_size(content.len() as u64);
+        header.set_cksum();
+        builder.append_data(&mut header, "foo-2.0/PKGBUILD", content.as_bytes()).unwrap();
+        std::fs::write(dir.join("foo.tar"), builder.into_inner().unwrap()).unwrap();
+
+        let yaml =
+            format!("remote_tarball:\n  url: {}/foo.tar\n  path: '*/PKGBUILD'\n", dir.display());
+        let remote = source(&yaml, &mut Vec::new());
+        assert_eq!(remote.kind(), SourceKind::Local);
+        let fetcher = Fetcher::new(&Options::default()).unwrap();
+        let content = fetcher.fetch(remote.location(), fetcher.policy(), &mut Vec::new()).unwrap();
+        assert_eq!(remote.upstream_version(&content).unwrap(), "2.0-1");
+
+        let relative = source("remote_tarball: {url: foo.tar, path: PKGBUILD}\n", &mut Vec::new());
+        assert_eq!(relative.location(), "tar+/srv/pkgs/foo/foo.tar#PKGBUILD");
+    }
+
+    #[test]
+    fn test_version_file() {
+        let url = "remote_version_file: https://example.org/foo/VERSION\n";
+        let remote = source(url, &mut Vec::new());
+        assert_eq!(remote.kind(), SourceKind::Url);
+        assert_eq!(remote.location(), "https://example.org/foo/VERSION");
+        let upstream = remote.upstream_version("3.1\n").unwrap();
+        let local = "3.1-4".parse().unwrap();
+        assert_eq!(remote.resolve_version(&upstream, &local).unwrap(), local);
+        let local = "3.0-4".parse().unwrap();
+        assert_eq!(remote.resolve_version(&upstream, &local).unwrap().to_string(), "3.1-1");
+
+        let relative = source("remote_version_file: VERSION\n", &mut Vec::new());
+        assert_eq!(relative.location(), "/srv/pkgs/foo/VERSION");
+
+        let dir = scratch_dir("version-file");
+        std::fs::write(dir.join("VERSION"), "3.2\n").unwrap();
+        let yaml = format!("remote_version_file: file://{}/VERSION\n", dir.display());
+        let remote = source(&yaml, &mut Vec::new());
+        assert_eq!(remote.kind(), SourceKind::Local);
+        assert_eq!(resolve(&*remote, "3.1-4"), "3.2-1");
+    }
+
+    #[test]
+    fn test_release_version() {
+        let local: PkgVersion = "1:0.9.0-3".parse().unwrap();
+        assert_eq!(release_version(&local, "0.9.0"), local);
+        let newer = release_version(&local, "1.0.0-rc1");
+        assert_eq!(newer.to_string(), "1:1.0.0_rc1-1");
+    }
+}
@@ -0,0 +1,152 @@
+use std::path::Path;
+use std::process::{Command, ExitCode};
+
+use anyhow::{Context, Result, bail};
+use colored::Colorize;
+
+use crate::builder;
+use crate::cli::Options;
+
+/// What refreshing one package's .SRCINFO did
+#[derive(Debug, PartialEq)]
+enum Refresh {
+    /// There was no .SRCINFO
+    Created,
+    /// The .SRCINFO was out of date
+    Updated,
+    Unchanged,
+}
+
+/// Run `rchan refresh-srcinfo [<name>...]`: regenerate `.SRCINFO` with
+/// `makepkg --printsrcinfo` in every package directory (or the named ones)
+/// and report which files changed. `--dry-run` only reports.
+pub fn run_refresh_srcinfo(base: &Path, opts: &Options) -> Result<ExitCode> {
+    let mut entries = builder::pkgbuild_dirs(base, opts.include_hidden)?;
+    if !opts.args.is_empty() {
+        for name in &opts.args {
+            if !entries.iter().any(|e| e.file_name().to_string_lossy() == name.as_str()) {
+                bail!("no package directory named '{name}' in {}", base.display());
+            }
+        }
+        entries.retain(|e| opts.args.contains(&e.file_name().to_string_lossy().into_owned()));
+    }
+    let makepkg = builder::find_executable(
+        opts.makepkg_bin.as_deref().unwrap_or(Path::new("makepkg")),
+    )?;
+
+    if !opts.no_banner {
+        println!(
+            "{} {}",
+            "rchan refresh-srcinfo".bold().cyan(),
+            "- .SRCINFO regenerator".dimmed()
+        );
+        println!("{} {}\n", "Working directory:".bold(), base.display());
+    }
+
+    let (mut changed, mut unchanged, mut failed) = (0, 0, 0);
+    for entry in &entries {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        match refresh(&makepkg, &entry.path(), opts.dry_run) {
+            Ok(Refresh::Unchanged) => {
+                unchanged += 1;
+                if !opts.quiet {
+                    println!("{} {name}", "OK".green().bold());
+                }
+            }
+            Ok(refresh) => {
+                changed += 1;
+                let what = match (refresh, opts.dry_run) {
+                    (Refresh::Created, false) => "created .SRCINFO",
+                    (Refresh::Created, true) => "would create .SRCINFO",
+                    (_, false) => "updated .SRCINFO",
+                    (_, true) => "would update .SRCINFO",
+                };
+                println!("{} {name} - {what}", "CHANGED".yellow().bold());
+            }
+            Err(e) => {
+                failed += 1;
+                println!("{} {name} - {e:#}", "ERROR".red().bold());
+            }
+        }
+    }
+
+    println!(
+        "\n{}: {} packages, {} changed, {} unchanged, {} failed",
+        "Summary".bold(),
+        entries.len(),
+        changed.to_string().yellow(),
+        unchanged,
+        failed.to_string().red()
+    );
+    Ok(match failed {
+        0 => ExitCode::SUCCESS,
+        _ => ExitCode::from(crate::EXIT_ERRORS),
+    })
+}
+
+/// Regenerate `dir/.SRCINFO`, writing it only when its content changes
+/// (and not at all with `dry_run`)
+fn refresh(makepkg: &Path, dir: &Path, dry_run: bool) -> Result<Refresh> {
+    let output = Command::new(makepkg)
+        .arg("--printsrcinfo")
+        .current_dir(dir)
+        .output()
+        .context("Failed to execute makepkg")?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        match stderr.lines().rfind(|l| !l.trim().is_empty()) {
+            Some(detail) => bail!("makepkg --printsrcinfo failed: {}", detail.trim()),
+            None => bail!("makepkg --printsrcinfo exited with {}", output.status),
+        }
+    }
+    if output.stdout.iter().all(u8::is_ascii_whitespace) {
+        bail!("makepkg --printsrcinfo printed nothing");
+    }
+
+    let path = dir.join(".SRCINFO");
+    let refresh = match std::fs::read(&path) {
+        Ok(current) if current == output.stdout => return Ok(Refresh::Unchanged),
+        Ok(_) => Refresh::Updated,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Refresh::Created,
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+    };
+    if !dry_run {
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, &output.stdout)
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, &path)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+    Ok(refresh)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+    use crate::scratch::scratch_dir;
+
+    #[test]
+    fn test_refresh() {
+        let dir = scratch_dir("srcinfo");
+        let makepkg = dir.join("makepkg");
+        std::fs::write(&makepkg, "#!/bin/sh\nprintf 'pkgbase = foo\\n\\tpkgver = 1\\n'\n").unwrap();
+        std::fs::set_permissions(&makepkg, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let srcinfo = dir.join(".SRCINFO");
+
+        assert_eq!(refresh(&makepkg, &dir, true).unwrap(), Refresh::Created);
+        assert!(!srcinfo.exists());
+        assert_eq!(refresh(&makepkg, &dir, false).unwrap(), Refresh::Created);
+        assert_eq!(std::fs::read_to_string(&srcinfo).unwrap(), "pkgbase = foo\n\tpkgver = 1\n");
+        assert_eq!(refresh(&makepkg, &dir, false).unwrap(), Refresh::Unchanged);
+
+        std::fs::write(&srcinfo, "pkgbase = foo\n\tpkgver = 0\n").unwrap();
+        assert_eq!(refresh(&makepkg, &dir, false).unwrap(), Refresh::Updated);
+
+        std::fs::write(&makepkg, "#!/bin/sh\necho 'PKGBUILD does not exist.' >&2\nexit 1\n")
+            .unwrap();
+        let err = refresh(&makepkg, &dir, false).unwrap_err();
+        assert!(err.to_string().contains("PKGBUILD does not exist."));
+    }
+}
@@ -0,0 +1,148 @@
+use std::process::Command;
+use std::time::Duration;
+
+use crate::process::{self, CommandError};
+
+/// A file on a host reachable over SSH
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SshTarget<'a> {
+    /// Destination passed to `ssh`: `host` or `user@host`, or a `Host` alias
+    pub host: &'a str,
+    pub port: Option<&'a str>,
+    /// Absolute, or relative to the remote home directory
+    pub path: &'a str,
+}
+
+impl<'a> SshTarget<'a> {
+    /// Parse `ssh://[user@]host[:port]/path` or scp-style `[user@]host:path`.
+    ///
+    /// As with git, `ssh://host/~/path` is relative to the remote home. As with
+    /// scp, a source is scp-style when a colon comes before any slash, unless
+    /// it starts with `/`, `./` or `~` (a local path) or the host is empty. A
+    /// single letter before the colon (`C:foo`) is a drive, not a host.
+    pub fn parse(source: &'a str) -> Option<Self> {
+        if let Some(rest) = source.strip_prefix("ssh://") {
+            let slash = rest.find('/')?;
+            let (authority, path) = rest.split_at(slash);
+            let is_port = |p: &str| !p.is_empty() && p.bytes().all(|b| b.is_ascii_digit());
+            let (host, port) = match authority.rsplit_once(':') {
+                Some((host, port)) if is_port(port) => (host, Some(port)),
+                _ => (authority, None),
+            };
+            let path = path.strip_prefix("/~/").unwrap_or(path);
+            if host.is_empty() || path.is_empty() || path == "/" {
+                return None;
+            }
+            return Some(Self { host, port, path });
+        }
+        if source.contains("://") || source.starts_with(['/', '~']) || source.starts_with("./") {
+            return None;
+        }
+        let (host, path) = source.split_once(':')?;
+        let hostname = host.rsplit_once('@').map_or(host, |(_, hostname)| hostname);
+        let drive = hostname.len() == 1 && hostname.bytes().all(|b| b.is_ascii_alphabetic());
+        if hostname.is_empty() || drive || host.contains('/') || path.is_empty() {
+            return None;
+        }
+        let path = path.strip_prefix("~/").unwrap_or(path);
+        Some(Self { host, port: None, path })
+    }
+}
+
+/// Read a file with `ssh host cat`, giving up after `timeout`.
+///
+/// `ssh` runs in batch mode so it never prompts: keys come from the user's
+/// agent and `~/.ssh/config` is honored as usual. More than `max_size` bytes
+/// of output is an error.
+pub fn read_file(
+    target: &SshTarget,
+    timeout: Duration,
+    max_size: u64,
+) -> Result<Vec<u8>, CommandError> {
+    let mut cmd = Command::new("ssh");
+    cmd.args(["-o", "BatchMode=yes", "-o"])
+        .arg(format!("ConnectTimeout={}", timeout.as_secs().max(1)));
+    if let Some(port) = target.port {
+        cmd.args(["-p", port]);
+    }
+    cmd.arg("--")
+        .arg(target.host)
+        .arg(format!("cat -- {}", shell_quote(target.path)));
+
+    let what = format!("ssh to {}", target.host);
+    let finished = process::run(&mut cmd, &what, timeout, max_size)?;
+    let detail = finished.detail();
+    match finished.code {
+        Some(0) if finished.overflow => Err(CommandError {
+            message: format!("file too large (over {max_size} bytes); is the path a PKGBUILD?"),
+            connection: false,
+        }),
+        Some(0) => Ok(finished.stdout),
+        // ssh itself exits 255 when it cannot connect or authenticate
+        Some(255) | None => Err(CommandError {
+            message: match detail {
+                "" => format!("{what} failed"),
+                detail => format!("{what} failed: {detail}"),
+            },
+            connection: true,
+        }),
+        Some(_) => Err(CommandError {
+            message: match detail {
+                "" => format!("cannot read {} on {}", target.path, target.host),
+                detail => format!("cannot read {} on {}: {detail}", target.path, target.host),
+            },
+            connection: false,
+        }),
+    }
+}
+
+/// Quote a path for the remote POSIX shell
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ssh_target() {
+        let t = |host, port, path| Some(SshTarget { host, port, path });
+        assert_eq!(
+            SshTarget::parse("ssh://me@build.lan/srv/pkgs/foo/PKGBUILD"),
+            t("me@build.lan", None, "/srv/pkgs/foo/PKGBUILD")
+        );
+        assert_eq!(
+            SshTarget::parse("ssh://build.lan:2222/~/foo/PKGBUILD"),
+            t("build.lan", Some("2222"), "foo/PKGBUILD")
+        );
+        assert_eq!(
+            SshTarget::parse("me@build.lan:pkgs/foo/PKGBUILD"),
+            t("me@build.lan", None, "pkgs/foo/PKGBUILD")
+        );
+        assert_eq!(
+            SshTarget::parse("build:/srv/foo/PKGBUILD"),
+            t("build", None, "/srv/foo/PKGBUILD")
+        );
+        assert_eq!(SshTarget::parse("ssh://build.lan"), None);
+        assert_eq!(SshTarget::parse("https://example.com/PKGBUILD"), None);
+        assert_eq!(SshTarget::parse("upstream/PKGBUILD"), None);
+        assert_eq!(SshTarget::parse("./odd:name/PKGBUILD"), None);
+        // Local paths, as scp reads them
+        assert_eq!(SshTarget::parse("./build:out/dir"), None);
+        assert_eq!(SshTarget::parse("/srv/build:out/PKGBUILD"), None);
+        assert_eq!(SshTarget::parse("~:foo/PKGBUILD"), None);
+        assert_eq!(SshTarget::parse("~/build:out/PKGBUILD"), None);
+        assert_eq!(SshTarget::parse("C:foo"), None);
+        // No host before the colon
+        assert_eq!(SshTarget::parse(":foo/PKGBUILD"), None);
+        assert_eq!(SshTarget::parse("me@:foo/PKGBUILD"), None);
+        assert_eq!(SshTarget::parse("build:out/dir"), t("build", None, "out/dir"));
+    }
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("a b"), "'a b'");
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+    }
+}
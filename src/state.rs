@@ -0,0 +1,174 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::scanner::{PackageCheck, ScanResult};
+
+/// File in the scanned directory that remembers the last check
+pub const STATE_FILE: &str = ".rchan-state.json";
+
+/// Remote versions seen by the previous check, to tell what changed since
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct State {
+    /// Last-seen remote version per package name
+    pub packages: BTreeMap<String, String>,
+    /// Packages whose last check ended in an error, for `--retry-errors`
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub errors: BTreeSet<String>,
+}
+
+impl State {
+    /// Load the state file; a missing file is an empty state
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    /// Write the state file, replacing it only once fully written
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut content = serde_json::to_string_pretty(self).expect("serializable state");
+        content.push('\n');
+        let tmp = path.with_extension("json.tmp");
+        std::fs::write(&tmp, content)
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, path).with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// The last-seen remote version of `result`'s package, if the remote
+    /// has moved on from it. Packages seen for the first time are not changes.
+    pub fn changed(&self, result: &ScanResult) -> Option<&str> {
+        let seen = self.packages.get(result.name())?;
+        let remote = remote_version(result)?;
+        (*seen != remote).then_some(seen.as_str())
+    }
+
+    /// Remember the remote versions of a finished check. Packages that
+    /// failed or weren't checked keep their previous entry. Which packages
+    /// failed is remembered too; a probe doesn't clear a failure.
+    pub fn record(&mut self, results: &[PackageCheck]) {
+        for check in results {
+            let name = check.result.name();
+            if let Some(remote) = remote_version(&check.result) {
+                self.packages.insert(name.to_string(), remote);
+            }
+            match check.result {
+                ScanResult::Error { .. } => {
+                    self.errors.insert(name.to_string());
+                }
+                ScanResult::Probed { .. } => {}
+                _ => {
+                    self.errors.remove(name);
+                }
+            }
+        }
+    }
+}
+
+/// Remote version a result reports; up to date means it equals the local one
+fn remote_version(result: &ScanResult) -> Option<String> {
+    match result {
+        ScanResult::Updated { remote_ver, .. } | ScanResult::RemoteOlder { remote_ver, .. } => {
+            Some(remote_ver.to_string())
+        }
+        ScanResult::UpToDate { local_ver, .. } => Some(local_ver.to_string()),
+        ScanResult::VcsPackage { .. } | ScanResult::Probed { .. } | ScanResult::Error { .. } => {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::scratch::scratch_dir;
+    use crate::fetch::SourceKind;
+
+    fn check(name: &str, local: &str, remote: Option<&str>) -> PackageCheck {
+        let local_ver = local.parse().unwrap();
+        let result = match remote {
+            Some(remote) => ScanResult::Updated {
+                name: name.to_string(),
+                source: SourceKind::Aur,
+                local_ver,
+                remote_ver: remote.parse().unwrap(),
+            },
+            None => ScanResult::UpToDate {
+                name: name.to_string(),
+                source: SourceKind::Aur,
+                local_ver,
+            },
+        };
+        PackageCheck {
+            result,
+            remote: None,
+            upstream_version: None,
+            elapsed: Duration::ZERO,
+            pkgbuild_path: format!("{name}/PKGBUILD").into(),
+            pkgver_var: None,
+            pkgbase: None,
+            lint: Vec::new(),
+            notes: Vec::new(),
+            label: None,
+            priority: None,
+        }
+    }
+
+    #[test]
+    fn test_changed_since_last_run() {
+        let mut state = State::default();
+        state.record(&[check("foo", "1.0-1", Some("1.1-1")), check("bar", "2.0-1", None)]);
+
+        // Still behind, but the remote hasn't moved
+        assert_eq!(state.changed(&check("foo", "1.0-1", Some("1.1-1")).result), None);
+        assert_eq!(state.changed(&check("foo", "1.0-1", Some("1.2-1")).result), Some("1.1-1"));
+        assert_eq!(state.changed(&check("bar", "2.0-1", Some("2.1-1")).result), Some("2.0-1"));
+        assert_eq!(state.changed(&check("new", "1.0-1", Some("1.1-1")).result), None);
+
+        state.record(&[check("foo", "1.0-1", Some("1.2-1"))]);
+        assert_eq!(state.packages["foo"], "1.2-1");
+        assert_eq!(state.packages["bar"], "2.0-1");
+    }
+
+    #[test]
+    fn test_record_errors() {
+        let error = |name: &str| PackageCheck {
+            result: ScanResult::Error {
+                name: name.to_string(),
+                source: None,
+                message: "boom".to_string(),
+            },
+            ..check(name, "1.0-1", None)
+        };
+        let mut state = State::default();
+        state.record(&[error("foo"), error("bar"), check("baz", "1.0-1", None)]);
+        assert_eq!(state.errors, BTreeSet::from(["bar".to_string(), "foo".to_string()]));
+        assert!(!state.packages.contains_key("foo"));
+
+        state.record(&[check("foo", "1.0-1", Some("1.1-1"))]);
+        assert_eq!(state.errors, BTreeSet::from(["bar".to_string()]));
+    }
+
+    #[test]
+    fn test_state_round_trip() {
+        let dir = scratch_dir("state");
+        let path = dir.join(STATE_FILE);
+        assert!(State::load(&path).unwrap().packages.is_empty());
+
+        let mut state = State::default();
+        state.record(&[check("foo", "1.0-1", Some("1:1.1-2"))]);
+        state.save(&path).unwrap();
+        assert_eq!(State::load(&path).unwrap().packages["foo"], "1:1.1-2");
+
+        std::fs::write(&path, "not json").unwrap();
+        assert!(State::load(&path).is_err());
+    }
+}
@@ -0,0 +1,292 @@
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use ratatui::DefaultTerminal;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, ListState, Paragraph};
+
+use crate::builder::format_duration;
+
+/// How much of the end of a log the log pane reads
+const LOG_TAIL: u64 = 64 * 1024;
+
+/// Where a package of the batch is
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RowState {
+    Queued,
+    Building(Instant),
+    Done { label: &'static str, ok: bool, elapsed: Duration },
+}
+
+struct Row {
+    name: String,
+    /// makepkg's output for this run (None until the build starts)
+    log: Option<PathBuf>,
+    state: RowState,
+}
+
+/// The `build --tui` live view: every package of the batch with its status,
+/// next to the end of the selected package's makepkg log. The terminal is
+/// restored when the view is dropped.
+pub struct LiveView {
+    terminal: DefaultTerminal,
+    board: Board,
+}
+
+/// What the live view shows, apart from the terminal it draws on
+struct Board {
+    rows: Vec<Row>,
+    list: ListState,
+    /// Lines scrolled back from the end of the log; 0 follows it
+    scroll: usize,
+    /// Lines in the log pane at the last draw, for paging
+    page: usize,
+    started: Instant,
+}
+
+impl LiveView {
+    /// Switch the terminal to the live view of a batch of `names`
+    pub fn start(names: &[String]) -> std::io::Result<Self> {
+        let terminal = ratatui::try_init()?;
+        let rows = names
+            .iter()
+            .map(|name| Row { name: name.clone(), log: None, state: RowState::Queued })
+            .collect();
+        let board = Board {
+            rows,
+            list: ListState::default().with_selected(Some(0)),
+            scroll: 0,
+            page: 0,
+            started: Instant::now(),
+        };
+        Ok(Self { terminal, board })
+    }
+
+    /// makepkg started for package `index`, writing to `log`. The selection
+    /// follows the newest build unless the user moved it.
+    pub fn building(&mut self, index: usize, log: PathBuf) {
+        let board = &mut self.board;
+        let follow = board.list.selected().is_none_or(|selected| {
+            !matches!(board.rows[selected].state, RowState::Building(_))
+        });
+        board.rows[index].log = Some(log);
+        board.rows[index].state = RowState::Building(Instant::now());
+        if follow {
+            board.select(index);
+        }
+    }
+
+    /// Package `index` is done; `label` is its status in the build table
+    pub fn finished(&mut self, index: usize, label: &'static str, ok: bool, elapsed: Duration) {
+        self.board.rows[index].state = RowState::Done { label, ok, elapsed };
+    }
+
+    /// Redraw, then handle keys for up to `timeout`. Returns false once the
+    /// user closes the view (`q`, Esc or Ctrl-C) or the terminal fails.
+    pub fn tick(&mut self, timeout: Duration) -> bool {
+        if !self.redraw() {
+            return false;
+        }
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match event::poll(remaining) {
+                Ok(true) => {}
+                Ok(false) => return true,
+                Err(_) => return false,
+            }
+            let Ok(Event::Key(key)) = event::read() else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            let board = &mut self.board;
+            let selected = board.list.selected().unwrap_or(0);
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return false,
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                    return false;
+                }
+                KeyCode::Up | KeyCode::Char('k') => board.select(selected.saturating_sub(1)),
+                KeyCode::Down | KeyCode::Char('j') => board.select(selected + 1),
+                KeyCode::PageUp => board.scroll += board.page.max(1),
+                KeyCode::PageDown => board.scroll = board.scroll.saturating_sub(board.page.max(1)),
+                KeyCode::End => board.scroll = 0,
+                _ => continue,
+            }
+            // Show the change at once
+            return self.redraw();
+        }
+    }
+
+    fn redraw(&mut self) -> bool {
+        let board = &mut self.board;
+        self.terminal.draw(|frame| draw(frame, board)).is_ok()
+    }
+}
+
+impl Board {
+    fn select(&mut self, index: usize) {
+        self.list.select(Some(index.min(self.rows.len().saturating_sub(1))));
+        self.scroll = 0;
+    }
+}
+
+impl Drop for LiveView {
+    fn drop(&mut self) {
+        ratatui::restore();
+    }
+}
+
+fn draw(frame: &mut ratatui::Frame, board: &mut Board) {
+    let [header, body, footer] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Min(3),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+    let name_width = board.rows.iter().map(|row| row.name.len()).max().unwrap_or(0);
+    let list_width = (name_width + 20).min(usize::from(body.width) / 2) as u16;
+    let [list_area, log_area] =
+        Layout::horizontal([Constraint::Length(list_width), Constraint::Min(10)]).areas(body);
+
+    let count = |f: fn(&RowState) -> bool| board.rows.iter().filter(|row| f(&row.state)).count();
+    let done = count(|state| matches!(state, RowState::Done { .. }));
+    let building = count(|state| matches!(state, RowState::Building(_)));
+    let failed = count(|state| matches!(state, RowState::Done { ok: false, .. }));
+    let status = format!(
+        "{done}/{} done, {building} building, {failed} failed in {}",
+        board.rows.len(),
+        format_duration(board.started.elapsed())
+    );
+    let bold = Style::new().add_modifier(Modifier::BOLD);
+    frame.render_widget(
+        Line::from(vec![
+            Span::styled("rchan build ", bold.fg(Color::Cyan)),
+            Span::raw(status),
+        ]),
+        header,
+    );
+
+    let items: Vec<ListItem> = board
+        .rows
+        .iter()
+        .map(|row| {
+            let (tag, color, time) = match row.state {
+                RowState::Queued => ("-", Color::DarkGray, String::new()),
+                RowState::Building(since) => {
+                    ("BUILD", Color::Yellow, format_duration(since.elapsed()))
+                }
+                RowState::Done { label, ok, elapsed } => {
+                    let color = if ok { Color::Green } else { Color::Red };
+                    (label, color, format_duration(elapsed))
+                }
+            };
+            ListItem::new(Line::from(vec![
+                Span::styled(format!("{tag:<6}"), bold.fg(color)),
+                Span::raw(format!("{:<name_width$} ", row.name)),
+                Span::styled(time, Style::new().fg(Color::DarkGray)),
+            ]))
+        })
+        .collect();
+    let list = List::new(items)
+        .block(Block::bordered().title(" Packages "))
+        .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+    frame.render_stateful_widget(list, list_area, &mut board.list);
+
+    let selected = &board.rows[board.list.selected().unwrap_or(0).min(board.rows.len() - 1)];
+    board.page = usize::from(log_area.height.saturating_sub(2));
+    let lines = match &selected.log {
+        Some(path) => read_tail(path)
+            .map(|text| log_lines(&text))
+            .unwrap_or_else(|e| vec![format!("cannot read {}: {e}", path.display())]),
+        None => vec!["not started".to_string()],
+    };
+    // Keep a full page in view when scrolled back to the start
+    board.scroll = board.scroll.min(lines.len().saturating_sub(board.page));
+    let end = lines.len() - board.scroll;
+    let shown: Vec<Line> =
+        lines[end.saturating_sub(board.page)..end].iter().map(|l| Line::raw(l.as_str())).collect();
+    let title = match board.scroll {
+        0 => format!(" {} ", selected.name),
+        n => format!(" {} ({n} lines back) ", selected.name),
+    };
+    frame.render_widget(Paragraph::new(shown).block(Block::bordered().title(title)), log_area);
+
+    frame.render_widget(
+        Line::styled(
+            " ↑/↓ select  PgUp/PgDn scroll log  End follow  q leave the live view",
+            Style::new().fg(Color::DarkGray),
+        ),
+        footer,
+    );
+}
+
+/// The last [`LOG_TAIL`] bytes of a log
+fn read_tail(path: &Path) -> std::io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let len = file.metadata()?.len();
+    file.seek(SeekFrom::Start(len.saturating_sub(LOG_TAIL)))?;
+    let mut bytes = Vec::new();
+    file.read_to_end(&mut bytes)?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Log text as the lines a terminal would show: color codes removed, and a
+/// line redrawn with `\r` (progress bars) reduced to what was drawn last
+fn log_lines(text: &str) -> Vec<String> {
+    text.lines()
+        .map(|line| {
+            let line = line.trim_end_matches('\r');
+            let line = line.rsplit('\r').next().unwrap_or_default();
+            strip_escapes(line)
+        })
+        .collect()
+}
+
+/// `line` without ANSI escape sequences (`ESC [ ... letter`) and tabs
+/// expanded, as the pane can show neither
+fn strip_escapes(line: &str) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut chars = line.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1b' => {
+                if chars.next() == Some('[') {
+                    for c in chars.by_ref() {
+                        if ('@'..='~').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+            }
+            '\t' => out.push_str("    "),
+            c if c.is_control() => {}
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_lines() {
+        let log = "==> Making package: foo 1.0-1\n\
+                   \x1b[1;32m==>\x1b[0m Building\r\n\
+                   10%\r50%\r100%\n\
+                   \tcc -O2 foo.c\n";
+        assert_eq!(
+            log_lines(log),
+            ["==> Making package: foo 1.0-1", "==> Building", "100%", "    cc -O2 foo.c"]
+        );
+        assert!(log_lines("").is_empty());
+    }
+}
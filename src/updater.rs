@@ -0,0 +1,219 @@
+use std::path::Path;
+use std::process::ExitCode;
+
+use anyhow::{Context, Result, bail};
+use colored::Colorize;
+
+use crate::cli::Options;
+use crate::fetch::Fetcher;
+use crate::output;
+use crate::pkgbuild::{self, PkgVersion};
+use crate::scanner::{self, PackageCheck, ScanResult};
+
+/// How `rchan update` writes pkgrel when rewriting a PKGBUILD
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PkgrelPolicy {
+    /// Take the remote pkgrel
+    Remote,
+    /// Reset pkgrel to 1
+    Reset,
+    /// Keep the local pkgrel (e.g. when carrying local patches)
+    Keep,
+}
+
+impl std::str::FromStr for PkgrelPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "remote" => Ok(PkgrelPolicy::Remote),
+            "reset" => Ok(PkgrelPolicy::Reset),
+            "keep" => Ok(PkgrelPolicy::Keep),
+            other => bail!("unknown pkgrel policy '{other}' (expected remote, reset or keep)"),
+        }
+    }
+}
+
+/// Compute the version to write for an updated package.
+/// Without an explicit policy, pkgrel resets to 1 on a pkgver change and
+/// follows the remote on a pkgrel-only change.
+pub fn target_version(
+    local: &PkgVersion,
+    remote: &PkgVersion,
+    policy: Option<PkgrelPolicy>,
+) -> PkgVersion {
+    let policy = policy.unwrap_or(if local.pkgver != remote.pkgver {
+        PkgrelPolicy::Reset
+    } else {
+        PkgrelPolicy::Remote
+    });
+    let pkgrel = match policy {
+        PkgrelPolicy::Remote => remote.pkgrel.clone(),
+        PkgrelPolicy::Reset => "1".to_string(),
+        PkgrelPolicy::Keep => local.pkgrel.clone(),
+    };
+    PkgVersion {
+        epoch: remote.epoch.clone().or_else(|| local.epoch.clone()),
+        pkgver: remote.pkgver.clone(),
+        pkgrel,
+    }
+}
+
+/// Run `rchan update`: rewrite the local PKGBUILD of every updated package
+/// to the remote version
+pub fn run_update(base: &Path, opts: &Options) -> Result<ExitCode> {
+    if !opts.no_banner {
+        println!(
+            "{} {}",
+            "rchan update".bold().cyan(),
+            "- PKGBUILD version updater".dimmed()
+        );
+        println!("{} {}\n", "Working directory:".bold(), base.display());
+    }
+
+    // Rewrite each PKGBUILD as soon as its check completes. The callback runs
+    // on this thread, so writes never overlap while fetches go on in parallel;
+    // the lines are printed in check order once the scan is done.
+    let fetcher = Fetcher::new(opts)?;
+    let mut lines = Vec::new();
+    let mut updated_count = 0;
+    let mut error_count = 0;
+    let scan = scanner::scan_directory(base, opts, &fetcher, |index, check, _| {
+        let Some((outcome, line)) = apply_update(check, opts) else {
+            return;
+        };
+        match outcome {
+            Outcome::Updated => updated_count += 1,
+            Outcome::Failed => error_count += 1,
+            Outcome::Kept => {}
+        }
+        lines.push((index, line));
+    })?;
+    lines.sort_by_key(|(index, _)| *index);
+    for (_, line) in lines {
+        println!("{line}");
+    }
+
+    println!();
+    println!(
+        "{}: {} checked, {} {}, {} errors",
+        "Summary".bold(),
+        scan.results.len(),
+        updated_count.to_string().green(),
+        if opts.dry_run { "would be updated" } else { "updated" },
+        error_count.to_string().red()
+    );
+
+    Ok(if error_count > 0 {
+        ExitCode::from(crate::EXIT_ERRORS)
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+/// What [`apply_update`] did with a package
+enum Outcome {
+    Updated,
+    Kept,
+    Failed,
+}
+
+/// Rewrite the PKGBUILD of an updated package to the remote version (unless
+/// `--dry-run`), returning what happened and the line to print for it.
+/// Packages with nothing to rewrite give None.
+fn apply_update(check: &PackageCheck, opts: &Options) -> Option<(Outcome, String)> {
+    let name = output::display_name(check.result.name(), check.pkgbase.as_deref());
+    match &check.result {
+        ScanResult::Updated {
+            local_ver,
+            remote_ver,
+            ..
+        } => {
+            let target = target_version(local_ver, remote_ver, opts.pkgrel_policy);
+            if &target == local_ver {
+                let line = format!(
+                    "{} {} {} (pkgrel kept)",
+                    "SKIP".yellow().bold(),
+                    name.white(),
+                    local_ver.to_string().dimmed()
+                );
+                return Some((Outcome::Kept, line));
+            }
+            let var = check.pkgver_var.as_deref().unwrap_or("pkgver");
+            Some(match write_version(&check.pkgbuild_path, &target, var, opts.dry_run) {
+                Ok(()) => (
+                    Outcome::Updated,
+                    format!(
+                        "{} {} {} -> {}",
+                        if opts.dry_run { "WOULD UPDATE" } else { "UPDATED" }
+                            .green()
+                            .bold(),
+                        name.white().bold(),
+                        local_ver.to_string().dimmed(),
+                        target.to_string().green()
+                    ),
+                ),
+                Err(e) => (
+                    Outcome::Failed,
+                    format!("{} {} - {}", "ERROR".red().bold(), name.white(), e),
+                ),
+            })
+        }
+        ScanResult::Error { message, .. } => Some((
+            Outcome::Failed,
+            format!("{} {} - {}", "ERROR".red().bold(), name.white(), message),
+        )),
+        // Never downgrade; the check already warned about it
+        ScanResult::RemoteOlder { .. }
+        | ScanResult::UpToDate { .. }
+        | ScanResult::VcsPackage { .. }
+        | ScanResult::Probed { .. } => None,
+    }
+}
+
+/// Rewrite a local PKGBUILD to `target` (only validated when `dry_run`)
+fn write_version(path: &Path, target: &PkgVersion, var: &str, dry_run: bool) -> Result<()> {
+    let content = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read PKGBUILD: {}", path.display()))?;
+    let rewritten = pkgbuild::set_version(&content, target, var)?;
+    if !dry_run {
+        std::fs::write(path, rewritten)
+            .with_context(|| format!("Failed to write PKGBUILD: {}", path.display()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ver(pkgver: &str, pkgrel: &str) -> PkgVersion {
+        PkgVersion {
+            epoch: None,
+            pkgver: pkgver.to_string(),
+            pkgrel: pkgrel.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_target_version_defaults() {
+        // pkgver change resets pkgrel
+        let target = target_version(&ver("1.0", "3"), &ver("1.1", "2"), None);
+        assert_eq!(target, ver("1.1", "1"));
+        // pkgrel-only change follows the remote
+        let target = target_version(&ver("1.0", "1"), &ver("1.0", "2"), None);
+        assert_eq!(target, ver("1.0", "2"));
+    }
+
+    #[test]
+    fn test_target_version_policies() {
+        let local = ver("1.0", "3");
+        let remote = ver("1.1", "2");
+        let remote_policy = target_version(&local, &remote, Some(PkgrelPolicy::Remote));
+        assert_eq!(remote_policy, ver("1.1", "2"));
+        let keep = target_version(&local, &remote, Some(PkgrelPolicy::Keep));
+        assert_eq!(keep, ver("1.1", "3"));
+        let reset = target_version(&local, &remote, Some(PkgrelPolicy::Reset));
+        assert_eq!(reset, ver("1.1", "1"));
+    }
+}
@@ -0,0 +1,165 @@
+use std::cmp::Ordering;
+
+use crate::pkgbuild::PkgVersion;
+
+/// Compare two full package versions the way pacman does (`vercmp`):
+/// epoch first (missing means 0), then pkgver, then pkgrel.
+pub fn vercmp(a: &PkgVersion, b: &PkgVersion) -> Ordering {
+    let epoch = |v: &PkgVersion| v.epoch.clone().unwrap_or_else(|| "0".to_string());
+    rpmvercmp(&epoch(a), &epoch(b))
+        .then_with(|| rpmvercmp(&a.pkgver, &b.pkgver))
+        .then_with(|| rpmvercmp(&a.pkgrel, &b.pkgrel))
+}
+
+/// Compare two version segments with libalpm's `rpmvercmp` algorithm.
+///
+/// Versions are split into alternating numeric and alphabetic runs separated
+/// by non-alphanumeric characters. Numeric runs compare by value and always
+/// beat alphabetic runs; a trailing alphabetic run (e.g. `1.0alpha`) is older
+/// than nothing at all (`1.0`).
+pub fn rpmvercmp(a: &str, b: &str) -> Ordering {
+    if a == b {
+        return Ordering::Equal;
+    }
+
+    let a = a.as_bytes();
+    let b = b.as_bytes();
+    // `one`/`two` walk the strings; `ptr1`/`ptr2` mark the end of the last segment
+    let (mut one, mut two) = (0, 0);
+    let (mut ptr1, mut ptr2) = (0, 0);
+
+    while one < a.len() && two < b.len() {
+        while one < a.len() && !a[one].is_ascii_alphanumeric() {
+            one += 1;
+        }
+        while two < b.len() && !b[two].is_ascii_alphanumeric() {
+            two += 1;
+        }
+        if one >= a.len() || two >= b.len() {
+            break;
+        }
+
+        // Different separator lengths decide the comparison
+        if one - ptr1 != two - ptr2 {
+            return (one - ptr1).cmp(&(two - ptr2));
+        }
+
+        ptr1 = one;
+        ptr2 = two;
+        let is_num = a[ptr1].is_ascii_digit();
+        let same_kind = |c: u8| {
+            if is_num {
+                c.is_ascii_digit()
+            } else {
+                c.is_ascii_alphabetic()
+            }
+        };
+        while ptr1 < a.len() && same_kind(a[ptr1]) {
+            ptr1 += 1;
+        }
+        while ptr2 < b.len() && same_kind(b[ptr2]) {
+            ptr2 += 1;
+        }
+
+        // Segments of different kinds: numeric is newer
+        if two == ptr2 {
+            return if is_num {
+                Ordering::Greater
+            } else {
+                Ordering::Less
+            };
+        }
+
+        let mut seg1 = &a[one..ptr1];
+        let mut seg2 = &b[two..ptr2];
+        if is_num {
+            while seg1.first() == Some(&b'0') {
+                seg1 = &seg1[1..];
+            }
+            while seg2.first() == Some(&b'0') {
+                seg2 = &seg2[1..];
+            }
+            // More digits means a larger number
+            match seg1.len().cmp(&seg2.len()) {
+                Ordering::Equal => {}
+                other => return other,
+            }
+        }
+        match seg1.cmp(seg2) {
+            Ordering::Equal => {}
+            other => return other,
+        }
+
+        one = ptr1;
+        two = ptr2;
+    }
+
+    let rest1 = &a[one.min(a.len())..];
+    let rest2 = &b[two.min(b.len())..];
+    if rest1.is_empty() && rest2.is_empty() {
+        return Ordering::Equal;
+    }
+
+    // A remaining alpha segment never beats an empty one
+    let alpha = |s: &[u8]| s.first().is_some_and(|c| c.is_ascii_alphabetic());
+    if (rest1.is_empty() && !alpha(rest2)) || alpha(rest1) {
+        Ordering::Less
+    } else {
+        Ordering::Greater
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ver(epoch: Option<&str>, pkgver: &str, pkgrel: &str) -> PkgVersion {
+        PkgVersion {
+            epoch: epoch.map(str::to_string),
+            pkgver: pkgver.to_string(),
+            pkgrel: pkgrel.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_rpmvercmp() {
+        // Expected results follow pacman's vercmp
+        let cases = [
+            ("1.5.0", "1.5.0", Ordering::Equal),
+            ("1.5.1", "1.5.0", Ordering::Greater),
+            ("1.5.0", "1.5", Ordering::Greater),
+            ("1.5a", "1.5", Ordering::Less),
+            ("1.5.0alpha", "1.5.0", Ordering::Less),
+            ("1.1alpha", "1.1beta", Ordering::Less),
+            ("1.0a", "1.0", Ordering::Less),
+            ("1.0", "1.0.a", Ordering::Less),
+            ("1.001", "1.1", Ordering::Equal),
+            ("1.10", "1.9", Ordering::Greater),
+            ("1_0", "1.0", Ordering::Equal),
+            ("1..0", "1.0", Ordering::Greater),
+            ("1.0.r10.g1234", "1.0.r9.gabcd", Ordering::Greater),
+        ];
+        for (a, b, expected) in cases {
+            assert_eq!(rpmvercmp(a, b), expected, "rpmvercmp({a}, {b})");
+            assert_eq!(rpmvercmp(b, a), expected.reverse(), "rpmvercmp({b}, {a})");
+        }
+    }
+
+    #[test]
+    fn test_vercmp_epoch_wins() {
+        let old = ver(None, "2.0", "1");
+        let new = ver(Some("1"), "1.0", "1");
+        assert_eq!(vercmp(&old, &new), Ordering::Less);
+        assert_eq!(vercmp(&ver(Some("0"), "1.0", "1"), &ver(None, "1.0", "1")), Ordering::Equal);
+    }
+
+    #[test]
+    fn test_vercmp_decimal_pkgrel() {
+        let rel1 = ver(None, "1.0", "1");
+        let rel1_1 = ver(None, "1.0", "1.1");
+        let rel2 = ver(None, "1.0", "2");
+        assert_eq!(vercmp(&rel1_1, &rel1), Ordering::Greater);
+        assert_eq!(vercmp(&rel1_1, &rel2), Ordering::Less);
+        assert_eq!(vercmp(&rel1, &rel2), Ordering::Less);
+    }
+}
@@ -0,0 +1,120 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::process::ExitCode;
+
+use anyhow::Result;
+use colored::Colorize;
+
+use crate::cli::Options;
+use crate::fetch::Fetcher;
+use crate::scanner::{self, ScanResult};
+
+/// Run `rchan watch`: re-check every `opts.interval` and print packages
+/// whose result changed since the previous pass.
+///
+/// With `--exit-on-update`, returns success as soon as a package becomes
+/// updated: one that already was on the first pass doesn't count, as the
+/// wait is for a new release. Otherwise it runs until interrupted.
+pub fn run_watch(base: &Path, opts: &Options) -> Result<ExitCode> {
+    if !opts.no_banner {
+        println!(
+            "{} {}",
+            "rchan watch".bold().cyan(),
+            "- PKGBUILD update watcher".dimmed()
+        );
+        println!(
+            "{} {} (every {}s)\n",
+            "Watching:".bold(),
+            base.display(),
+            opts.interval.as_secs_f64()
+        );
+    }
+
+    // Last rendered line per package, to only print changes
+    let mut previous: HashMap<String, String> = HashMap::new();
+    // Packages updated on the previous pass (None before the first)
+    let mut updated: Option<HashSet<String>> = None;
+    loop {
+        // A fresh fetcher per pass, so `--deadline` caps each pass
+        let fetcher = Fetcher::new(opts)?;
+        let scan = scanner::scan_directory(base, opts, &fetcher, |_, _, _| {})?;
+
+        for check in &scan.results {
+            let result = &check.result;
+            let rendered = crate::render_line(opts, check);
+            if previous.get(result.name()) != Some(&rendered) {
+                let unremarkable =
+                    matches!(result, ScanResult::UpToDate { .. } | ScanResult::VcsPackage { .. });
+                if !(opts.quiet && unremarkable) {
+                    println!("{rendered}");
+                }
+                previous.insert(result.name().to_string(), rendered);
+            }
+        }
+
+        if opts.exit_on_update {
+            let new = newly_updated(&mut updated, scan.results.iter().map(|c| &c.result));
+            if !new.is_empty() {
+                println!("\n{} {}", "Update available:".bold(), new.join(", "));
+                return Ok(ExitCode::SUCCESS);
+            }
+        }
+
+        std::thread::sleep(opts.interval);
+    }
+}
+
+/// Names of the packages updated in `results` that weren't in `updated`,
+/// which then holds this pass's updated packages. The first pass (`updated`
+/// None) only records them.
+fn newly_updated<'a>(
+    updated: &mut Option<HashSet<String>>,
+    results: impl Iterator<Item = &'a ScanResult>,
+) -> Vec<String> {
+    let now: HashSet<String> = results
+        .filter(|r| matches!(r, ScanResult::Updated { .. }))
+        .map(|r| r.name().to_string())
+        .collect();
+    let mut new: Vec<String> = match updated.as_ref() {
+        Some(before) => now.difference(before).cloned().collect(),
+        None => Vec::new(),
+    };
+    new.sort();
+    *updated = Some(now);
+    new
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fetch::SourceKind;
+
+    #[test]
+    fn test_newly_updated() {
+        let result = |name: &str, remote: Option<&str>| {
+            let local_ver = "1-1".parse().unwrap();
+            let name = name.to_string();
+            match remote {
+                Some(remote) => ScanResult::Updated {
+                    name,
+                    source: SourceKind::Aur,
+                    local_ver,
+                    remote_ver: remote.parse().unwrap(),
+                },
+                None => ScanResult::UpToDate {
+                    name,
+                    source: SourceKind::Aur,
+                    local_ver,
+                },
+            }
+        };
+        let mut updated = None;
+        // foo was already updated when watching started
+        let first = [result("foo", Some("2-1")), result("bar", None)];
+        assert!(newly_updated(&mut updated, first.iter()).is_empty());
+        let second = [result("foo", Some("3-1")), result("bar", None)];
+        assert!(newly_updated(&mut updated, second.iter()).is_empty());
+        let third = [result("foo", Some("3-1")), result("bar", Some("2-1"))];
+        assert_eq!(newly_updated(&mut updated, third.iter()), ["bar"]);
+    }
+}
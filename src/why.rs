@@ -0,0 +1,219 @@
+use std::cmp::Ordering;
+use std::path::Path;
+use std::process::ExitCode;
+
+use anyhow::{Result, bail};
+use colored::Colorize;
+
+use crate::cli::Options;
+use crate::fetch::Fetcher;
+use crate::output;
+use crate::pkgbuild::PkgVersion;
+use crate::scanner::{self, ScanResult};
+use crate::vercmp::rpmvercmp;
+
+/// One component of the local and remote versions, side by side
+#[derive(Debug, PartialEq)]
+struct Component {
+    name: &'static str,
+    local: String,
+    remote: String,
+    /// The remote against the local one, by vercmp
+    order: Ordering,
+}
+
+/// Run `rchan why <name>`: check one package and show how its local and
+/// remote versions compare, component by component, and which component
+/// decided the verdict
+pub fn run_why(base: &Path, opts: &Options) -> Result<ExitCode> {
+    let [name] = opts.args.as_slice() else {
+        bail!("usage: rchan why <name>");
+    };
+    if !opts.no_banner {
+        println!(
+            "{} {}",
+            "rchan why".bold().cyan(),
+            "- version comparison explained".dimmed()
+        );
+        println!("{} {}\n", "Working directory:".bold(), base.display());
+    }
+
+    let fetcher = Fetcher::new(opts)?;
+    let (check, warnings, srcinfo) = scanner::check_named(base, opts, &fetcher, name)?;
+    for warning in &warnings {
+        println!("{} {warning}", "WARN".yellow().bold());
+    }
+    let local_file = match srcinfo {
+        true => check.pkgbuild_path.with_file_name(".SRCINFO"),
+        false => check.pkgbuild_path.clone(),
+    };
+    println!("{} {}", "Local: ".bold(), local_file.display());
+    if let Some(remote) = &check.remote {
+        match &check.upstream_version {
+            Some(published) => {
+                println!("{} {remote} (published as {published})", "Remote:".bold())
+            }
+            None => println!("{} {remote}", "Remote:".bold()),
+        }
+    }
+    println!();
+
+    let (local_ver, remote_ver) = match &check.result {
+        ScanResult::Updated {
+            local_ver,
+            remote_ver,
+            ..
+        }
+        | ScanResult::RemoteOlder {
+            local_ver,
+            remote_ver,
+            ..
+        } => (local_ver, remote_ver),
+        // Nothing newer was found; the remote resolved to the local version
+        // (up to pkgrel with --ignore-pkgrel)
+        ScanResult::UpToDate { local_ver, .. } => {
+            println!("{}", output::render(&check.result, check.pkgbase.as_deref()));
+            println!("\n{}", explain(local_ver, local_ver, opts.ignore_pkgrel));
+            return Ok(ExitCode::SUCCESS);
+        }
+        ScanResult::VcsPackage { .. } | ScanResult::Probed { .. } | ScanResult::Error { .. } => {
+            println!("{}", output::render(&check.result, check.pkgbase.as_deref()));
+            println!("\nNo versions were compared.");
+            return Ok(match check.result.is_failure() {
+                true => ExitCode::from(crate::EXIT_ERRORS),
+                false => ExitCode::SUCCESS,
+            });
+        }
+    };
+
+    let rows = components(local_ver, remote_ver);
+    let width = |side: fn(&Component) -> &str, title: &str| {
+        rows.iter().map(|row| side(row).len()).chain([title.len()]).max().unwrap_or(0)
+    };
+    let local_width = width(|row| &row.local, "local");
+    let remote_width = width(|row| &row.remote, "remote");
+    println!("  {:8}{:local_width$}  {:remote_width$}", "", "local", "remote");
+    for row in &rows {
+        let comparison = match row.order {
+            _ if row.name == "pkgrel" && opts.ignore_pkgrel => {
+                "ignored (--ignore-pkgrel)".dimmed()
+            }
+            Ordering::Greater => "remote newer".green(),
+            Ordering::Less => "remote older".red(),
+            Ordering::Equal if row.local != row.remote => "equal by vercmp".yellow(),
+            Ordering::Equal => "equal".dimmed(),
+        };
+        println!(
+            "  {:8}{:local_width$}  {:remote_width$}  {comparison}",
+            row.name, row.local, row.remote
+        );
+    }
+    println!("\n{}", output::render(&check.result, check.pkgbase.as_deref()));
+    println!("{}", explain(local_ver, remote_ver, opts.ignore_pkgrel));
+
+    Ok(match check.result {
+        ScanResult::Updated { .. } => ExitCode::from(crate::EXIT_UPDATES),
+        _ => ExitCode::SUCCESS,
+    })
+}
+
+/// Epoch, pkgver and pkgrel of both versions with how they compare. A
+/// missing epoch is shown as `-` and compares as 0, as in vercmp.
+fn components(local: &PkgVersion, remote: &PkgVersion) -> [Component; 3] {
+    let epoch = |v: &PkgVersion| v.epoch.clone().unwrap_or_else(|| "-".to_string());
+    let epoch_value = |v: &PkgVersion| v.epoch.clone().unwrap_or_else(|| "0".to_string());
+    [
+        Component {
+            name: "epoch",
+            local: epoch(local),
+            remote: epoch(remote),
+            order: rpmvercmp(&epoch_value(remote), &epoch_value(local)),
+        },
+        Component {
+            name: "pkgver",
+            local: local.pkgver.clone(),
+            remote: remote.pkgver.clone(),
+            order: rpmvercmp(&remote.pkgver, &local.pkgver),
+        },
+        Component {
+            name: "pkgrel",
+            local: local.pkgrel.clone(),
+            remote: remote.pkgrel.clone(),
+            order: rpmvercmp(&remote.pkgrel, &local.pkgrel),
+        },
+    ]
+}
+
+/// Which component decided the verdict, in words. vercmp compares epoch,
+/// then pkgver, then pkgrel, and the first difference decides. Versions
+/// written differently that vercmp still finds equal (`1.0` and `1.00`)
+/// count as a change, as check compares the text.
+fn explain(local: &PkgVersion, remote: &PkgVersion, ignore_pkgrel: bool) -> String {
+    let rows = components(local, remote);
+    let considered = match ignore_pkgrel {
+        true => &rows[..2],
+        false => &rows[..],
+    };
+    if let Some(row) = considered.iter().find(|row| row.order.is_ne()) {
+        let newer = match row.order {
+            Ordering::Greater => "newer",
+            _ => "older",
+        };
+        return format!(
+            "The {} decides: remote {} is {newer} than local {}{}.",
+            row.name,
+            row.remote,
+            row.local,
+            match row.name {
+                "epoch" => "; pkgver and pkgrel don't matter then",
+                "pkgver" => "; pkgrel doesn't matter then",
+                _ => "",
+            }
+        );
+    }
+    match considered.iter().find(|row| row.local != row.remote) {
+        Some(row) => format!(
+            "vercmp finds the versions equal, but the {} is written differently \
+             ({} locally, {} remotely), which counts as a change.",
+            row.name, row.local, row.remote
+        ),
+        None if ignore_pkgrel => "Epoch and pkgver are equal; pkgrel is ignored.".to_string(),
+        None => "All components are equal.".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ver(version: &str) -> PkgVersion {
+        version.parse().unwrap()
+    }
+
+    #[test]
+    fn test_components() {
+        let rows = components(&ver("1.2.3-1"), &ver("1:1.2.3-2"));
+        let orders: Vec<_> = rows.iter().map(|row| row.order).collect();
+        assert_eq!(orders, [Ordering::Greater, Ordering::Equal, Ordering::Greater]);
+        assert_eq!((rows[0].local.as_str(), rows[0].remote.as_str()), ("-", "1"));
+    }
+
+    #[test]
+    fn test_explain() {
+        let pkgrel = explain(&ver("1.2.3-1"), &ver("1.2.3-2"), false);
+        assert_eq!(pkgrel, "The pkgrel decides: remote 2 is newer than local 1.");
+        let epoch = explain(&ver("2.0-1"), &ver("1:1.0-1"), false);
+        assert!(epoch.starts_with("The epoch decides: remote 1 is newer than local -"));
+        let older = explain(&ver("1.10-1"), &ver("1.9-1"), false);
+        assert!(older.starts_with("The pkgver decides: remote 1.9 is older than local 1.10"));
+
+        let normalized = explain(&ver("1.0-1"), &ver("1.00-1"), false);
+        assert!(normalized.contains("pkgver is written differently (1.0 locally, 1.00"));
+        let zero_epoch = explain(&ver("1.0-1"), &ver("0:1.0-1"), false);
+        assert!(zero_epoch.contains("the epoch is written differently"));
+
+        assert_eq!(explain(&ver("1.0-1"), &ver("1.0-1"), false), "All components are equal.");
+        let ignored = explain(&ver("1.0-1"), &ver("1.0-3"), true);
+        assert_eq!(ignored, "Epoch and pkgver are equal; pkgrel is ignored.");
+    }
+}